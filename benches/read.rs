@@ -0,0 +1,54 @@
+//! Reading a secret in 4 KiB pieces, the way applications read through SecretFS
+//!
+//! Compares decrypting the whole secret for every read, what `SecretFS::read`
+//! used to do, with decrypting only the chunks a read covers.
+//!
+//! Run with `cargo bench --bench read`.
+
+use criterion::{BenchmarkId, Criterion, Throughput, criterion_group, criterion_main};
+use ephemfs::encryption::{AesGcmCipher, SecretCipher, XChaCha20Cipher};
+use std::hint::black_box;
+
+/// Size of a read request
+const READ_SIZE: usize = 4096;
+
+/// Secret sizes, up to a CA bundle like Mozilla's (about 220 KiB)
+const SECRET_SIZES: &[usize] = &[4 * 1024, 64 * 1024, 256 * 1024];
+
+/// Read the whole secret in `READ_SIZE` pieces with `read`
+fn read_in_pieces(len: usize, mut read: impl FnMut(u64) -> Vec<u8>) -> usize {
+    (0..len).step_by(READ_SIZE).map(|offset| read(offset as u64).len()).sum()
+}
+
+fn bench_cipher(c: &mut Criterion, label: &str, cipher: &dyn SecretCipher) {
+    let mut group = c.benchmark_group(format!("read_4k_pieces/{}", label));
+
+    for &size in SECRET_SIZES {
+        let plaintext = vec![b'A'; size];
+        let stored = cipher.encrypt_secret("ca-bundle.pem", &plaintext).unwrap();
+        group.throughput(Throughput::Bytes(size as u64));
+
+        group.bench_with_input(BenchmarkId::new("decrypt_whole_secret", size), &stored, |b, stored| {
+            b.iter(|| read_in_pieces(size, |offset| {
+                let decrypted = cipher.decrypt_secret("ca-bundle.pem", black_box(stored)).unwrap();
+                let start = (offset as usize).min(decrypted.len());
+                decrypted[start..(start + READ_SIZE).min(decrypted.len())].to_vec()
+            }))
+        });
+
+        group.bench_with_input(BenchmarkId::new("decrypt_range", size), &stored, |b, stored| {
+            b.iter(|| read_in_pieces(size, |offset| {
+                cipher.decrypt_secret_range("ca-bundle.pem", black_box(stored), offset, READ_SIZE).unwrap()
+            }))
+        });
+    }
+    group.finish();
+}
+
+fn bench_reads(c: &mut Criterion) {
+    bench_cipher(c, "aes-gcm", &AesGcmCipher::new(&[7u8; 32]).unwrap());
+    bench_cipher(c, "xchacha20", &XChaCha20Cipher::new(&[7u8; 32]).unwrap());
+}
+
+criterion_group!(benches, bench_reads);
+criterion_main!(benches);
@@ -4,7 +4,8 @@
 // for SecretFS by implementing the SecretCipher trait.
 
 use std::env;
-use secretfs::encryption::{SecretCipher, EncryptionError};
+use ephemfs::encryption::{SecretCipher, EncryptionError};
+use ephemfs::filesystem::{SecretFsBuilder, SecretFsError};
 
 /// Example: AES-like cipher (simplified for demonstration)
 /// 
@@ -95,6 +96,12 @@ impl Base64Cipher {
     }
 }
 
+impl Default for Base64Cipher {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl SecretCipher for Base64Cipher {
     fn encrypt(&self, plaintext: &[u8]) -> Result<Vec<u8>, EncryptionError> {
         // ⚠️ NOT ENCRYPTION - just encoding!
@@ -120,6 +127,12 @@ impl SecretCipher for Base64Cipher {
 /// Example: ROT13 cipher (for demonstration)
 pub struct ROT13Cipher;
 
+impl Default for ROT13Cipher {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl ROT13Cipher {
     pub fn new() -> Self {
         Self
@@ -149,32 +162,48 @@ impl SecretCipher for ROT13Cipher {
     }
 }
 
-// Example usage in main.rs:
-//
-// mod custom_cipher;
-// use custom_cipher::{CustomAESCipher, Base64Cipher, ROT13Cipher};
-//
-// fn create_custom_cipher() -> Box<dyn SecretCipher> {
-//     let cipher_type = std::env::var("SECRETFS_CIPHER_TYPE")
-//         .unwrap_or_else(|_| "default".to_string())
-//         .to_lowercase();
-//     
-//     match cipher_type.as_str() {
-//         "aes" => {
-//             match CustomAESCipher::from_env() {
-//                 Ok(cipher) => Box::new(cipher),
-//                 Err(e) => {
-//                     eprintln!("Failed to create AES cipher: {}", e);
-//                     std::process::exit(1);
-//                 }
-//             }
-//         },
-//         "base64" => Box::new(Base64Cipher::new()),
-//         "rot13" => Box::new(ROT13Cipher::new()),
-//         "plaintext" => Box::new(PlaintextCipher::new()),
-//         "default" | _ => Box::new(DefaultCipher::from_env()),
-//     }
-// }
+/// Mount SecretFS with the example ciphers registered next to the built-in ones
+/// 
+/// Select one like any other cipher:
+/// 
+/// ```text
+/// SECRETFS_CIPHER_TYPE=rot13 cargo run --example custom_cipher -- /mnt/secrets
+/// SECRETFS_CIPHER_TYPE=custom-aes SECRETFS_AES_KEY=$(openssl rand -hex 32) \
+///     cargo run --example custom_cipher -- /mnt/secrets
+/// ```
+fn mount(mount_point: &str) -> Result<(), SecretFsError> {
+    SecretFsBuilder::new()
+        .register_cipher("custom-aes", || Ok(Box::new(CustomAESCipher::from_env()?) as Box<dyn SecretCipher>))
+        .register_cipher("base64", || Ok(Box::new(Base64Cipher::new()) as Box<dyn SecretCipher>))
+        .register_cipher("rot13", || Ok(Box::new(ROT13Cipher::new()) as Box<dyn SecretCipher>))
+        .mount(mount_point)
+}
+
+fn main() {
+    // With a mount point: mount SecretFS using the custom ciphers
+    if let Some(mount_point) = env::args().nth(1) {
+        if let Err(e) = mount(&mount_point) {
+            eprintln!("❌ {}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    let ciphers: Vec<Box<dyn SecretCipher>> = vec![
+        Box::new(CustomAESCipher::new(&[7u8; 32]).expect("32-byte key")),
+        Box::new(Base64Cipher::new()),
+        Box::new(ROT13Cipher::new()),
+    ];
+
+    let plaintext = b"Hello SecretFS";
+    for cipher in &ciphers {
+        let encrypted = cipher.encrypt(plaintext).expect("encrypt");
+        let decrypted = cipher.decrypt(&encrypted).expect("decrypt");
+        assert_eq!(decrypted, plaintext);
+        println!("✅ {} round-trips {} bytes", cipher.cipher_info(), plaintext.len());
+    }
+    println!("💡 Pass a mount point to mount SecretFS with SECRETFS_CIPHER_TYPE=custom-aes, base64 or rot13");
+}
 
 #[cfg(test)]
 mod tests {
@@ -70,7 +70,7 @@ API_KEY="env_api_key_456" \
 JWT_SECRET="env_jwt_secret_789" \
 SECRET_STRIPE_KEY="sk_live_stripe_key_abc" \
 SECRETFS_CIPHER_TYPE="default" \
-SECRETFS_ENCRYPTION_KEY="demo-encryption-key-2024" \
+SECRETFS_ENCRYPTION_KEY="$(openssl rand -base64 32)" \
 ../target/release/ephemfs "$MOUNT_POINT" &
 
 FUSE_PID=$!
@@ -36,7 +36,7 @@ fn main() {
                 
                 for (name, value) in secrets.iter() {
                     println!("🔓 Secret: {}", name);
-                    println!("   Value: {}", value);
+                    println!("   Value: {}", value.as_str());
                     println!("   Length: {} characters", value.len());
                     println!();
                 }
@@ -46,15 +46,15 @@ fn main() {
                 println!();
                 
                 if let Ok(db_password) = convenience::get_secret("database_password") {
-                    println!("✅ Database Password: {}", db_password);
+                    println!("✅ Database Password: {}", db_password.as_str());
                 }
                 
                 if let Ok(api_key) = convenience::get_secret("api_key") {
-                    println!("✅ API Key: {}", api_key);
+                    println!("✅ API Key: {}", api_key.as_str());
                 }
                 
                 if let Ok(jwt_secret) = convenience::get_secret("jwt_secret") {
-                    println!("✅ JWT Secret: {}", jwt_secret);
+                    println!("✅ JWT Secret: {}", jwt_secret.as_str());
                 }
                 
                 println!();
@@ -102,7 +102,7 @@ fn get_secret(secret_name: &str) {
                 Ok(value) => {
                     println!("✅ Secret retrieved successfully:");
                     println!("   Name: {}", secret_name);
-                    println!("   Value: {}", value);
+                    println!("   Value: {}", value.as_str());
                     println!("   Length: {} characters", value.len());
                 },
                 Err(e) => {
@@ -159,7 +159,7 @@ fn get_all_secrets() {
                     } else {
                         println!("   Retrieved {} secret(s):", secrets.len());
                         for (name, value) in secrets.iter() {
-                            println!("   • {}: {} ({} chars)", name, value, value.len());
+                            println!("   • {}: {} ({} chars)", name, value.as_str(), value.len());
                         }
                     }
                 },
@@ -185,7 +185,7 @@ fn wait_for_secret(secret_name: &str, timeout_seconds: u64) {
                 Ok(value) => {
                     println!("✅ Secret became available:");
                     println!("   Name: {}", secret_name);
-                    println!("   Value: {}", value);
+                    println!("   Value: {}", value.as_str());
                     println!("   Length: {} characters", value.len());
                 },
                 Err(e) => {
@@ -37,7 +37,8 @@ show_usage() {
     echo ""
     echo "  Encryption Configuration:"
     echo "    SECRETFS_CIPHER_TYPE - 'default', 'plaintext', or 'rsa'"
-    echo "    SECRETFS_ENCRYPTION_KEY - Encryption key (for default cipher)"
+    echo "    SECRETFS_ENCRYPTION_KEY - Base64 32-byte key for symmetric ciphers (openssl rand -base64 32)"
+    echo "    SECRETFS_ENCRYPTION_KEY_FILE - Key file (alternative to SECRETFS_ENCRYPTION_KEY)"
     echo "    SECRETFS_PUBLIC_KEY_FILE - RSA public key file (for RSA mode)"
     echo ""
     echo "  External Secret Fetching:"
@@ -0,0 +1,69 @@
+//! Reference helper for the `external` cipher type
+//!
+//! Speaks the cipher helper protocol on stdin/stdout (see
+//! `ephemfs::external_cipher::HelperMessage`) and encrypts with
+//! XChaCha20-Poly1305 under the key configured as for the `xchacha20`
+//! cipher. Real deployments replace it with a helper talking to their HSM;
+//! this one documents the protocol and is used by the tests.
+
+use std::io::{self, BufReader};
+use std::process;
+use ephemfs::encryption::{SecretCipher, XChaCha20Cipher};
+use ephemfs::external_cipher::{HELPER_PROTOCOL_VERSION, HelperMessage};
+use zeroize::Zeroizing;
+
+fn main() {
+    let cipher = match XChaCha20Cipher::from_env() {
+        Ok(cipher) => cipher,
+        Err(e) => {
+            eprintln!("❌ secretfs-cipher-helper: {}", e);
+            eprintln!("💡 Configure a key, for example: export SECRETFS_ENCRYPTION_KEY=\"$(openssl rand -base64 32)\"");
+            process::exit(1);
+        }
+    };
+
+    let mut stdin = BufReader::new(io::stdin().lock());
+    let mut stdout = io::stdout().lock();
+    loop {
+        let request = match HelperMessage::read_from(&mut stdin) {
+            Ok(Some(request)) => request,
+            Ok(None) => return,
+            Err(e) => {
+                eprintln!("❌ secretfs-cipher-helper: {}", e);
+                process::exit(1);
+            }
+        };
+
+        let response = respond(&cipher, &request);
+        if let Err(e) = response.write_to(&mut stdout) {
+            eprintln!("❌ secretfs-cipher-helper: {}", e);
+            process::exit(1);
+        }
+    }
+}
+
+fn respond(cipher: &XChaCha20Cipher, request: &HelperMessage) -> HelperMessage {
+    let operation = request.get("operation").unwrap_or_default();
+    if operation == "hello" {
+        return HelperMessage::new()
+            .with("protocol", HELPER_PROTOCOL_VERSION)
+            .with("info", format!("reference helper, {}", cipher.cipher_info()));
+    }
+
+    let name = request.get("name").unwrap_or_default();
+    let data = match request.get_data("data") {
+        Ok(Some(data)) => data,
+        Ok(None) => return HelperMessage::new().with("error", "missing data"),
+        Err(e) => return HelperMessage::new().with("error", e.to_string()),
+    };
+
+    let result = match operation {
+        "encrypt" => cipher.encrypt_secret(name, &data),
+        "decrypt" => cipher.decrypt_secret(name, &data),
+        other => return HelperMessage::new().with("error", format!("unknown operation '{}'", other)),
+    };
+    match result {
+        Ok(output) => HelperMessage::new().with_data("data", &Zeroizing::new(output)),
+        Err(e) => HelperMessage::new().with("error", e.to_string()),
+    }
+}
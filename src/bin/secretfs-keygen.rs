@@ -1,6 +1,8 @@
 use std::env;
 use std::path::Path;
-use ephemfs::asymmetric_encryption::key_utils;
+use ephemfs::asymmetric_encryption::{key_utils, read_passphrase_file};
+use ephemfs::header::{SecretHeader, is_age_file};
+use ephemfs::jwe::JweHeader;
 
 fn main() {
     let args: Vec<String> = env::args().collect();
@@ -12,20 +14,80 @@ fn main() {
     
     match args[1].as_str() {
         "generate" => {
-            if args.len() < 4 {
-                eprintln!("Usage: secretfs-keygen generate <private_key_file> <public_key_file> [key_size]");
+            let mut key_type = "rsa".to_string();
+            let mut passphrase_file: Option<String> = None;
+            let mut positional: Vec<&String> = Vec::new();
+            let mut options = args[2..].iter();
+            while let Some(arg) = options.next() {
+                if let Some(value) = arg.strip_prefix("--type=") {
+                    key_type = value.to_lowercase();
+                } else if arg == "--type" {
+                    match options.next() {
+                        Some(value) => key_type = value.to_lowercase(),
+                        None => {
+                            eprintln!("❌ Error: --type requires a value (rsa, x25519 or age)");
+                            return;
+                        }
+                    }
+                } else if let Some(value) = arg.strip_prefix("--passphrase-file=") {
+                    passphrase_file = Some(value.to_string());
+                } else if arg == "--passphrase-file" {
+                    match options.next() {
+                        Some(value) => passphrase_file = Some(value.to_string()),
+                        None => {
+                            eprintln!("❌ Error: --passphrase-file requires a file name");
+                            return;
+                        }
+                    }
+                } else {
+                    positional.push(arg);
+                }
+            }
+            
+            if positional.len() < 2 {
+                eprintln!("Usage: secretfs-keygen generate [--type rsa|x25519|age] [--passphrase-file <file>] <private_key_file> <public_key_file> [key_size]");
                 return;
             }
             
-            let private_key_file = &args[2];
-            let public_key_file = &args[3];
-            let key_size = if args.len() > 4 {
-                args[4].parse().unwrap_or(2048)
-            } else {
-                2048
+            let private_key_file = positional[0];
+            let public_key_file = positional[1];
+            
+            let passphrase = match passphrase_file.as_deref().map(read_passphrase_file).transpose() {
+                Ok(passphrase) => passphrase,
+                Err(e) => {
+                    eprintln!("❌ Error: {}", e);
+                    return;
+                }
             };
+            if passphrase.as_ref().is_some_and(|passphrase| passphrase.is_empty()) {
+                eprintln!("❌ Error: The passphrase file is empty");
+                return;
+            }
             
-            generate_keys(private_key_file, public_key_file, key_size);
+            match key_type.as_str() {
+                "rsa" => {
+                    let key_size = positional.get(2)
+                        .map(|size| size.parse().unwrap_or(2048))
+                        .unwrap_or(2048);
+                    generate_keys(private_key_file, public_key_file, key_size, passphrase.as_deref().map(Vec::as_slice));
+                },
+                "x25519" => {
+                    if positional.len() > 2 {
+                        eprintln!("⚠️  Ignoring key size: X25519 keys are always 256 bits");
+                    }
+                    generate_x25519_keys(private_key_file, public_key_file, passphrase.as_deref().map(Vec::as_slice));
+                },
+                "age" => {
+                    if passphrase.is_some() {
+                        eprintln!("❌ Error: --passphrase-file is not supported for age identities (use `age -p` to protect the identity file)");
+                        return;
+                    }
+                    generate_age_identity(private_key_file, public_key_file);
+                },
+                other => {
+                    eprintln!("❌ Error: Unknown key type '{}'. Use 'rsa', 'x25519' or 'age'", other);
+                }
+            }
         },
         "info" => {
             if args.len() < 3 {
@@ -36,6 +98,14 @@ fn main() {
             let key_file = &args[2];
             show_key_info(key_file);
         },
+        "inspect" => {
+            if args.len() < 3 {
+                eprintln!("Usage: secretfs-keygen inspect <secret_file>");
+                return;
+            }
+            
+            inspect_secret(&args[2]);
+        },
         "help" | "--help" | "-h" => {
             print_usage();
         },
@@ -51,13 +121,15 @@ fn print_usage() {
     println!("===============================");
     println!();
     println!("USAGE:");
-    println!("  secretfs-keygen generate <private_key_file> <public_key_file> [key_size]");
+    println!("  secretfs-keygen generate [--type rsa|x25519|age] [--passphrase-file <file>] <private_key_file> <public_key_file> [key_size]");
     println!("  secretfs-keygen info <key_file>");
+    println!("  secretfs-keygen inspect <secret_file>");
     println!("  secretfs-keygen help");
     println!();
     println!("COMMANDS:");
-    println!("  generate    Generate a new RSA key pair");
+    println!("  generate    Generate a new RSA (default), X25519 or age key pair");
     println!("  info        Display information about a key file");
+    println!("  inspect     Show which cipher and key encrypted a secret file");
     println!("  help        Show this help message");
     println!();
     println!("EXAMPLES:");
@@ -67,9 +139,21 @@ fn print_usage() {
     println!("  # Generate 4096-bit RSA key pair");
     println!("  secretfs-keygen generate private.pem public.pem 4096");
     println!();
+    println!("  # Generate X25519 key pair (fast, small ciphertexts)");
+    println!("  secretfs-keygen generate --type x25519 private.pem public.pem");
+    println!();
+    println!("  # Generate an RSA key pair with a passphrase-protected private key");
+    println!("  secretfs-keygen generate --passphrase-file passphrase.txt private.pem public.pem");
+    println!();
+    println!("  # Generate age identity and recipient (for `age -d -i key.txt`)");
+    println!("  secretfs-keygen generate --type age key.txt recipient.txt");
+    println!();
     println!("  # Show key information");
     println!("  secretfs-keygen info public.pem");
     println!();
+    println!("  # Show which key a mounted secret is encrypted for");
+    println!("  secretfs-keygen inspect /mnt/secrets/api_key");
+    println!();
     println!("SECURITY NOTES:");
     println!("  • Keep private keys secure and never share them");
     println!("  • Distribute only public keys for encryption");
@@ -77,7 +161,7 @@ fn print_usage() {
     println!("  • Store private keys in secure locations with proper permissions");
 }
 
-fn generate_keys(private_key_file: &str, public_key_file: &str, key_size: usize) {
+fn generate_keys(private_key_file: &str, public_key_file: &str, key_size: usize, passphrase: Option<&[u8]>) {
     // Validate key size
     if key_size < 1024 {
         eprintln!("❌ Error: Key size must be at least 1024 bits");
@@ -89,57 +173,137 @@ fn generate_keys(private_key_file: &str, public_key_file: &str, key_size: usize)
         return;
     }
     
-    // Check if files already exist
-    if Path::new(private_key_file).exists() {
-        eprintln!("❌ Error: Private key file '{}' already exists", private_key_file);
-        eprintln!("   Remove the existing file or choose a different name");
+    if !check_output_files(private_key_file, public_key_file) {
         return;
     }
     
-    if Path::new(public_key_file).exists() {
-        eprintln!("❌ Error: Public key file '{}' already exists", public_key_file);
-        eprintln!("   Remove the existing file or choose a different name");
+    println!("🔑 Generating RSA-{} key pair...", key_size);
+    println!("   Private key: {}", private_key_file);
+    println!("   Public key: {}", public_key_file);
+    println!();
+    
+    match key_utils::generate_key_pair(key_size, private_key_file, public_key_file, passphrase) {
+        Ok(()) => print_next_steps("rsa", private_key_file, public_key_file, passphrase.is_some()),
+        Err(e) => {
+            eprintln!("❌ Failed to generate key pair: {}", e);
+        }
+    }
+}
+
+fn generate_x25519_keys(private_key_file: &str, public_key_file: &str, passphrase: Option<&[u8]>) {
+    if !check_output_files(private_key_file, public_key_file) {
         return;
     }
     
-    println!("🔑 Generating RSA-{} key pair...", key_size);
+    println!("🔑 Generating X25519 key pair...");
     println!("   Private key: {}", private_key_file);
     println!("   Public key: {}", public_key_file);
     println!();
     
-    match key_utils::generate_key_pair(key_size, private_key_file, public_key_file) {
-        Ok(()) => {
-            println!();
-            println!("✅ Key pair generated successfully!");
-            println!();
-            println!("📋 NEXT STEPS:");
-            println!("   1. Secure the private key:");
-            println!("      chmod 600 {}", private_key_file);
-            println!("      # Move to secure location if needed");
-            println!();
-            println!("   2. Configure SecretFS with the public key:");
-            println!("      export SECRETFS_CIPHER_TYPE=rsa");
-            println!("      export SECRETFS_PUBLIC_KEY_FILE={}", public_key_file);
-            println!("      # OR");
-            println!("      export SECRETFS_PUBLIC_KEY_PEM=\"$(cat {})\"", public_key_file);
-            println!();
-            println!("   3. Configure applications with the private key:");
-            println!("      export SECRETFS_PRIVATE_KEY_FILE={}", private_key_file);
-            println!("      # OR");
-            println!("      export SECRETFS_PRIVATE_KEY_PEM=\"$(cat {})\"", private_key_file);
-            println!();
-            println!("🔐 SECURITY REMINDERS:");
-            println!("   • Private key can decrypt all secrets - keep it secure!");
-            println!("   • Public key is safe to distribute and store in containers");
-            println!("   • Use different key pairs for different environments");
-            println!("   • Regularly rotate keys according to your security policy");
-        },
+    match key_utils::generate_x25519_key_pair(private_key_file, public_key_file, passphrase) {
+        Ok(()) => print_next_steps("x25519", private_key_file, public_key_file, passphrase.is_some()),
         Err(e) => {
             eprintln!("❌ Failed to generate key pair: {}", e);
         }
     }
 }
 
+fn generate_age_identity(identity_file: &str, recipient_file: &str) {
+    use age::secrecy::ExposeSecret;
+    
+    if !check_output_files(identity_file, recipient_file) {
+        return;
+    }
+    
+    println!("🔑 Generating age X25519 identity...");
+    
+    let identity = age::x25519::Identity::generate();
+    let recipient = identity.to_public();
+    // Same layout as `age-keygen -o key.txt`
+    let identity_text = format!("# public key: {}\n{}\n", recipient, identity.to_string().expose_secret());
+    
+    let written = write_private_file(identity_file, identity_text.as_bytes())
+        .and_then(|_| std::fs::write(recipient_file, format!("{}\n", recipient)));
+    if let Err(e) = written {
+        eprintln!("❌ Failed to write age identity: {}", e);
+        return;
+    }
+    
+    println!("✅ age identity generated successfully!");
+    println!("   Identity: {}", identity_file);
+    println!("   Recipient: {}", recipient);
+    println!();
+    println!("📋 NEXT STEPS:");
+    println!("   1. Configure SecretFS with the recipient:");
+    println!("      export SECRETFS_CIPHER_TYPE=age");
+    println!("      export SECRETFS_AGE_RECIPIENTS_FILE={}", recipient_file);
+    println!();
+    println!("   2. Decrypt mounted secrets with the identity:");
+    println!("      age -d -i {} /mnt/secrets/<name>", identity_file);
+}
+
+/// Write a file readable only by the owner
+fn write_private_file(path: &str, contents: &[u8]) -> std::io::Result<()> {
+    use std::io::Write;
+    use std::os::unix::fs::OpenOptionsExt;
+    
+    std::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(path)?
+        .write_all(contents)
+}
+
+fn check_output_files(private_key_file: &str, public_key_file: &str) -> bool {
+    // Check if files already exist
+    if Path::new(private_key_file).exists() {
+        eprintln!("❌ Error: Private key file '{}' already exists", private_key_file);
+        eprintln!("   Remove the existing file or choose a different name");
+        return false;
+    }
+    
+    if Path::new(public_key_file).exists() {
+        eprintln!("❌ Error: Public key file '{}' already exists", public_key_file);
+        eprintln!("   Remove the existing file or choose a different name");
+        return false;
+    }
+    
+    true
+}
+
+fn print_next_steps(cipher_type: &str, private_key_file: &str, public_key_file: &str, passphrase_protected: bool) {
+    println!();
+    println!("✅ Key pair generated successfully!");
+    println!();
+    println!("📋 NEXT STEPS:");
+    println!("   1. Secure the private key:");
+    println!("      chmod 600 {}", private_key_file);
+    println!("      # Move to secure location if needed");
+    println!();
+    println!("   2. Configure SecretFS with the public key:");
+    println!("      export SECRETFS_CIPHER_TYPE={}", cipher_type);
+    println!("      export SECRETFS_PUBLIC_KEY_FILE={}", public_key_file);
+    println!("      # OR");
+    println!("      export SECRETFS_PUBLIC_KEY_PEM=\"$(cat {})\"", public_key_file);
+    println!();
+    println!("   3. Configure applications with the private key:");
+    println!("      export SECRETFS_PRIVATE_KEY_FILE={}", private_key_file);
+    println!("      # OR");
+    println!("      export SECRETFS_PRIVATE_KEY_PEM=\"$(cat {})\"", private_key_file);
+    if passphrase_protected {
+        println!("      # The private key is passphrase-protected; also set one of:");
+        println!("      export SECRETFS_PRIVATE_KEY_PASSPHRASE_FILE=/path/to/passphrase.txt");
+        println!("      export SECRETFS_PRIVATE_KEY_PASSPHRASE_FD=3   # with 3</path/to/passphrase.txt");
+    }
+    println!();
+    println!("🔐 SECURITY REMINDERS:");
+    println!("   • Private key can decrypt all secrets - keep it secure!");
+    println!("   • Public key is safe to distribute and store in containers");
+    println!("   • Use different key pairs for different environments");
+    println!("   • Regularly rotate keys according to your security policy");
+}
+
 fn show_key_info(key_file: &str) {
     if !Path::new(key_file).exists() {
         eprintln!("❌ Error: Key file '{}' does not exist", key_file);
@@ -169,3 +333,55 @@ fn show_key_info(key_file: &str) {
         }
     }
 }
+
+fn inspect_secret(secret_file: &str) {
+    let content = match std::fs::read(secret_file) {
+        Ok(content) => content,
+        Err(e) => {
+            eprintln!("❌ Failed to read secret file '{}': {}", secret_file, e);
+            return;
+        }
+    };
+    
+    if is_age_file(&content) {
+        let stanzas = content.split(|&b| b == b'\n')
+            .take_while(|line| !line.starts_with(b"---"))
+            .filter(|line| line.starts_with(b"-> "))
+            .count();
+        println!("🔍 Secret File Information:");
+        println!("   File: {}", secret_file);
+        println!("   Format: age v1 ({} recipient stanza(s))", stanzas);
+        println!("   Decrypt with: age -d -i key.txt {}", secret_file);
+        return;
+    }
+    
+    if let Some(header) = JweHeader::peek(&content) {
+        println!("🔍 Secret File Information:");
+        println!("   File: {}", secret_file);
+        println!("   Format: JWE compact ({} + {})", header.alg, header.enc);
+        println!("   Key id: {}", header.kid.as_deref().unwrap_or("(none)"));
+        println!("   Decrypt with any JOSE library and the matching private key");
+        return;
+    }
+    
+    match SecretHeader::parse(&content) {
+        Ok((header, header_len)) => {
+            println!("🔍 Secret File Information:");
+            println!("   File: {}", secret_file);
+            println!("   Cipher: {}", header.cipher_id);
+            if header.key_id.is_empty() {
+                println!("   Key id: (none)");
+            } else {
+                println!("   Key id: {}", header.key_id_hex());
+            }
+            println!("   Payload: {} bytes", content.len() - header_len);
+        },
+        Err(_) if !SecretHeader::is_present(&content) => {
+            println!("🔍 '{}' has no SecretFS header", secret_file);
+            println!("   It is either served decrypted, legacy RSA output or from a custom cipher");
+        },
+        Err(e) => {
+            eprintln!("❌ Invalid SecretFS header in '{}': {}", secret_file, e);
+        }
+    }
+}
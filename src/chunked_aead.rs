@@ -0,0 +1,277 @@
+//! Seekable chunked AEAD format of the symmetric ciphers
+//!
+//! A secret is split into chunks of `CHUNK_SIZE` plaintext bytes that are
+//! sealed separately, so a read at an offset only decrypts the chunks that
+//! cover it. Chunk nonces follow the STREAM construction (Hoang et al.,
+//! "Online Authenticated-Encryption and its Nonce-Reuse
+//! Misuse-Resistance"): a random per-secret prefix, the big-endian chunk
+//! index and a flag marking the last chunk. Chunks can therefore not be
+//! reordered, dropped from the end or spliced in from another secret.
+//!
+//! Body layout after the `SecretHeader`:
+//! `version || chunk size || nonce prefix || chunk 0 || ... || chunk n-1`,
+//! where every chunk is `ciphertext || tag` and all but the last one hold
+//! exactly `chunk size` bytes of plaintext. An empty secret is one empty
+//! last chunk. The header, version, chunk size and nonce prefix are bound
+//! as associated data of every chunk, together with the secret name.
+
+use std::ops::Range;
+use aes_gcm::aead::{Aead, AeadCore, Nonce, Payload};
+use aes_gcm::aead::generic_array::typenum::Unsigned;
+use rand::rngs::OsRng;
+use rand::RngCore;
+use zeroize::Zeroizing;
+use crate::encryption::{EncryptionError, aead_associated_data};
+use crate::key_derivation::{SECRET_VERSION_LEN, split_secret_version};
+
+/// Plaintext bytes per chunk: one page, the size of most reads
+pub const CHUNK_SIZE: usize = 4096;
+
+/// Largest chunk size accepted from a stored secret
+const MAX_CHUNK_SIZE: usize = 1 << 20;
+
+/// Length of the chunk size field
+pub(crate) const CHUNK_SIZE_LEN: usize = 4;
+
+/// Length of the chunk index and last-chunk flag at the end of every nonce
+pub(crate) const NONCE_SUFFIX_LEN: usize = 5;
+
+/// Largest number of chunks the 32-bit chunk index can count
+const MAX_CHUNKS: u64 = 1 << 32;
+
+/// Clamp a read of `size` bytes at `offset` to a plaintext of `len` bytes
+pub(crate) fn read_range(offset: u64, size: usize, len: usize) -> Range<usize> {
+    let start = usize::try_from(offset).unwrap_or(usize::MAX).min(len);
+    start..start.saturating_add(size).min(len)
+}
+
+/// Length of the random nonce prefix for the AEAD `A`
+fn nonce_prefix_len<A: AeadCore>() -> usize {
+    A::NonceSize::USIZE - NONCE_SUFFIX_LEN
+}
+
+/// Nonce of chunk `index`: `prefix || index || last`
+fn chunk_nonce<A: AeadCore>(prefix: &[u8], index: usize, last: bool) -> Nonce<A> {
+    let index = u32::try_from(index).expect("chunk count is checked against MAX_CHUNKS");
+    let mut nonce = Nonce::<A>::default();
+    nonce[..prefix.len()].copy_from_slice(prefix);
+    nonce[prefix.len()..prefix.len() + 4].copy_from_slice(&index.to_be_bytes());
+    nonce[prefix.len() + 4] = u8::from(last);
+    nonce
+}
+
+/// Seal `plaintext` in chunks and append the body to `output`
+///
+/// `output` holds the encoded header and `cipher` is keyed with the subkey
+/// of `version`; `algorithm` names the AEAD in error messages.
+pub(crate) fn seal<A: Aead>(
+    cipher: &A,
+    mut output: Vec<u8>,
+    version: u64,
+    name: &str,
+    plaintext: &[u8],
+    algorithm: &str,
+) -> Result<Vec<u8>, EncryptionError> {
+    if (plaintext.len() / CHUNK_SIZE) as u64 >= MAX_CHUNKS {
+        return Err(EncryptionError::EncryptionFailed(format!("Secret is too large for {}", algorithm)));
+    }
+
+    let mut nonce_prefix = vec![0u8; nonce_prefix_len::<A>()];
+    OsRng.fill_bytes(&mut nonce_prefix);
+    output.extend_from_slice(&version.to_be_bytes());
+    output.extend_from_slice(&(CHUNK_SIZE as u32).to_be_bytes());
+    output.extend_from_slice(&nonce_prefix);
+    let aad = aead_associated_data(&output, name);
+
+    let chunk_count = plaintext.len().div_ceil(CHUNK_SIZE).max(1);
+    output.reserve(plaintext.len() + chunk_count * A::TagSize::USIZE);
+    for index in 0..chunk_count {
+        let chunk = &plaintext[read_range((index * CHUNK_SIZE) as u64, CHUNK_SIZE, plaintext.len())];
+        let nonce = chunk_nonce::<A>(&nonce_prefix, index, index == chunk_count - 1);
+        let sealed = cipher.encrypt(&nonce, Payload { msg: chunk, aad: &aad })
+            .map_err(|_| EncryptionError::EncryptionFailed(format!("{} encryption failed", algorithm)))?;
+        output.extend_from_slice(&sealed);
+    }
+    Ok(output)
+}
+
+/// Body of a secret in the chunked format
+pub(crate) struct ChunkedBody<'a> {
+    version: u64,
+    chunk_size: usize,
+    tag_len: usize,
+    nonce_prefix: &'a [u8],
+    chunks: &'a [u8],
+    aad: Vec<u8>,
+    algorithm: &'static str,
+}
+
+impl<'a> ChunkedBody<'a> {
+    /// Parse the body following the encoded `header` of a secret sealed with the AEAD `A`
+    pub(crate) fn parse<A: AeadCore>(
+        header: &[u8],
+        body: &'a [u8],
+        name: &str,
+        algorithm: &'static str,
+    ) -> Result<Self, EncryptionError> {
+        let preamble_len = SECRET_VERSION_LEN + CHUNK_SIZE_LEN + nonce_prefix_len::<A>();
+        let tag_len = A::TagSize::USIZE;
+        if body.len() < preamble_len + tag_len {
+            return Err(EncryptionError::InvalidData(format!("{} ciphertext is too short", algorithm)));
+        }
+
+        let (preamble, chunks) = body.split_at(preamble_len);
+        let (version, rest) = split_secret_version(preamble)?;
+        let (chunk_size, nonce_prefix) = rest.split_at(CHUNK_SIZE_LEN);
+        let chunk_size = u32::from_be_bytes(chunk_size.try_into().expect("4-byte chunk size")) as usize;
+        if chunk_size == 0 || chunk_size > MAX_CHUNK_SIZE {
+            return Err(EncryptionError::InvalidData(format!("Invalid {} chunk size {}", algorithm, chunk_size)));
+        }
+
+        // Only the last chunk may be short, and it still holds its tag
+        let partial = chunks.len() % (chunk_size + tag_len);
+        if (partial != 0 && partial < tag_len) || chunks.len().div_ceil(chunk_size + tag_len) as u64 > MAX_CHUNKS {
+            return Err(EncryptionError::InvalidData(format!("{} ciphertext has a malformed chunk", algorithm)));
+        }
+
+        let mut authenticated = header.to_vec();
+        authenticated.extend_from_slice(preamble);
+        Ok(Self {
+            version,
+            chunk_size,
+            tag_len,
+            nonce_prefix,
+            chunks,
+            aad: aead_associated_data(&authenticated, name),
+            algorithm,
+        })
+    }
+
+    /// Version of the secret, which selects its subkey
+    pub(crate) fn version(&self) -> u64 {
+        self.version
+    }
+
+    fn chunk_count(&self) -> usize {
+        self.chunks.len().div_ceil(self.chunk_size + self.tag_len)
+    }
+
+    /// Length of the plaintext
+    pub(crate) fn plaintext_len(&self) -> usize {
+        self.chunks.len() - self.chunk_count() * self.tag_len
+    }
+
+    /// Decrypt `size` bytes of plaintext at `offset`, cut short at the end
+    ///
+    /// Only the chunks covering the range are decrypted and authenticated.
+    /// A read past the end still authenticates the last chunk, so a wrong
+    /// key or a truncated secret is never mistaken for end of file.
+    pub(crate) fn decrypt_range<A: Aead>(&self, cipher: &A, offset: u64, size: usize) -> Result<Vec<u8>, EncryptionError> {
+        let range = read_range(offset, size, self.plaintext_len());
+        let last_chunk = self.chunk_count() - 1;
+        let first = (range.start / self.chunk_size).min(last_chunk);
+        let last = if range.is_empty() { first } else { (range.end - 1) / self.chunk_size };
+
+        let stride = self.chunk_size + self.tag_len;
+        let mut plaintext = Zeroizing::new(Vec::with_capacity((last - first + 1) * self.chunk_size));
+        for index in first..=last {
+            let sealed = &self.chunks[index * stride..self.chunks.len().min((index + 1) * stride)];
+            let nonce = chunk_nonce::<A>(self.nonce_prefix, index, index == last_chunk);
+            let chunk = Zeroizing::new(cipher.decrypt(&nonce, Payload { msg: sealed, aad: &self.aad })
+                .map_err(|_| EncryptionError::DecryptionFailed(format!(
+                    "{} authentication failed (wrong key or tampered data)", self.algorithm
+                )))?);
+            plaintext.extend_from_slice(&chunk);
+        }
+
+        let skip = first * self.chunk_size;
+        Ok(plaintext[range.start - skip..range.end - skip].to_vec())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aes_gcm::{Aes256Gcm, Key};
+    use aes_gcm::aead::KeyInit;
+    use chacha20poly1305::XChaCha20Poly1305;
+
+    const HEADER: &[u8] = b"test-header";
+
+    fn aes() -> Aes256Gcm {
+        Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&[3u8; 32]))
+    }
+
+    fn sealed(plaintext: &[u8]) -> Vec<u8> {
+        seal(&aes(), HEADER.to_vec(), 42, "ca.pem", plaintext, "AES-256-GCM").unwrap()
+    }
+
+    fn read(stored: &[u8], offset: u64, size: usize) -> Result<Vec<u8>, EncryptionError> {
+        let (header, body) = stored.split_at(HEADER.len());
+        ChunkedBody::parse::<Aes256Gcm>(header, body, "ca.pem", "AES-256-GCM")?.decrypt_range(&aes(), offset, size)
+    }
+
+    #[test]
+    fn test_chunked_range_reads() {
+        let plaintext: Vec<u8> = (0..3 * CHUNK_SIZE + 100).map(|i| (i % 251) as u8).collect();
+        let stored = sealed(&plaintext);
+        let (header, body) = stored.split_at(HEADER.len());
+        let parsed = ChunkedBody::parse::<Aes256Gcm>(header, body, "ca.pem", "AES-256-GCM").unwrap();
+        assert_eq!(parsed.version(), 42);
+        assert_eq!(parsed.chunk_count(), 4);
+        assert_eq!(parsed.plaintext_len(), plaintext.len());
+
+        for (offset, size) in [(0, usize::MAX), (0, 1), (CHUNK_SIZE - 1, 2), (CHUNK_SIZE, CHUNK_SIZE), (100, 2 * CHUNK_SIZE),
+                               (3 * CHUNK_SIZE + 99, 4096), (plaintext.len(), 10), (usize::MAX, 10)] {
+            let range = read_range(offset as u64, size, plaintext.len());
+            assert_eq!(read(&stored, offset as u64, size).unwrap(), &plaintext[range], "{} + {}", offset, size);
+        }
+
+        for len in [0, 1, CHUNK_SIZE - 1, CHUNK_SIZE, CHUNK_SIZE + 1, 2 * CHUNK_SIZE] {
+            assert_eq!(read(&sealed(&plaintext[..len]), 0, usize::MAX).unwrap(), &plaintext[..len]);
+        }
+
+        // XChaCha20-Poly1305 nonces have a longer random prefix
+        let cipher = XChaCha20Poly1305::new(chacha20poly1305::Key::from_slice(&[3u8; 32]));
+        let stored = seal(&cipher, HEADER.to_vec(), 1, "ca.pem", &plaintext, "XChaCha20-Poly1305").unwrap();
+        let parsed = ChunkedBody::parse::<XChaCha20Poly1305>(HEADER, &stored[HEADER.len()..], "ca.pem", "XChaCha20-Poly1305").unwrap();
+        assert_eq!(parsed.decrypt_range(&cipher, CHUNK_SIZE as u64 - 5, 10).unwrap(), &plaintext[CHUNK_SIZE - 5..CHUNK_SIZE + 5]);
+    }
+
+    #[test]
+    fn test_chunked_tampering() {
+        let plaintext = vec![0x5a; 2 * CHUNK_SIZE + 10];
+        let stored = sealed(&plaintext);
+        let preamble = HEADER.len() + SECRET_VERSION_LEN + CHUNK_SIZE_LEN + nonce_prefix_len::<Aes256Gcm>();
+        let stride = CHUNK_SIZE + 16;
+
+        // Flipping a bit only breaks the chunk it is in
+        let mut flipped = stored.clone();
+        flipped[preamble + stride + 7] ^= 1;
+        assert!(read(&flipped, 0, CHUNK_SIZE).is_ok());
+        assert!(matches!(read(&flipped, CHUNK_SIZE as u64, 1), Err(EncryptionError::DecryptionFailed(_))));
+        assert!(read(&flipped, 0, usize::MAX).is_err());
+
+        // Dropping the last chunk: the new last chunk lacks the last-chunk flag
+        let truncated = &stored[..preamble + 2 * stride];
+        assert!(read(truncated, 0, usize::MAX).is_err());
+        assert!(read(truncated, 10 * CHUNK_SIZE as u64, 1).is_err());
+
+        // Swapping chunks changes their indices
+        let mut swapped = stored.clone();
+        swapped[preamble..preamble + 2 * stride].rotate_left(stride);
+        assert!(read(&swapped, 0, 1).is_err());
+
+        // The preamble and the name are authenticated
+        let mut resized = stored.clone();
+        resized[HEADER.len() + SECRET_VERSION_LEN + 3] ^= 1;
+        assert!(read(&resized, 0, 1).is_err());
+        let (header, body) = stored.split_at(HEADER.len());
+        let renamed = ChunkedBody::parse::<Aes256Gcm>(header, body, "other.pem", "AES-256-GCM").unwrap();
+        assert!(renamed.decrypt_range(&aes(), 0, 1).is_err());
+
+        // A chunk too short for its tag is malformed
+        assert!(matches!(read(&stored[..stored.len() - 20], 0, 1), Err(EncryptionError::InvalidData(_))));
+        assert!(matches!(read(&stored[..preamble], 0, 1), Err(EncryptionError::InvalidData(_))));
+    }
+}
@@ -1,15 +1,32 @@
+use std::collections::HashMap;
 use std::error::Error;
 use std::fmt;
-use std::env;
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use aes_gcm::aead::{Aead, KeyInit, Payload};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+use rand::rngs::OsRng;
+use rand::RngCore;
+use zeroize::{Zeroize, Zeroizing};
+use crate::chunked_aead::{self, ChunkedBody, read_range};
 use crate::asymmetric_encryption::{AsymmetricEncryption, AsymmetricError};
+use crate::recipient_policy::RecipientPolicy;
+use crate::x25519_encryption::X25519Encryption;
+use crate::jwe::{JweEncryption, JweRecipient};
+use crate::external_cipher::ExternalCipher;
+use crate::vault_transit::TransitCipher;
+use crate::header::{CipherId, SecretHeader, symmetric_key_id};
+use crate::self_test::{Primitive, SELF_TEST_NAME, encrypt_only_self_test, known_answer_tests, round_trip_self_test};
+use crate::key_derivation::{SECRET_VERSION_LEN, SecretKeys, split_secret_version, symmetric_key_configured, symmetric_key_from_env};
 
 /// Custom error type for encryption operations
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum EncryptionError {
     EncryptionFailed(String),
     DecryptionFailed(String),
     InvalidKey(String),
     InvalidData(String),
+    ConfigurationError(String),
+    SelfTestFailed(String),
 }
 
 impl fmt::Display for EncryptionError {
@@ -19,6 +36,8 @@ impl fmt::Display for EncryptionError {
             EncryptionError::DecryptionFailed(msg) => write!(f, "Decryption failed: {}", msg),
             EncryptionError::InvalidKey(msg) => write!(f, "Invalid key: {}", msg),
             EncryptionError::InvalidData(msg) => write!(f, "Invalid data: {}", msg),
+            EncryptionError::ConfigurationError(msg) => write!(f, "Configuration error: {}", msg),
+            EncryptionError::SelfTestFailed(msg) => write!(f, "Cipher self-test failed: {}", msg),
         }
     }
 }
@@ -30,10 +49,14 @@ impl Error for EncryptionError {}
 /// This trait allows users to implement custom encryption methods
 /// or use the default implementation provided by SecretFS.
 /// 
+/// The built-in ciphers prefix their output with a `SecretHeader` naming the
+/// cipher and key id. Custom ciphers may omit it; SecretFS then always
+/// decrypts their output before serving it.
+/// 
 /// # Example
 /// 
 /// ```rust
-/// use secretfs::encryption::{SecretCipher, EncryptionError};
+/// use ephemfs::encryption::{SecretCipher, EncryptionError};
 /// 
 /// struct MyCustomCipher {
 ///     key: String,
@@ -72,75 +95,148 @@ pub trait SecretCipher: Send + Sync {
     /// * `Err(EncryptionError)` - If decryption fails
     fn decrypt(&self, ciphertext: &[u8]) -> Result<Vec<u8>, EncryptionError>;
     
+    /// Encrypt the content of a named secret
+    /// 
+    /// SecretFS calls this instead of `encrypt` so that ciphers can bind the
+    /// ciphertext to the secret it belongs to. The default implementation
+    /// ignores the name and delegates to `encrypt`.
+    fn encrypt_secret(&self, _name: &str, plaintext: &[u8]) -> Result<Vec<u8>, EncryptionError> {
+        self.encrypt(plaintext)
+    }
+    
+    /// Decrypt the content of a named secret
+    /// 
+    /// Counterpart of `encrypt_secret`; the default implementation ignores
+    /// the name and delegates to `decrypt`.
+    fn decrypt_secret(&self, _name: &str, ciphertext: &[u8]) -> Result<Vec<u8>, EncryptionError> {
+        self.decrypt(ciphertext)
+    }
+    
+    /// Decrypt `size` bytes at `offset` of the content of a named secret
+    /// 
+    /// SecretFS calls this for every `read`, with the range being read.
+    /// Ciphers with a seekable format decrypt only that part; the default
+    /// implementation decrypts the whole secret with `decrypt_secret` and
+    /// returns the range, cut short at the end of the secret.
+    fn decrypt_secret_range(&self, name: &str, ciphertext: &[u8], offset: u64, size: usize) -> Result<Vec<u8>, EncryptionError> {
+        let plaintext = Zeroizing::new(self.decrypt_secret(name, ciphertext)?);
+        Ok(plaintext[read_range(offset, size, plaintext.len())].to_vec())
+    }
+    
     /// Get cipher information for logging/debugging
     fn cipher_info(&self) -> String {
         "Custom SecretCipher".to_string()
     }
+    
+    /// Check that the cipher works before any secret is stored
+    /// 
+    /// SecretFS refuses to start when this fails. The default implementation
+    /// encrypts a test secret and checks that it decrypts again; the
+    /// built-in ciphers also run the known-answer tests of their primitives.
+    fn self_test(&self) -> Result<(), EncryptionError> {
+        round_trip_self_test(self)
+    }
 }
 
 /// Default implementation using XOR cipher (for demo purposes)
 /// 
+/// Each secret is XORed with its own HKDF subkey (see `SecretKeys`), so
+/// equal values in different files do not produce equal ciphertexts. The
+/// stored layout is `header || version || ciphertext`.
+/// 
 /// ⚠️ WARNING: This is NOT cryptographically secure!
 /// This is only for demonstration. In production, use proper encryption
 /// like AES-256-GCM or implement your own secure cipher.
 pub struct DefaultCipher {
-    key: Vec<u8>,
+    keys: SecretKeys,
+    key_id: Vec<u8>,
 }
 
 impl DefaultCipher {
     /// Create a new DefaultCipher with a key
     /// 
     /// # Arguments
-    /// * `key` - Encryption key (will be repeated if shorter than data)
+    /// * `key` - Master key; each secret is XORed with its 32-byte HKDF subkey, repeated to the length of the data
     pub fn new(key: &str) -> Self {
+        Self::from_key_bytes(key.as_bytes())
+    }
+    
+    /// Create a new DefaultCipher with raw key bytes
+    pub fn from_key_bytes(key: &[u8]) -> Self {
         Self {
-            key: key.as_bytes().to_vec(),
+            keys: SecretKeys::new(key),
+            key_id: symmetric_key_id(key),
         }
     }
     
-    /// Create DefaultCipher from environment variable or use default
-    pub fn from_env() -> Self {
-        let key = std::env::var("SECRETFS_ENCRYPTION_KEY")
-            .unwrap_or_else(|_| "default-secretfs-key-2024".to_string());
-
-        Self::new(&key)
+    fn xor(subkey: &[u8], data: &[u8], output: &mut Vec<u8>) {
+        for (i, &byte) in data.iter().enumerate() {
+            output.push(byte ^ subkey[i % subkey.len()]);
+        }
     }
-}
-
-impl SecretCipher for DefaultCipher {
-    fn encrypt(&self, plaintext: &[u8]) -> Result<Vec<u8>, EncryptionError> {
-        if self.key.is_empty() {
+    
+    fn seal(&self, plaintext: &[u8], name: &str) -> Result<Vec<u8>, EncryptionError> {
+        if self.keys.is_empty() {
             return Err(EncryptionError::InvalidKey("Key cannot be empty".to_string()));
         }
         
-        let mut encrypted = Vec::with_capacity(plaintext.len());
-        
-        for (i, &byte) in plaintext.iter().enumerate() {
-            let key_byte = self.key[i % self.key.len()];
-            encrypted.push(byte ^ key_byte);
-        }
+        let version = self.keys.next_version();
+        let subkey = self.keys.derive(CipherId::Xor, name, version);
+        let mut encrypted = SecretHeader::new(CipherId::Xor, &self.key_id).encode();
+        encrypted.extend_from_slice(&version.to_be_bytes());
+        Self::xor(subkey.as_slice(), plaintext, &mut encrypted);
         
         Ok(encrypted)
     }
     
-    fn decrypt(&self, ciphertext: &[u8]) -> Result<Vec<u8>, EncryptionError> {
-        if self.key.is_empty() {
+    fn open(&self, ciphertext: &[u8], name: &str) -> Result<Vec<u8>, EncryptionError> {
+        if self.keys.is_empty() {
             return Err(EncryptionError::InvalidKey("Key cannot be empty".to_string()));
         }
         
-        // XOR is symmetric, so decryption is the same as encryption
-        let mut decrypted = Vec::with_capacity(ciphertext.len());
+        let (_, body) = SecretHeader::split(ciphertext, CipherId::Xor, &self.key_id)?;
+        let (version, body) = split_secret_version(body)?;
         
-        for (i, &byte) in ciphertext.iter().enumerate() {
-            let key_byte = self.key[i % self.key.len()];
-            decrypted.push(byte ^ key_byte);
-        }
+        // XOR is symmetric, so decryption is the same as encryption
+        let subkey = self.keys.derive(CipherId::Xor, name, version);
+        let mut decrypted = Vec::with_capacity(body.len());
+        Self::xor(subkey.as_slice(), body, &mut decrypted);
         
         Ok(decrypted)
     }
     
+    /// Create DefaultCipher from the key configured in the environment
+    /// 
+    /// See `symmetric_key_from_env`; fails when no key is configured.
+    pub fn from_env() -> Result<Self, EncryptionError> {
+        Ok(Self::from_key_bytes(&symmetric_key_from_env()?))
+    }
+}
+
+impl SecretCipher for DefaultCipher {
+    fn encrypt(&self, plaintext: &[u8]) -> Result<Vec<u8>, EncryptionError> {
+        self.seal(plaintext, "")
+    }
+    
+    fn decrypt(&self, ciphertext: &[u8]) -> Result<Vec<u8>, EncryptionError> {
+        self.open(ciphertext, "")
+    }
+    
+    fn encrypt_secret(&self, name: &str, plaintext: &[u8]) -> Result<Vec<u8>, EncryptionError> {
+        self.seal(plaintext, name)
+    }
+    
+    fn decrypt_secret(&self, name: &str, ciphertext: &[u8]) -> Result<Vec<u8>, EncryptionError> {
+        self.open(ciphertext, name)
+    }
+    
     fn cipher_info(&self) -> String {
-        format!("DefaultCipher (XOR with {}-byte key) - ⚠️ DEMO ONLY, NOT SECURE!", self.key.len())
+        "DefaultCipher (XOR with per-secret subkeys) - ⚠️ DEMO ONLY, NOT SECURE!".to_string()
+    }
+    
+    fn self_test(&self) -> Result<(), EncryptionError> {
+        known_answer_tests(&[Primitive::HkdfSha256])?;
+        round_trip_self_test(self)
     }
 }
 
@@ -155,13 +251,22 @@ impl PlaintextCipher {
     }
 }
 
+impl Default for PlaintextCipher {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl SecretCipher for PlaintextCipher {
     fn encrypt(&self, plaintext: &[u8]) -> Result<Vec<u8>, EncryptionError> {
-        Ok(plaintext.to_vec())
+        let mut output = SecretHeader::new(CipherId::Plaintext, &[]).encode();
+        output.extend_from_slice(plaintext);
+        Ok(output)
     }
     
     fn decrypt(&self, ciphertext: &[u8]) -> Result<Vec<u8>, EncryptionError> {
-        Ok(ciphertext.to_vec())
+        let (_, body) = SecretHeader::split(ciphertext, CipherId::Plaintext, &[])?;
+        Ok(body.to_vec())
     }
     
     fn cipher_info(&self) -> String {
@@ -169,25 +274,376 @@ impl SecretCipher for PlaintextCipher {
     }
 }
 
+/// Associated data for the AEAD ciphers: the encoded header followed by the secret name
+pub(crate) fn aead_associated_data(header: &[u8], name: &str) -> Vec<u8> {
+    let mut aad = Vec::with_capacity(header.len() + name.len());
+    aad.extend_from_slice(header);
+    aad.extend_from_slice(name.as_bytes());
+    aad
+}
+
+/// Length of the random nonce prepended to every AES-256-GCM ciphertext
+pub(crate) const AES_GCM_NONCE_LEN: usize = 12;
+
+/// Length of the authentication tag appended by AES-256-GCM
+pub(crate) const AES_GCM_TAG_LEN: usize = 16;
+
+/// AES-256-GCM authenticated cipher
+/// 
+/// Every secret is encrypted under its own HKDF subkey (see `SecretKeys`)
+/// in the seekable chunked format of `chunked_aead`: 4 KiB chunks with
+/// their own tags, so a read decrypts only the chunks it covers. The stored
+/// layout is `header || version || chunk size || nonce prefix || chunks`.
+/// The header and, when used through `encrypt_secret`/`decrypt_secret`, the
+/// secret name are bound as associated data and through the subkey, so a
+/// ciphertext moved to another file fails to decrypt. Secrets sealed in one
+/// piece (`header || version || nonce || ciphertext || tag`) by earlier
+/// versions still decrypt.
+pub struct AesGcmCipher {
+    keys: SecretKeys,
+    key_id: Vec<u8>,
+}
+
+impl AesGcmCipher {
+    /// Create a new AesGcmCipher from a raw 256-bit key
+    pub fn new(key: &[u8]) -> Result<Self, EncryptionError> {
+        if key.len() != 32 {
+            return Err(EncryptionError::InvalidKey(
+                format!("AES-256-GCM requires a 32-byte key, got {} bytes", key.len())
+            ));
+        }
+
+        Ok(Self {
+            keys: SecretKeys::new(key),
+            key_id: symmetric_key_id(key),
+        })
+    }
+
+    /// Create AesGcmCipher from the key configured in the environment
+    /// 
+    /// The key comes from `SECRETFS_ENCRYPTION_KEY_FILE`,
+    /// `SECRETFS_ENCRYPTION_KEY` (generate with `openssl rand -base64 32`) or
+    /// a passphrase, see `symmetric_key_from_env`.
+    pub fn from_env() -> Result<Self, EncryptionError> {
+        Self::new(&symmetric_key_from_env()?)
+    }
+
+    fn cipher(&self, cipher_id: CipherId, name: &str, version: u64) -> Aes256Gcm {
+        let subkey = self.keys.derive(cipher_id, name, version);
+        Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(subkey.as_slice()))
+    }
+
+    fn seal(&self, plaintext: &[u8], name: &str) -> Result<Vec<u8>, EncryptionError> {
+        let header = SecretHeader::new(CipherId::AesGcmChunked, &self.key_id).encode();
+        let version = self.keys.next_version();
+        chunked_aead::seal(&self.cipher(CipherId::AesGcmChunked, name, version), header, version, name, plaintext, "AES-256-GCM")
+    }
+
+    fn open(&self, ciphertext: &[u8], name: &str) -> Result<Vec<u8>, EncryptionError> {
+        if is_sealed_in_one_piece(ciphertext, CipherId::AesGcm) {
+            return self.open_in_one_piece(ciphertext, name);
+        }
+        self.open_range(ciphertext, name, 0, usize::MAX)
+    }
+
+    fn open_range(&self, ciphertext: &[u8], name: &str, offset: u64, size: usize) -> Result<Vec<u8>, EncryptionError> {
+        if is_sealed_in_one_piece(ciphertext, CipherId::AesGcm) {
+            let plaintext = Zeroizing::new(self.open_in_one_piece(ciphertext, name)?);
+            return Ok(plaintext[read_range(offset, size, plaintext.len())].to_vec());
+        }
+
+        let (header, body) = SecretHeader::split(ciphertext, CipherId::AesGcmChunked, &self.key_id)?;
+        let chunked = ChunkedBody::parse::<Aes256Gcm>(header, body, name, "AES-256-GCM")?;
+        chunked.decrypt_range(&self.cipher(CipherId::AesGcmChunked, name, chunked.version()), offset, size)
+    }
+
+    fn open_in_one_piece(&self, ciphertext: &[u8], name: &str) -> Result<Vec<u8>, EncryptionError> {
+        let (header, body) = SecretHeader::split(ciphertext, CipherId::AesGcm, &self.key_id)?;
+        if body.len() < SECRET_VERSION_LEN + AES_GCM_NONCE_LEN + AES_GCM_TAG_LEN {
+            return Err(EncryptionError::InvalidData("AES-256-GCM ciphertext is too short".to_string()));
+        }
+
+        let aad = aead_associated_data(header, name);
+        let (version, body) = split_secret_version(body)?;
+        let (nonce, sealed) = body.split_at(AES_GCM_NONCE_LEN);
+        self.cipher(CipherId::AesGcm, name, version).decrypt(Nonce::from_slice(nonce), Payload { msg: sealed, aad: &aad })
+            .map_err(|_| EncryptionError::DecryptionFailed(
+                "AES-256-GCM authentication failed (wrong key or tampered data)".to_string()
+            ))
+    }
+}
+
+impl SecretCipher for AesGcmCipher {
+    fn encrypt(&self, plaintext: &[u8]) -> Result<Vec<u8>, EncryptionError> {
+        self.seal(plaintext, "")
+    }
+
+    fn decrypt(&self, ciphertext: &[u8]) -> Result<Vec<u8>, EncryptionError> {
+        self.open(ciphertext, "")
+    }
+
+    fn encrypt_secret(&self, name: &str, plaintext: &[u8]) -> Result<Vec<u8>, EncryptionError> {
+        self.seal(plaintext, name)
+    }
+
+    fn decrypt_secret(&self, name: &str, ciphertext: &[u8]) -> Result<Vec<u8>, EncryptionError> {
+        self.open(ciphertext, name)
+    }
+
+    fn decrypt_secret_range(&self, name: &str, ciphertext: &[u8], offset: u64, size: usize) -> Result<Vec<u8>, EncryptionError> {
+        self.open_range(ciphertext, name, offset, size)
+    }
+
+    fn cipher_info(&self) -> String {
+        "AesGcmCipher (AES-256-GCM, random nonce, name-bound, 4 KiB chunks)".to_string()
+    }
+
+    fn self_test(&self) -> Result<(), EncryptionError> {
+        known_answer_tests(&[Primitive::HkdfSha256, Primitive::Aes256Gcm])?;
+        round_trip_self_test(self)
+    }
+}
+
+/// Whether a stored secret was sealed in one piece under `cipher_id`, before the chunked format
+fn is_sealed_in_one_piece(ciphertext: &[u8], cipher_id: CipherId) -> bool {
+    SecretHeader::peek(ciphertext).is_some_and(|header| header.cipher_id == cipher_id)
+}
+
+/// Length of the random nonce of XChaCha20-Poly1305 secrets sealed in one piece
+const XCHACHA20_NONCE_LEN: usize = 24;
+
+/// Length of the Poly1305 authentication tag
+const XCHACHA20_TAG_LEN: usize = 16;
+
+/// XChaCha20-Poly1305 authenticated cipher
+/// 
+/// A software-friendly alternative to `AesGcmCipher` for hosts without
+/// AES hardware acceleration. Secrets are stored in the same chunked
+/// format, with a longer random nonce prefix; per-secret subkeys and
+/// associated data work the same way, and secrets sealed in one piece
+/// (`header || version || nonce || ciphertext || tag`) still decrypt.
+pub struct XChaCha20Cipher {
+    keys: SecretKeys,
+    key_id: Vec<u8>,
+}
+
+impl XChaCha20Cipher {
+    /// Create a new XChaCha20Cipher from a raw 256-bit key
+    pub fn new(key: &[u8]) -> Result<Self, EncryptionError> {
+        if key.len() != 32 {
+            return Err(EncryptionError::InvalidKey(
+                format!("XChaCha20-Poly1305 requires a 32-byte key, got {} bytes", key.len())
+            ));
+        }
+
+        Ok(Self {
+            keys: SecretKeys::new(key),
+            key_id: symmetric_key_id(key),
+        })
+    }
+
+    /// Create XChaCha20Cipher from the key configured in the environment
+    pub fn from_env() -> Result<Self, EncryptionError> {
+        Self::new(&symmetric_key_from_env()?)
+    }
+
+    fn cipher(&self, cipher_id: CipherId, name: &str, version: u64) -> XChaCha20Poly1305 {
+        let subkey = self.keys.derive(cipher_id, name, version);
+        XChaCha20Poly1305::new(chacha20poly1305::Key::from_slice(subkey.as_slice()))
+    }
+
+    fn seal(&self, plaintext: &[u8], name: &str) -> Result<Vec<u8>, EncryptionError> {
+        let header = SecretHeader::new(CipherId::XChaCha20Chunked, &self.key_id).encode();
+        let version = self.keys.next_version();
+        chunked_aead::seal(&self.cipher(CipherId::XChaCha20Chunked, name, version), header, version, name, plaintext, "XChaCha20-Poly1305")
+    }
+
+    fn open(&self, ciphertext: &[u8], name: &str) -> Result<Vec<u8>, EncryptionError> {
+        if is_sealed_in_one_piece(ciphertext, CipherId::XChaCha20) {
+            return self.open_in_one_piece(ciphertext, name);
+        }
+        self.open_range(ciphertext, name, 0, usize::MAX)
+    }
+
+    fn open_range(&self, ciphertext: &[u8], name: &str, offset: u64, size: usize) -> Result<Vec<u8>, EncryptionError> {
+        if is_sealed_in_one_piece(ciphertext, CipherId::XChaCha20) {
+            let plaintext = Zeroizing::new(self.open_in_one_piece(ciphertext, name)?);
+            return Ok(plaintext[read_range(offset, size, plaintext.len())].to_vec());
+        }
+
+        let (header, body) = SecretHeader::split(ciphertext, CipherId::XChaCha20Chunked, &self.key_id)?;
+        let chunked = ChunkedBody::parse::<XChaCha20Poly1305>(header, body, name, "XChaCha20-Poly1305")?;
+        chunked.decrypt_range(&self.cipher(CipherId::XChaCha20Chunked, name, chunked.version()), offset, size)
+    }
+
+    fn open_in_one_piece(&self, ciphertext: &[u8], name: &str) -> Result<Vec<u8>, EncryptionError> {
+        let (header, body) = SecretHeader::split(ciphertext, CipherId::XChaCha20, &self.key_id)?;
+        if body.len() < SECRET_VERSION_LEN + XCHACHA20_NONCE_LEN + XCHACHA20_TAG_LEN {
+            return Err(EncryptionError::InvalidData("XChaCha20-Poly1305 ciphertext is too short".to_string()));
+        }
+
+        let aad = aead_associated_data(header, name);
+        let (version, body) = split_secret_version(body)?;
+        let (nonce, sealed) = body.split_at(XCHACHA20_NONCE_LEN);
+        self.cipher(CipherId::XChaCha20, name, version).decrypt(XNonce::from_slice(nonce), Payload { msg: sealed, aad: &aad })
+            .map_err(|_| EncryptionError::DecryptionFailed(
+                "XChaCha20-Poly1305 authentication failed (wrong key or tampered data)".to_string()
+            ))
+    }
+}
+
+impl SecretCipher for XChaCha20Cipher {
+    fn encrypt(&self, plaintext: &[u8]) -> Result<Vec<u8>, EncryptionError> {
+        self.seal(plaintext, "")
+    }
+
+    fn decrypt(&self, ciphertext: &[u8]) -> Result<Vec<u8>, EncryptionError> {
+        self.open(ciphertext, "")
+    }
+
+    fn encrypt_secret(&self, name: &str, plaintext: &[u8]) -> Result<Vec<u8>, EncryptionError> {
+        self.seal(plaintext, name)
+    }
+
+    fn decrypt_secret(&self, name: &str, ciphertext: &[u8]) -> Result<Vec<u8>, EncryptionError> {
+        self.open(ciphertext, name)
+    }
+
+    fn decrypt_secret_range(&self, name: &str, ciphertext: &[u8], offset: u64, size: usize) -> Result<Vec<u8>, EncryptionError> {
+        self.open_range(ciphertext, name, offset, size)
+    }
+
+    fn cipher_info(&self) -> String {
+        "XChaCha20Cipher (XChaCha20-Poly1305, random nonce, name-bound, 4 KiB chunks)".to_string()
+    }
+
+    fn self_test(&self) -> Result<(), EncryptionError> {
+        known_answer_tests(&[Primitive::HkdfSha256, Primitive::XChaCha20Poly1305])?;
+        round_trip_self_test(self)
+    }
+}
+
+/// AES-256-GCM under a random key that only exists inside this process
+/// 
+/// For sidecar deployments where nothing outside SecretFS decrypts what it
+/// holds in memory. The key is generated from the OS RNG at startup and is
+/// never logged, exported or written anywhere, so it is lost when the
+/// process exits and every start uses a new one.
+pub struct EphemeralCipher {
+    cipher: AesGcmCipher,
+}
+
+impl EphemeralCipher {
+    /// Create a new EphemeralCipher with a fresh random key
+    pub fn new() -> Self {
+        let mut key = [0u8; 32];
+        OsRng.fill_bytes(&mut key);
+        let cipher = AesGcmCipher::new(&key).expect("AES-256-GCM accepts a 32-byte key");
+        key.zeroize();
+        
+        Self { cipher }
+    }
+}
+
+impl Default for EphemeralCipher {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SecretCipher for EphemeralCipher {
+    fn encrypt(&self, plaintext: &[u8]) -> Result<Vec<u8>, EncryptionError> {
+        self.cipher.encrypt(plaintext)
+    }
+
+    fn decrypt(&self, ciphertext: &[u8]) -> Result<Vec<u8>, EncryptionError> {
+        self.cipher.decrypt(ciphertext)
+    }
+
+    fn encrypt_secret(&self, name: &str, plaintext: &[u8]) -> Result<Vec<u8>, EncryptionError> {
+        self.cipher.encrypt_secret(name, plaintext)
+    }
+
+    fn decrypt_secret(&self, name: &str, ciphertext: &[u8]) -> Result<Vec<u8>, EncryptionError> {
+        self.cipher.decrypt_secret(name, ciphertext)
+    }
+
+    fn decrypt_secret_range(&self, name: &str, ciphertext: &[u8], offset: u64, size: usize) -> Result<Vec<u8>, EncryptionError> {
+        self.cipher.decrypt_secret_range(name, ciphertext, offset, size)
+    }
+
+    fn cipher_info(&self) -> String {
+        "EphemeralCipher (AES-256-GCM, random per-process key, never exported)".to_string()
+    }
+
+    fn self_test(&self) -> Result<(), EncryptionError> {
+        self.cipher.self_test()
+    }
+}
+
 /// RSA asymmetric cipher implementation
+/// 
+/// Output is `header || AsymmetricEncryption ciphertext`, with the SHA-256
+/// SPKI fingerprint of the public key as key id. Secrets matching a rule of
+/// the recipient policy are encrypted to that rule's keys only; all others
+/// go to the default recipients.
 pub struct RsaCipher {
-    encryption: AsymmetricEncryption,
+    encryption: Option<AsymmetricEncryption>,
+    policy: RecipientPolicy,
 }
 
 impl RsaCipher {
     /// Create new RSA cipher with public key for encryption
+    /// 
+    /// Default recipients may be omitted when a recipient policy is
+    /// configured; secrets no rule matches are then refused.
     pub fn new() -> Result<Self, EncryptionError> {
-        let encryption = AsymmetricEncryption::from_env()
-            .map_err(|e| EncryptionError::InvalidKey(format!("RSA key error: {}", e)))?;
+        let policy = RecipientPolicy::from_env()
+            .map_err(|e| EncryptionError::InvalidKey(format!("Recipient policy error: {}", e)))?;
+
+        let encryption = match AsymmetricEncryption::from_env() {
+            Ok(encryption) => Some(encryption),
+            Err(AsymmetricError::ConfigurationError(_)) if !policy.is_empty() => None,
+            Err(e) => return Err(EncryptionError::InvalidKey(format!("RSA key error: {}", e))),
+        };
 
-        Ok(RsaCipher { encryption })
+        Self::with_recipients(encryption, policy)
+    }
+
+    /// Create RSA cipher from default recipients and a recipient policy
+    pub fn with_recipients(encryption: Option<AsymmetricEncryption>, policy: RecipientPolicy) -> Result<Self, EncryptionError> {
+        if encryption.is_none() && policy.is_empty() {
+            return Err(EncryptionError::InvalidKey("RSA cipher needs default recipients or a recipient policy".to_string()));
+        }
+
+        Ok(RsaCipher { encryption, policy })
+    }
+
+    fn seal(&self, encryption: &AsymmetricEncryption, plaintext: &[u8]) -> Result<Vec<u8>, EncryptionError> {
+        let body = encryption.encrypt(plaintext)
+            .map_err(|e| EncryptionError::EncryptionFailed(format!("RSA encryption failed: {}", e)))?;
+        
+        let mut output = SecretHeader::new(CipherId::Rsa, encryption.key_id()).encode();
+        output.extend_from_slice(&body);
+        Ok(output)
     }
 }
 
 impl SecretCipher for RsaCipher {
     fn encrypt(&self, plaintext: &[u8]) -> Result<Vec<u8>, EncryptionError> {
-        self.encryption.encrypt(plaintext)
-            .map_err(|e| EncryptionError::EncryptionFailed(format!("RSA encryption failed: {}", e)))
+        let encryption = self.encryption.as_ref().ok_or_else(|| EncryptionError::EncryptionFailed(
+            "No default RSA recipients configured".to_string()
+        ))?;
+        self.seal(encryption, plaintext)
+    }
+
+    fn encrypt_secret(&self, name: &str, plaintext: &[u8]) -> Result<Vec<u8>, EncryptionError> {
+        let encryption = self.policy.encryption_for(name)
+            .or(self.encryption.as_ref())
+            .ok_or_else(|| EncryptionError::EncryptionFailed(format!(
+                "No recipient policy rule matches secret '{}' and no default RSA recipients are configured", name
+            )))?;
+        self.seal(encryption, plaintext)
     }
 
     fn decrypt(&self, _ciphertext: &[u8]) -> Result<Vec<u8>, EncryptionError> {
@@ -197,26 +653,430 @@ impl SecretCipher for RsaCipher {
         ))
     }
 
+    fn cipher_info(&self) -> String {
+        let recipients = match &self.encryption {
+            Some(encryption) => encryption.encryption_info(),
+            None => "RSA (no default recipients)",
+        };
+        let policy = if self.policy.is_empty() {
+            String::new()
+        } else {
+            format!(", {} recipient policy rule(s)", self.policy.len())
+        };
+        format!("{}{} - ⚠️ SECRETS ENCRYPTED FOR AUTHORIZED APPLICATIONS ONLY!", recipients, policy)
+    }
+
+    fn self_test(&self) -> Result<(), EncryptionError> {
+        known_answer_tests(&[Primitive::Aes256Gcm])?;
+
+        // Every recipient set, so a bad key in any policy rule shows up at startup
+        for encryption in self.encryption.iter().chain(self.policy.encryptions()) {
+            encrypt_only_self_test(|plaintext| self.seal(encryption, plaintext))?;
+        }
+        Ok(())
+    }
+}
+
+/// X25519 asymmetric cipher implementation
+/// 
+/// Output is `header || X25519Encryption ciphertext`, with the SHA-256
+/// SPKI fingerprint of the public key as key id. Like `RsaCipher`, secrets
+/// are only decrypted by applications holding a matching private key.
+pub struct X25519Cipher {
+    encryption: X25519Encryption,
+}
+
+impl X25519Cipher {
+    /// Create new X25519 cipher with public key(s) from the environment
+    pub fn new() -> Result<Self, EncryptionError> {
+        let encryption = X25519Encryption::from_env()
+            .map_err(|e| EncryptionError::InvalidKey(format!("X25519 key error: {}", e)))?;
+
+        Ok(X25519Cipher { encryption })
+    }
+
+    /// Create X25519 cipher from a configured encryption
+    pub fn with_encryption(encryption: X25519Encryption) -> Self {
+        X25519Cipher { encryption }
+    }
+}
+
+impl SecretCipher for X25519Cipher {
+    fn encrypt(&self, plaintext: &[u8]) -> Result<Vec<u8>, EncryptionError> {
+        let body = self.encryption.encrypt(plaintext)
+            .map_err(|e| EncryptionError::EncryptionFailed(format!("X25519 encryption failed: {}", e)))?;
+        
+        let mut output = SecretHeader::new(CipherId::X25519, self.encryption.key_id()).encode();
+        output.extend_from_slice(&body);
+        Ok(output)
+    }
+
+    fn decrypt(&self, _ciphertext: &[u8]) -> Result<Vec<u8>, EncryptionError> {
+        // X25519 cipher in SecretFS only encrypts - decryption happens in applications
+        Err(EncryptionError::DecryptionFailed(
+            "X25519 decryption not available in SecretFS - use application with private key".to_string()
+        ))
+    }
+
     fn cipher_info(&self) -> String {
         format!("{} - ⚠️ SECRETS ENCRYPTED FOR AUTHORIZED APPLICATIONS ONLY!",
                 self.encryption.encryption_info())
     }
+
+    fn self_test(&self) -> Result<(), EncryptionError> {
+        known_answer_tests(&[Primitive::X25519, Primitive::HkdfSha256, Primitive::XChaCha20Poly1305])?;
+        encrypt_only_self_test(|plaintext| self.encrypt_secret(SELF_TEST_NAME, plaintext))
+    }
+}
+
+/// age cipher writing standard age files to X25519 recipients
+/// 
+/// Output is a binary age v1 file without a `SecretHeader`, so mounted
+/// secrets can be decrypted with `age -d -i key.txt` or any other age
+/// implementation. SecretFS serves them as stored. Decryption is only
+/// available when identities are configured (e.g. in applications).
+pub struct AgeCipher {
+    recipients: Vec<age::x25519::Recipient>,
+    identities: Vec<age::x25519::Identity>,
+}
+
+impl AgeCipher {
+    /// Create an age cipher encrypting to `recipients`
+    pub fn new(recipients: Vec<age::x25519::Recipient>) -> Result<Self, EncryptionError> {
+        if recipients.is_empty() {
+            return Err(EncryptionError::InvalidKey("At least one age recipient is required".to_string()));
+        }
+
+        Ok(Self {
+            recipients,
+            identities: Vec::new(),
+        })
+    }
+
+    /// Also decrypt with `identities`
+    pub fn with_identities(mut self, identities: Vec<age::x25519::Identity>) -> Self {
+        self.identities = identities;
+        self
+    }
+
+    /// Parse `age1...` recipients separated by newlines or commas
+    /// 
+    /// Accepts the recipients file format of `age -R`: blank lines and
+    /// lines starting with `#` are ignored.
+    pub fn parse_recipients(text: &str) -> Result<Vec<age::x25519::Recipient>, EncryptionError> {
+        text.lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .flat_map(|line| line.split(','))
+            .map(str::trim)
+            .filter(|recipient| !recipient.is_empty())
+            .map(|recipient| recipient.parse::<age::x25519::Recipient>()
+                .map_err(|e| EncryptionError::InvalidKey(format!("Invalid age recipient '{}': {}", recipient, e))))
+            .collect()
+    }
+
+    /// Parse an age identity file as written by `age-keygen`
+    pub fn parse_identities(text: &str) -> Result<Vec<age::x25519::Identity>, EncryptionError> {
+        text.lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(|line| line.parse::<age::x25519::Identity>()
+                .map_err(|e| EncryptionError::InvalidKey(format!("Invalid age identity: {}", e))))
+            .collect()
+    }
+
+    /// Create AgeCipher from the environment
+    /// 
+    /// Recipients come from `SECRETFS_AGE_RECIPIENTS` (comma-separated) and
+    /// `SECRETFS_AGE_RECIPIENTS_FILE`. Identities for decryption are read
+    /// from `SECRETFS_AGE_IDENTITY_FILE` if set.
+    pub fn from_env() -> Result<Self, EncryptionError> {
+        let mut recipients = Vec::new();
+        if let Ok(value) = std::env::var("SECRETFS_AGE_RECIPIENTS") {
+            recipients.extend(Self::parse_recipients(&value)?);
+        }
+        if let Ok(path) = std::env::var("SECRETFS_AGE_RECIPIENTS_FILE") {
+            let text = std::fs::read_to_string(&path)
+                .map_err(|e| EncryptionError::InvalidKey(format!("Failed to read age recipients file {}: {}", path, e)))?;
+            recipients.extend(Self::parse_recipients(&text)?);
+        }
+
+        if recipients.is_empty() {
+            return Err(EncryptionError::InvalidKey(
+                "No age recipients configured. Set SECRETFS_AGE_RECIPIENTS or SECRETFS_AGE_RECIPIENTS_FILE".to_string()
+            ));
+        }
+
+        let mut cipher = Self::new(recipients)?;
+        if let Ok(path) = std::env::var("SECRETFS_AGE_IDENTITY_FILE") {
+            let text = std::fs::read_to_string(&path)
+                .map_err(|e| EncryptionError::InvalidKey(format!("Failed to read age identity file {}: {}", path, e)))?;
+            cipher = cipher.with_identities(Self::parse_identities(&text)?);
+        }
+        Ok(cipher)
+    }
+}
+
+impl SecretCipher for AgeCipher {
+    fn encrypt(&self, plaintext: &[u8]) -> Result<Vec<u8>, EncryptionError> {
+        use std::io::Write;
+
+        let encryptor = age::Encryptor::with_recipients(self.recipients.iter().map(|r| r as &dyn age::Recipient))
+            .map_err(|e| EncryptionError::EncryptionFailed(format!("age encryption failed: {}", e)))?;
+
+        let mut output = Vec::with_capacity(plaintext.len() + 256);
+        let mut writer = encryptor.wrap_output(&mut output)
+            .map_err(|e| EncryptionError::EncryptionFailed(format!("age encryption failed: {}", e)))?;
+        writer.write_all(plaintext)
+            .and_then(|_| writer.finish())
+            .map_err(|e| EncryptionError::EncryptionFailed(format!("age encryption failed: {}", e)))?;
+        Ok(output)
+    }
+
+    fn decrypt(&self, ciphertext: &[u8]) -> Result<Vec<u8>, EncryptionError> {
+        use std::io::Read;
+
+        if self.identities.is_empty() {
+            return Err(EncryptionError::DecryptionFailed(
+                "age decryption not available in SecretFS - use `age -d -i` or an application with the identity".to_string()
+            ));
+        }
+
+        let decryptor = age::Decryptor::new_buffered(ciphertext)
+            .map_err(|e| EncryptionError::InvalidData(format!("Not a valid age file: {}", e)))?;
+        let mut reader = decryptor.decrypt(self.identities.iter().map(|i| i as &dyn age::Identity))
+            .map_err(|e| EncryptionError::DecryptionFailed(format!("age decryption failed: {}", e)))?;
+
+        let mut plaintext = Vec::with_capacity(ciphertext.len());
+        reader.read_to_end(&mut plaintext)
+            .map_err(|e| EncryptionError::DecryptionFailed(format!("age decryption failed: {}", e)))?;
+        Ok(plaintext)
+    }
+
+    fn cipher_info(&self) -> String {
+        format!("AgeCipher (age v1, X25519, {} recipient(s)) - decrypt with `age -d -i key.txt`",
+                self.recipients.len())
+    }
+
+    fn self_test(&self) -> Result<(), EncryptionError> {
+        if self.identities.is_empty() {
+            encrypt_only_self_test(|plaintext| self.encrypt(plaintext))
+        } else {
+            round_trip_self_test(self)
+        }
+    }
+}
+
+/// JWE cipher writing each secret as a JWE compact token
+/// 
+/// Output is a standard RFC 7516 compact serialization without a
+/// `SecretHeader`: `A256GCM` content encryption with the key wrapped by
+/// `RSA-OAEP-256` for an RSA public key, or agreed by `ECDH-ES` for an
+/// X25519 public key. Off-the-shelf JOSE libraries decrypt it with the
+/// application's private key; SecretFS serves the tokens as stored.
+pub struct JweCipher {
+    encryption: JweEncryption,
+}
+
+impl JweCipher {
+    /// Create new JWE cipher with the public key from the environment
+    pub fn new() -> Result<Self, EncryptionError> {
+        let encryption = JweEncryption::from_env()
+            .map_err(|e| EncryptionError::InvalidKey(format!("JWE key error: {}", e)))?;
+
+        Ok(JweCipher { encryption })
+    }
+
+    /// Create JWE cipher from a configured encryption
+    pub fn with_encryption(encryption: JweEncryption) -> Self {
+        JweCipher { encryption }
+    }
+}
+
+impl SecretCipher for JweCipher {
+    fn encrypt(&self, plaintext: &[u8]) -> Result<Vec<u8>, EncryptionError> {
+        self.encryption.encrypt(plaintext)
+            .map(String::into_bytes)
+            .map_err(|e| EncryptionError::EncryptionFailed(format!("JWE encryption failed: {}", e)))
+    }
+
+    fn decrypt(&self, _ciphertext: &[u8]) -> Result<Vec<u8>, EncryptionError> {
+        // JWE cipher in SecretFS only encrypts - decryption happens in applications
+        Err(EncryptionError::DecryptionFailed(
+            "JWE decryption not available in SecretFS - use a JOSE library or application with the private key".to_string()
+        ))
+    }
+
+    fn cipher_info(&self) -> String {
+        format!("{} (kid {}) - ⚠️ SECRETS ENCRYPTED FOR AUTHORIZED APPLICATIONS ONLY!",
+                self.encryption.encryption_info(), self.encryption.kid())
+    }
+
+    fn self_test(&self) -> Result<(), EncryptionError> {
+        match self.encryption.recipient() {
+            JweRecipient::Rsa(_) => known_answer_tests(&[Primitive::Aes256Gcm])?,
+            JweRecipient::X25519(_) => known_answer_tests(&[Primitive::X25519, Primitive::Aes256Gcm])?,
+        }
+        encrypt_only_self_test(|plaintext| self.encrypt(plaintext))
+    }
 }
 
 /// Factory function to create cipher based on environment variable
 /// 
 /// Environment variable `SECRETFS_CIPHER_TYPE` can be:
-/// - "default" or unset: Use DefaultCipher with XOR
+/// - unset: Use DefaultCipher with XOR when a key is configured, otherwise
+///   EphemeralCipher
+/// - "default": Use DefaultCipher with XOR
 /// - "plaintext": Use PlaintextCipher (no encryption)
-/// - Custom implementations can be added here
-pub fn create_cipher_from_env() -> Box<dyn SecretCipher> {
-    let cipher_type = std::env::var("SECRETFS_CIPHER_TYPE")
-        .unwrap_or_else(|_| "default".to_string())
-        .to_lowercase();
+/// - "aes-gcm": Use AesGcmCipher (AES-256-GCM)
+/// - "xchacha20": Use XChaCha20Cipher (XChaCha20-Poly1305)
+/// - "ephemeral": Use EphemeralCipher (AES-256-GCM, random per-process key)
+/// 
+/// The symmetric ciphers take their key from `symmetric_key_from_env`.
+/// - "rsa": Use RsaCipher (public key encryption, decrypted by applications)
+/// - "x25519": Use X25519Cipher (elliptic-curve public key encryption, decrypted by applications)
+/// - "age": Use AgeCipher (standard age files, decrypted with the `age` CLI or applications)
+/// - "jwe": Use JweCipher (JWE compact tokens, decrypted by JOSE libraries or applications)
+/// - "external": Use ExternalCipher (a helper executable from `SECRETFS_CIPHER_HELPER`)
+/// - "vault-transit": Use TransitCipher (data keys generated and unwrapped by Vault Transit)
+/// - the name of a custom cipher registered in a `CipherRegistry`
+/// 
+/// Fails closed: an unknown cipher type or a cipher that cannot be set up
+/// is an error, unless `SECRETFS_CIPHER_FALLBACK` names a cipher type to use
+/// instead. The selected cipher must pass its `self_test` before it is
+/// returned.
+pub fn create_cipher_from_env() -> Result<Box<dyn SecretCipher>, EncryptionError> {
+    CipherRegistry::new().select_from_env()
+}
+
+/// Names of the built-in cipher types
+pub const BUILTIN_CIPHER_TYPES: &[&str] = &["default", "plaintext", "aes-gcm", "xchacha20", "ephemeral", "rsa", "x25519", "age", "jwe", "external", "vault-transit"];
+
+/// Creates a cipher, typically from its own environment configuration
+pub type CipherFactory = Box<dyn Fn() -> Result<Box<dyn SecretCipher>, EncryptionError> + Send + Sync>;
+
+/// The cipher types `SECRETFS_CIPHER_TYPE` can select
+/// 
+/// Starts out with the built-in ciphers. Custom ciphers are registered under
+/// a name of their own; one registered under a built-in name (or alias)
+/// replaces that cipher.
+#[derive(Default)]
+pub struct CipherRegistry {
+    custom: HashMap<String, CipherFactory>,
+}
+
+impl CipherRegistry {
+    /// Create a registry with only the built-in ciphers
+    pub fn new() -> Self {
+        Self::default()
+    }
     
-    match cipher_type.as_str() {
+    /// Make `factory` selectable as cipher type `name` (case-insensitive)
+    pub fn register<F>(&mut self, name: &str, factory: F)
+    where
+        F: Fn() -> Result<Box<dyn SecretCipher>, EncryptionError> + Send + Sync + 'static,
+    {
+        self.custom.insert(name.to_lowercase(), Box::new(factory));
+    }
+    
+    /// Whether `name` is a registered custom cipher
+    pub fn is_registered(&self, name: &str) -> bool {
+        self.custom.contains_key(&name.to_lowercase())
+    }
+    
+    /// All selectable cipher types, built-in first
+    pub fn cipher_types(&self) -> Vec<String> {
+        let mut custom: Vec<String> = self.custom.keys()
+            .filter(|name| !BUILTIN_CIPHER_TYPES.contains(&name.as_str()))
+            .cloned()
+            .collect();
+        custom.sort();
+        BUILTIN_CIPHER_TYPES.iter().map(|name| name.to_string()).chain(custom).collect()
+    }
+    
+    /// Create the cipher of type `cipher_type`, without fallback or self-test
+    pub fn create(&self, cipher_type: &str) -> Result<Box<dyn SecretCipher>, EncryptionError> {
+        let cipher_type = cipher_type.to_lowercase();
+        match self.custom.get(&cipher_type) {
+            Some(factory) => factory().inspect_err(|e| eprintln!("❌ '{}' cipher setup failed: {}", cipher_type, e)),
+            None => create_builtin_cipher(&cipher_type, &self.cipher_types()),
+        }
+    }
+    
+    /// Create the cipher of type `cipher_type` the way SecretFS mounts with it
+    /// 
+    /// Falls back to the `fallback` cipher type, if given, when the cipher
+    /// cannot be set up, and runs the `self_test` of the result.
+    pub fn select(&self, cipher_type: &str, fallback: Option<&str>) -> Result<Box<dyn SecretCipher>, EncryptionError> {
+        let cipher = match (self.create(cipher_type), fallback) {
+            (Ok(cipher), _) => cipher,
+            (Err(e), Some(fallback)) => {
+                eprintln!("⚠️  '{}' cipher unavailable ({}), falling back to '{}' as explicitly allowed",
+                          cipher_type, e, fallback);
+                self.create(fallback)?
+            },
+            (Err(e), None) => return Err(e),
+        };
+        
+        cipher.self_test()?;
+        println!("🧪 Cipher self-test passed");
+        Ok(cipher)
+    }
+    
+    /// Select the cipher named by `SECRETFS_CIPHER_TYPE`, falling back to
+    /// `SECRETFS_CIPHER_FALLBACK`
+    pub fn select_from_env(&self) -> Result<Box<dyn SecretCipher>, EncryptionError> {
+        self.select(&cipher_type_from_env(), std::env::var("SECRETFS_CIPHER_FALLBACK").ok().as_deref())
+    }
+}
+
+/// The cipher type `SECRETFS_CIPHER_TYPE` selects
+/// 
+/// When it is unset: `default` if a symmetric key is configured,
+/// `ephemeral` otherwise.
+pub fn cipher_type_from_env() -> String {
+    match std::env::var("SECRETFS_CIPHER_TYPE") {
+        Ok(cipher_type) => cipher_type,
+        Err(_) if !symmetric_key_configured() => "ephemeral".to_string(),
+        Err(_) => "default".to_string(),
+    }
+}
+
+/// Create the built-in cipher of type `cipher_type` from the environment
+/// 
+/// Prints what the cipher needs when it cannot be set up.
+fn create_builtin_cipher(cipher_type: &str, known_types: &[String]) -> Result<Box<dyn SecretCipher>, EncryptionError> {
+    match cipher_type {
+        "ephemeral" | "memory" => {
+            println!("🔑 Encrypting secrets in memory with a random per-process key");
+            Ok(Box::new(EphemeralCipher::new()))
+        },
         "plaintext" | "none" => {
-            Box::new(PlaintextCipher::new())
+            Ok(Box::new(PlaintextCipher::new()))
+        },
+        "default" | "xor" => {
+            DefaultCipher::from_env()
+                .map(|cipher| Box::new(cipher) as Box<dyn SecretCipher>)
+                .inspect_err(|e| {
+                    eprintln!("❌ Default cipher setup failed: {}", e);
+                    print_symmetric_key_help();
+                })
+        },
+        "aes-gcm" | "aes256-gcm" | "aes" => {
+            AesGcmCipher::from_env()
+                .map(|cipher| Box::new(cipher) as Box<dyn SecretCipher>)
+                .inspect_err(|e| {
+                    eprintln!("❌ AES-256-GCM encryption setup failed: {}", e);
+                    print_symmetric_key_help();
+                })
+        },
+        "xchacha20" | "xchacha20-poly1305" | "chacha20" => {
+            XChaCha20Cipher::from_env()
+                .map(|cipher| Box::new(cipher) as Box<dyn SecretCipher>)
+                .inspect_err(|e| {
+                    eprintln!("❌ XChaCha20-Poly1305 encryption setup failed: {}", e);
+                    print_symmetric_key_help();
+                })
         },
         "rsa" | "asymmetric" => {
             println!("🔐 RSA asymmetric encryption requested");
@@ -227,7 +1087,7 @@ pub fn create_cipher_from_env() -> Box<dyn SecretCipher> {
                     println!("📋 RSA Configuration:");
                     println!("   • Cipher: {}", cipher.cipher_info());
                     println!("   • Security: Application-level access control");
-                    Box::new(cipher)
+                    Ok(Box::new(cipher))
                 },
                 Err(e) => {
                     eprintln!("❌ RSA encryption setup failed: {}", e);
@@ -235,21 +1095,128 @@ pub fn create_cipher_from_env() -> Box<dyn SecretCipher> {
                     eprintln!("   export SECRETFS_PUBLIC_KEY_FILE=/path/to/public.pem");
                     eprintln!("   # OR");
                     eprintln!("   export SECRETFS_PUBLIC_KEY_PEM=\"$(cat public.pem)\"");
+                    eprintln!("   # OR, for several recipients");
+                    eprintln!("   export SECRETFS_PUBLIC_KEY_FILES=/path/to/app1.pem,/path/to/app2.pem");
+                    eprintln!("   # OR, per secret");
+                    eprintln!("   export SECRETFS_RECIPIENT_POLICY_FILE=/path/to/recipients.policy");
                     eprintln!("📖 Generate keys with: ./target/release/secretfs-keygen generate private.pem public.pem");
-                    eprintln!("🔄 Falling back to default symmetric encryption");
-                    Box::new(DefaultCipher::from_env())
+                    Err(e)
+                }
+            }
+        },
+        "x25519" | "ecies" => {
+            match X25519Cipher::new() {
+                Ok(cipher) => {
+                    println!("✅ X25519 encryption initialized successfully");
+                    println!("🔑 Only applications with private key can decrypt secrets");
+                    println!("   • Cipher: {}", cipher.cipher_info());
+                    Ok(Box::new(cipher))
+                },
+                Err(e) => {
+                    eprintln!("❌ X25519 encryption setup failed: {}", e);
+                    eprintln!("💡 X25519 requires public key configuration:");
+                    eprintln!("   export SECRETFS_PUBLIC_KEY_FILE=/path/to/public.pem");
+                    eprintln!("   # OR, for several recipients");
+                    eprintln!("   export SECRETFS_PUBLIC_KEY_FILES=/path/to/app1.pem,/path/to/app2.pem");
+                    eprintln!("📖 Generate keys with: ./target/release/secretfs-keygen generate --type x25519 private.pem public.pem");
+                    Err(e)
+                }
+            }
+        },
+        "age" => {
+            match AgeCipher::from_env() {
+                Ok(cipher) => {
+                    println!("✅ age encryption initialized successfully");
+                    println!("   • Cipher: {}", cipher.cipher_info());
+                    Ok(Box::new(cipher))
+                },
+                Err(e) => {
+                    eprintln!("❌ age encryption setup failed: {}", e);
+                    eprintln!("💡 age requires recipients:");
+                    eprintln!("   export SECRETFS_AGE_RECIPIENTS=age1...");
+                    eprintln!("   # OR");
+                    eprintln!("   export SECRETFS_AGE_RECIPIENTS_FILE=/path/to/recipients.txt");
+                    eprintln!("📖 Generate an identity with: age-keygen -o key.txt");
+                    eprintln!("   # OR: ./target/release/secretfs-keygen generate --type age key.txt recipient.txt");
+                    Err(e)
+                }
+            }
+        },
+        "jwe" => {
+            match JweCipher::new() {
+                Ok(cipher) => {
+                    println!("✅ JWE encryption initialized successfully");
+                    println!("🔑 Secrets are JWE compact tokens for standard JOSE libraries");
+                    println!("   • Cipher: {}", cipher.cipher_info());
+                    Ok(Box::new(cipher))
+                },
+                Err(e) => {
+                    eprintln!("❌ JWE encryption setup failed: {}", e);
+                    eprintln!("💡 JWE requires a single RSA (RSA-OAEP-256) or X25519 (ECDH-ES) public key:");
+                    eprintln!("   export SECRETFS_PUBLIC_KEY_FILE=/path/to/public.pem");
+                    eprintln!("📖 Generate keys with: ./target/release/secretfs-keygen generate [--type x25519] private.pem public.pem");
+                    Err(e)
+                }
+            }
+        },
+        "external" | "helper" => {
+            match ExternalCipher::from_env() {
+                Ok(cipher) => {
+                    println!("✅ External cipher helper started successfully");
+                    println!("   • Cipher: {}", cipher.cipher_info());
+                    Ok(Box::new(cipher))
+                },
+                Err(e) => {
+                    eprintln!("❌ External cipher setup failed: {}", e);
+                    eprintln!("💡 The external cipher requires a helper speaking the cipher helper protocol:");
+                    eprintln!("   export SECRETFS_CIPHER_HELPER=\"/usr/local/bin/hsm-cipher-helper --slot 3\"");
+                    eprintln!("📖 Reference helper: ./target/release/secretfs-cipher-helper");
+                    Err(e)
+                }
+            }
+        },
+        "vault-transit" | "transit" => {
+            match TransitCipher::from_env() {
+                Ok(cipher) => {
+                    println!("✅ Vault Transit envelope encryption initialized successfully");
+                    println!("🔑 Data key generated by Transit, held in locked memory only");
+                    println!("   • Cipher: {}", cipher.cipher_info());
+                    Ok(Box::new(cipher))
+                },
+                Err(e) => {
+                    eprintln!("❌ Vault Transit encryption setup failed: {}", e);
+                    eprintln!("💡 Vault Transit requires a server, a key and a token:");
+                    eprintln!("   export SECRETFS_TRANSIT_ADDR=https://vault.example.com:8200");
+                    eprintln!("   export SECRETFS_TRANSIT_KEY=secretfs");
+                    eprintln!("   export SECRETFS_TRANSIT_TOKEN_FILE=/var/run/secrets/vault-token");
+                    eprintln!("📖 The token needs update on <mount>/datakey/plaintext/<key> and <mount>/decrypt/<key>");
+                    Err(e)
                 }
             }
         },
-        "default" | _ => {
-            Box::new(DefaultCipher::from_env())
+        other => {
+            Err(EncryptionError::ConfigurationError(format!(
+                "Unknown cipher type '{}'; expected one of {}", other, known_types.join(", ")
+            )))
         }
     }
 }
 
+fn print_symmetric_key_help() {
+    eprintln!("💡 Configure a key, for example:");
+    eprintln!("   export SECRETFS_ENCRYPTION_KEY=\"$(openssl rand -base64 32)\"");
+    eprintln!("   # OR, a key file");
+    eprintln!("   head -c 32 /dev/urandom > secretfs.key && export SECRETFS_ENCRYPTION_KEY_FILE=secretfs.key");
+    eprintln!("   # OR, a passphrase (Argon2id, or SECRETFS_KDF=pbkdf2)");
+    eprintln!("   export SECRETFS_ENCRYPTION_PASSPHRASE_FILE=/path/to/passphrase.txt");
+    eprintln!("   export SECRETFS_ENCRYPTION_SALT=\"$(openssl rand -hex 16)\"");
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use aes_gcm::aead::AeadCore;
+    use crate::chunked_aead::{CHUNK_SIZE_LEN, NONCE_SUFFIX_LEN};
     
     #[test]
     fn test_default_cipher_encrypt_decrypt() {
@@ -263,22 +1230,221 @@ mod tests {
         assert_eq!(decrypted, plaintext);
     }
     
+    #[test]
+    fn test_default_cipher_uses_per_secret_subkeys() {
+        let cipher = DefaultCipher::new("test-key");
+        let plaintext = b"same value";
+        
+        let api_key = cipher.encrypt_secret("api_key", plaintext).unwrap();
+        let jwt_secret = cipher.encrypt_secret("jwt_secret", plaintext).unwrap();
+        let body_start = api_key.len() - plaintext.len();
+        assert_ne!(api_key[body_start..], jwt_secret[body_start..]);
+        
+        // Re-encrypting a secret moves it to a new version and subkey
+        let api_key_again = cipher.encrypt_secret("api_key", plaintext).unwrap();
+        assert_ne!(api_key[body_start..], api_key_again[body_start..]);
+        
+        assert_eq!(cipher.decrypt_secret("api_key", &api_key).unwrap(), plaintext);
+        assert_eq!(cipher.decrypt_secret("api_key", &api_key_again).unwrap(), plaintext);
+        assert_ne!(cipher.decrypt_secret("jwt_secret", &api_key).unwrap(), plaintext);
+    }
+    
     #[test]
     fn test_plaintext_cipher() {
         let cipher = PlaintextCipher::new();
         let plaintext = b"hello world";
         
         let encrypted = cipher.encrypt(plaintext).unwrap();
-        assert_eq!(encrypted, plaintext);
+        let (header, body) = SecretHeader::split(&encrypted, CipherId::Plaintext, &[]).unwrap();
+        assert!(!header.is_empty());
+        assert_eq!(body, plaintext);
         
         let decrypted = cipher.decrypt(&encrypted).unwrap();
         assert_eq!(decrypted, plaintext);
     }
     
+    #[test]
+    fn test_aes_gcm_cipher_round_trip() {
+        let cipher = AesGcmCipher::new(&[7u8; 32]).unwrap();
+        let plaintext = b"hello world";
+        
+        let encrypted = cipher.encrypt_secret("api_key", plaintext).unwrap();
+        let header = SecretHeader::new(CipherId::AesGcmChunked, &cipher.key_id).encode();
+        assert!(encrypted.starts_with(&header));
+        assert_eq!(encrypted.len(), header.len() + SECRET_VERSION_LEN + CHUNK_SIZE_LEN + AES_GCM_NONCE_LEN - NONCE_SUFFIX_LEN + plaintext.len() + AES_GCM_TAG_LEN);
+        
+        let decrypted = cipher.decrypt_secret("api_key", &encrypted).unwrap();
+        assert_eq!(decrypted, plaintext);
+        
+        // A fresh nonce is used for every encryption
+        let encrypted_again = cipher.encrypt_secret("api_key", plaintext).unwrap();
+        assert_ne!(encrypted, encrypted_again);
+    }
+    
+    #[test]
+    fn test_aes_gcm_cipher_detects_tampering() {
+        let cipher = AesGcmCipher::new(&[7u8; 32]).unwrap();
+        let mut encrypted = cipher.encrypt_secret("api_key", b"hello world").unwrap();
+        
+        // Secret name is bound as associated data
+        assert!(cipher.decrypt_secret("jwt_secret", &encrypted).is_err());
+        
+        let last = encrypted.len() - 1;
+        encrypted[last] ^= 0x01;
+        assert!(cipher.decrypt_secret("api_key", &encrypted).is_err());
+        
+        let other = AesGcmCipher::new(&[8u8; 32]).unwrap();
+        let encrypted = cipher.encrypt(b"hello world").unwrap();
+        assert!(matches!(other.decrypt(&encrypted), Err(EncryptionError::InvalidKey(_))));
+        
+        // The header is authenticated too
+        let mut relabeled = cipher.encrypt(b"hello world").unwrap();
+        let key_id_offset = SecretHeader::new(CipherId::AesGcmChunked, &cipher.key_id).encode().len() - 1;
+        relabeled[key_id_offset] ^= 0x01;
+        assert!(cipher.decrypt(&relabeled).is_err());
+        
+        // Ciphertext from another cipher is rejected by the header
+        let xchacha = XChaCha20Cipher::new(&[7u8; 32]).unwrap().encrypt(b"hello world").unwrap();
+        assert!(matches!(cipher.decrypt(&xchacha), Err(EncryptionError::InvalidData(_))));
+    }
+    
+    #[test]
+    fn test_aes_gcm_cipher_rejects_bad_key_length() {
+        assert!(AesGcmCipher::new(b"too-short").is_err());
+    }
+    
+    #[test]
+    fn test_xchacha20_cipher_round_trip() {
+        let cipher = XChaCha20Cipher::new(&[9u8; 32]).unwrap();
+        let plaintext = b"hello world";
+        
+        let encrypted = cipher.encrypt_secret("api_key", plaintext).unwrap();
+        let header = SecretHeader::new(CipherId::XChaCha20Chunked, &cipher.key_id).encode();
+        assert_eq!(encrypted.len(), header.len() + SECRET_VERSION_LEN + CHUNK_SIZE_LEN + XCHACHA20_NONCE_LEN - NONCE_SUFFIX_LEN + plaintext.len() + XCHACHA20_TAG_LEN);
+        
+        let decrypted = cipher.decrypt_secret("api_key", &encrypted).unwrap();
+        assert_eq!(decrypted, plaintext);
+    }
+    
+    #[test]
+    fn test_xchacha20_cipher_detects_tampering() {
+        let cipher = XChaCha20Cipher::new(&[9u8; 32]).unwrap();
+        let mut encrypted = cipher.encrypt_secret("api_key", b"hello world").unwrap();
+        
+        assert!(cipher.decrypt_secret("jwt_secret", &encrypted).is_err());
+        
+        let last = encrypted.len() - 1;
+        encrypted[last] ^= 0x01;
+        assert!(cipher.decrypt_secret("api_key", &encrypted).is_err());
+    }
+    
+    #[test]
+    fn test_symmetric_ciphers_decrypt_ranges() {
+        let plaintext: Vec<u8> = (0..64 * 1024).map(|i| (i % 253) as u8).collect();
+        let ciphers: Vec<Box<dyn SecretCipher>> = vec![
+            Box::new(AesGcmCipher::new(&[7u8; 32]).unwrap()),
+            Box::new(XChaCha20Cipher::new(&[9u8; 32]).unwrap()),
+            Box::new(EphemeralCipher::new()),
+            Box::new(DefaultCipher::new("test-key")),
+        ];
+
+        for cipher in &ciphers {
+            let encrypted = cipher.encrypt_secret("ca.pem", &plaintext).unwrap();
+            for offset in (0..plaintext.len() + 4096).step_by(1000) {
+                let range = read_range(offset as u64, 4096, plaintext.len());
+                assert_eq!(cipher.decrypt_secret_range("ca.pem", &encrypted, offset as u64, 4096).unwrap(), &plaintext[range]);
+            }
+            assert!(cipher.decrypt_secret_range("other.pem", &encrypted, 0, 4096).unwrap_or_default() != plaintext[..4096]);
+        }
+    }
+
+    #[test]
+    fn test_symmetric_ciphers_open_secrets_sealed_in_one_piece() {
+        let plaintext = vec![0x42; 10_000];
+
+        // The layout written before the chunked format
+        let aes = AesGcmCipher::new(&[7u8; 32]).unwrap();
+        let mut stored = SecretHeader::new(CipherId::AesGcm, &aes.key_id).encode();
+        let aad = aead_associated_data(&stored, "ca.pem");
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let sealed = aes.cipher(CipherId::AesGcm, "ca.pem", 5).encrypt(&nonce, Payload { msg: &plaintext, aad: &aad }).unwrap();
+        stored.extend_from_slice(&5u64.to_be_bytes());
+        stored.extend_from_slice(&nonce);
+        stored.extend_from_slice(&sealed);
+        assert_eq!(aes.decrypt_secret("ca.pem", &stored).unwrap(), plaintext);
+        assert_eq!(aes.decrypt_secret_range("ca.pem", &stored, 9_000, 4096).unwrap(), &plaintext[9_000..]);
+        assert!(aes.decrypt_secret("other.pem", &stored).is_err());
+
+        let xchacha = XChaCha20Cipher::new(&[9u8; 32]).unwrap();
+        let mut stored = SecretHeader::new(CipherId::XChaCha20, &xchacha.key_id).encode();
+        let aad = aead_associated_data(&stored, "ca.pem");
+        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let sealed = xchacha.cipher(CipherId::XChaCha20, "ca.pem", 5).encrypt(&nonce, Payload { msg: &plaintext, aad: &aad }).unwrap();
+        stored.extend_from_slice(&5u64.to_be_bytes());
+        stored.extend_from_slice(&nonce);
+        stored.extend_from_slice(&sealed);
+        assert_eq!(xchacha.decrypt_secret_range("ca.pem", &stored, 0, 100).unwrap(), &plaintext[..100]);
+    }
+    
+    #[test]
+    fn test_ephemeral_cipher_keys_are_per_instance() {
+        let cipher = EphemeralCipher::new();
+        let encrypted = cipher.encrypt_secret("api_key", b"hello world").unwrap();
+        assert_eq!(cipher.decrypt_secret("api_key", &encrypted).unwrap(), b"hello world");
+        
+        // Another process (or restart) cannot read what this one stored
+        assert!(EphemeralCipher::new().decrypt_secret("api_key", &encrypted).is_err());
+    }
+    
     #[test]
     fn test_empty_key_error() {
         let cipher = DefaultCipher::new("");
         let result = cipher.encrypt(b"test");
         assert!(result.is_err());
     }
+    
+    #[test]
+    fn test_cipher_selection_fails_closed() {
+        // A typo no longer downgrades to another cipher
+        let err = CipherRegistry::new().select("aes-gmc", None).err().unwrap();
+        assert!(matches!(err, EncryptionError::ConfigurationError(_)));
+        
+        // unless a fallback is explicitly allowed
+        let cipher = CipherRegistry::new().select("aes-gmc", Some("ephemeral")).unwrap();
+        assert!(cipher.cipher_info().starts_with("EphemeralCipher"));
+    }
+    
+    #[test]
+    fn test_builtin_cipher_self_tests() {
+        DefaultCipher::new("test-key").self_test().unwrap();
+        PlaintextCipher::new().self_test().unwrap();
+        AesGcmCipher::new(&[1u8; 32]).unwrap().self_test().unwrap();
+        XChaCha20Cipher::new(&[2u8; 32]).unwrap().self_test().unwrap();
+        EphemeralCipher::new().self_test().unwrap();
+        
+        let err = DefaultCipher::new("").self_test().unwrap_err();
+        assert!(matches!(err, EncryptionError::SelfTestFailed(_)));
+    }
+    
+    #[test]
+    fn test_age_cipher_writes_standard_age_files() {
+        let identity = age::x25519::Identity::generate();
+        let cipher = AgeCipher::parse_recipients(&format!("# app\n{}\n", identity.to_public()))
+            .and_then(AgeCipher::new)
+            .unwrap();
+        
+        let encrypted = cipher.encrypt_secret("api_key", b"sk-test").unwrap();
+        assert!(crate::header::is_age_file(&encrypted));
+        assert!(crate::header::is_served_encrypted(&encrypted));
+        
+        // Any age implementation can decrypt it
+        assert_eq!(age::decrypt(&identity, &encrypted).unwrap(), b"sk-test");
+        
+        assert!(cipher.decrypt(&encrypted).is_err());
+        let cipher = cipher.with_identities(vec![identity]);
+        assert_eq!(cipher.decrypt_secret("api_key", &encrypted).unwrap(), b"sk-test");
+        
+        assert!(AgeCipher::parse_recipients("age1notarecipient").is_err());
+        assert!(AgeCipher::new(Vec::new()).is_err());
+    }
 }
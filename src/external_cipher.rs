@@ -0,0 +1,361 @@
+use std::env;
+use std::io::{self, BufRead, BufReader, Write};
+use std::process::{Child, Command, Stdio};
+use std::sync::Mutex;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::thread;
+use std::time::Duration;
+use base64::{Engine as _, engine::general_purpose};
+use zeroize::Zeroizing;
+use crate::encryption::{EncryptionError, SecretCipher};
+
+/// Version of the helper protocol spoken by SecretFS
+pub const HELPER_PROTOCOL_VERSION: &str = "1";
+
+/// How long a helper may take to answer one request by default
+pub const HELPER_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// One message of the cipher helper protocol
+///
+/// Like the git credential helper protocol, a message is a sequence of
+/// `key=value` lines ended by a blank line. Keys may not contain `=` and
+/// neither keys nor values may contain a newline, so binary data is sent
+/// base64-encoded. Values are zeroized on drop since they carry secrets.
+///
+/// SecretFS starts the helper once and keeps it running, sending one
+/// request at a time on its stdin and reading the response from its stdout
+/// (stderr is passed through for logging):
+///
+/// ```text
+/// > operation=hello          < protocol=1
+/// > protocol=1               < info=Corporate HSM, key slot 3
+/// >                          <
+/// > operation=encrypt        < data=<base64 ciphertext>
+/// > name=database_password   <
+/// > data=<base64 plaintext>
+/// >
+/// > operation=decrypt        < error=key slot 3 is locked
+/// > name=database_password   <
+/// > data=<base64 ciphertext>
+/// >
+/// ```
+///
+/// A helper answers `encrypt` and `decrypt` with `data=` or `error=`.
+/// Unknown keys are ignored by both sides, so later versions can add some.
+#[derive(Default)]
+pub struct HelperMessage {
+    fields: Vec<(String, Zeroizing<String>)>,
+}
+
+impl HelperMessage {
+    /// Create an empty message
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Append a field
+    pub fn with(mut self, key: &str, value: impl Into<String>) -> Self {
+        self.fields.push((key.to_string(), Zeroizing::new(value.into())));
+        self
+    }
+
+    /// Append a field holding `data` base64-encoded
+    pub fn with_data(self, key: &str, data: &[u8]) -> Self {
+        self.with(key, general_purpose::STANDARD.encode(data))
+    }
+
+    /// Value of the first field named `key`
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.fields.iter()
+            .find(|(field, _)| field == key)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Base64-decoded value of the first field named `key`
+    pub fn get_data(&self, key: &str) -> io::Result<Option<Zeroizing<Vec<u8>>>> {
+        self.get(key)
+            .map(|value| general_purpose::STANDARD.decode(value)
+                .map(Zeroizing::new)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("Invalid base64 in '{}': {}", key, e))))
+            .transpose()
+    }
+
+    /// Read the next message, `None` at end of input
+    pub fn read_from<R: BufRead>(reader: &mut R) -> io::Result<Option<Self>> {
+        let mut message = Self::new();
+        let mut line = Zeroizing::new(String::new());
+        loop {
+            line.clear();
+            if reader.read_line(&mut line)? == 0 {
+                if message.fields.is_empty() {
+                    return Ok(None);
+                }
+                return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "Helper message not ended by a blank line"));
+            }
+
+            let content = line.strip_suffix('\n').unwrap_or(&line);
+            let content = content.strip_suffix('\r').unwrap_or(content);
+            if content.is_empty() {
+                return Ok(Some(message));
+            }
+
+            let (key, value) = content.split_once('=')
+                .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Helper message line is not 'key=value'"))?;
+            message = message.with(key, value);
+        }
+    }
+
+    /// Write the message, including the blank line that ends it
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        let mut encoded = Zeroizing::new(String::new());
+        for (key, value) in &self.fields {
+            if key.is_empty() || key.contains(['=', '\n']) || value.contains('\n') {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("Field '{}' cannot be sent to a helper", key.escape_debug())));
+            }
+            encoded.push_str(key);
+            encoded.push('=');
+            encoded.push_str(value);
+            encoded.push('\n');
+        }
+        encoded.push('\n');
+
+        writer.write_all(encoded.as_bytes())?;
+        writer.flush()
+    }
+}
+
+/// A running helper process
+///
+/// A thread owns the helper's pipes and runs one exchange at a time, so a
+/// helper that hangs can be given up on after a deadline and killed.
+struct HelperProcess {
+    child: Child,
+    requests: Sender<Zeroizing<Vec<u8>>>,
+    responses: Receiver<io::Result<Option<HelperMessage>>>,
+}
+
+impl HelperProcess {
+    fn exchange(&mut self, request: &HelperMessage, timeout: Duration) -> io::Result<HelperMessage> {
+        let mut encoded = Zeroizing::new(Vec::new());
+        request.write_to(&mut *encoded)?;
+
+        let exited = || io::Error::new(io::ErrorKind::UnexpectedEof, "Helper exited");
+        self.requests.send(encoded).map_err(|_| exited())?;
+        match self.responses.recv_timeout(timeout) {
+            Ok(response) => response?.ok_or_else(exited),
+            Err(RecvTimeoutError::Timeout) => Err(io::Error::new(
+                io::ErrorKind::TimedOut, format!("Helper did not answer within {} ms", timeout.as_millis())
+            )),
+            Err(RecvTimeoutError::Disconnected) => Err(exited()),
+        }
+    }
+}
+
+impl Drop for HelperProcess {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+/// Cipher delegating to an external helper executable
+///
+/// For HSMs and corporate crypto tools that SecretFS cannot link: the
+/// helper speaks the line protocol described at `HelperMessage`. It is
+/// started when the cipher is created and must keep running; if it exits
+/// anyway it is restarted once for the next request. A helper that does not
+/// answer within `HELPER_TIMEOUT` (see `with_timeout`) is killed and only
+/// started again for the next request. Secrets are encrypted as whatever
+/// bytes the helper returns, SecretFS adds no `SecretHeader`, so SecretFS
+/// decrypts them through the helper again when they are read.
+pub struct ExternalCipher {
+    command: Box<dyn Fn() -> Command + Send + Sync>,
+    program: String,
+    info: String,
+    timeout: Duration,
+    process: Mutex<Option<HelperProcess>>,
+}
+
+impl ExternalCipher {
+    /// Start the helper that `command` runs and check it speaks protocol 1
+    ///
+    /// `command` is called again whenever the helper has to be restarted.
+    pub fn new<F>(command: F) -> Result<Self, EncryptionError>
+    where
+        F: Fn() -> Command + Send + Sync + 'static,
+    {
+        let program = command().get_program().to_string_lossy().into_owned();
+        let mut process = spawn_helper(command(), &program)?;
+
+        let hello = HelperMessage::new()
+            .with("operation", "hello")
+            .with("protocol", HELPER_PROTOCOL_VERSION);
+        let response = process.exchange(&hello, HELPER_TIMEOUT)
+            .map_err(|e| EncryptionError::ConfigurationError(format!("Cipher helper '{}' failed the handshake: {}", program, e)))?;
+        if let Some(error) = response.get("error") {
+            return Err(EncryptionError::ConfigurationError(format!("Cipher helper '{}' refused the handshake: {}", program, error)));
+        }
+        if response.get("protocol") != Some(HELPER_PROTOCOL_VERSION) {
+            return Err(EncryptionError::ConfigurationError(format!(
+                "Cipher helper '{}' speaks protocol {}, SecretFS speaks {}",
+                program, response.get("protocol").unwrap_or("(none)"), HELPER_PROTOCOL_VERSION
+            )));
+        }
+
+        Ok(Self {
+            info: response.get("info").unwrap_or("no description").to_string(),
+            command: Box::new(command),
+            program,
+            timeout: HELPER_TIMEOUT,
+            process: Mutex::new(Some(process)),
+        })
+    }
+
+    /// Give up on requests the helper does not answer within `timeout`
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Start the helper configured in `SECRETFS_CIPHER_HELPER`
+    ///
+    /// The variable holds the helper executable followed by its arguments,
+    /// separated by whitespace. The helper inherits the environment.
+    pub fn from_env() -> Result<Self, EncryptionError> {
+        let command_line = env::var("SECRETFS_CIPHER_HELPER")
+            .map_err(|_| EncryptionError::ConfigurationError("SECRETFS_CIPHER_HELPER is not set".to_string()))?;
+        let mut words = command_line.split_whitespace().map(str::to_string);
+        let program = words.next()
+            .ok_or_else(|| EncryptionError::ConfigurationError("SECRETFS_CIPHER_HELPER is empty".to_string()))?;
+        let args: Vec<String> = words.collect();
+
+        Self::new(move || {
+            let mut command = Command::new(&program);
+            command.args(&args);
+            command
+        })
+    }
+
+    /// Send `request`, restarting the helper once if it is gone
+    fn request(&self, request: &HelperMessage) -> io::Result<HelperMessage> {
+        let mut process = self.process.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+
+        if let Some(running) = process.as_mut() {
+            match running.exchange(request, self.timeout) {
+                Ok(response) => return Ok(response),
+                // The rest of a garbled or late response would be read as the answer to
+                // the next request, so the helper is stopped; it is not restarted for
+                // this request, it would send garbage or hang again
+                Err(e) if matches!(e.kind(), io::ErrorKind::InvalidData | io::ErrorKind::TimedOut) => {
+                    *process = None;
+                    return Err(e);
+                },
+                Err(e) => eprintln!("⚠️  Cipher helper '{}' failed ({}), restarting it", self.program, e),
+            }
+        }
+
+        *process = None;
+        let mut restarted = spawn_helper((self.command)(), &self.program)
+            .map_err(|e| io::Error::other(e.to_string()))?;
+        let response = restarted.exchange(request, self.timeout)?;
+        *process = Some(restarted);
+        Ok(response)
+    }
+
+    fn call(&self, operation: &str, name: &str, data: &[u8]) -> Result<Vec<u8>, String> {
+        let request = HelperMessage::new()
+            .with("operation", operation)
+            .with("name", name)
+            .with_data("data", data);
+
+        let response = self.request(&request)
+            .map_err(|e| format!("Cipher helper '{}': {}", self.program, e))?;
+        if let Some(error) = response.get("error") {
+            return Err(format!("Cipher helper '{}': {}", self.program, error));
+        }
+
+        let mut data = response.get_data("data")
+            .map_err(|e| format!("Cipher helper '{}': {}", self.program, e))?
+            .ok_or_else(|| format!("Cipher helper '{}' returned neither data nor error", self.program))?;
+        Ok(std::mem::take(&mut *data))
+    }
+}
+
+impl SecretCipher for ExternalCipher {
+    fn encrypt(&self, plaintext: &[u8]) -> Result<Vec<u8>, EncryptionError> {
+        self.encrypt_secret("", plaintext)
+    }
+
+    fn decrypt(&self, ciphertext: &[u8]) -> Result<Vec<u8>, EncryptionError> {
+        self.decrypt_secret("", ciphertext)
+    }
+
+    fn encrypt_secret(&self, name: &str, plaintext: &[u8]) -> Result<Vec<u8>, EncryptionError> {
+        self.call("encrypt", name, plaintext).map_err(EncryptionError::EncryptionFailed)
+    }
+
+    fn decrypt_secret(&self, name: &str, ciphertext: &[u8]) -> Result<Vec<u8>, EncryptionError> {
+        self.call("decrypt", name, ciphertext).map_err(EncryptionError::DecryptionFailed)
+    }
+
+    fn cipher_info(&self) -> String {
+        format!("ExternalCipher (helper '{}': {})", self.program, self.info)
+    }
+}
+
+fn spawn_helper(mut command: Command, program: &str) -> Result<HelperProcess, EncryptionError> {
+    let mut child = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::inherit())
+        .spawn()
+        .map_err(|e| EncryptionError::ConfigurationError(format!("Failed to start cipher helper '{}': {}", program, e)))?;
+
+    let mut stdin = child.stdin.take().expect("stdin is piped");
+    let mut stdout = BufReader::new(child.stdout.take().expect("stdout is piped"));
+    let (requests, pending) = mpsc::channel::<Zeroizing<Vec<u8>>>();
+    let (answers, responses) = mpsc::channel();
+    // Ends once the helper is dropped: killing it ends blocked reads and writes
+    thread::spawn(move || {
+        for request in pending {
+            let response = stdin.write_all(&request)
+                .and_then(|()| stdin.flush())
+                .and_then(|()| HelperMessage::read_from(&mut stdout));
+            if answers.send(response).is_err() {
+                break;
+            }
+        }
+    });
+    Ok(HelperProcess { child, requests, responses })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_helper_message_framing() {
+        let mut buffer = Vec::new();
+        HelperMessage::new()
+            .with("operation", "encrypt")
+            .with("name", "api_key")
+            .with_data("data", b"sk-123\n")
+            .write_to(&mut buffer)
+            .unwrap();
+        HelperMessage::new().with("error", "locked").write_to(&mut buffer).unwrap();
+        assert_eq!(buffer, b"operation=encrypt\nname=api_key\ndata=c2stMTIzCg==\n\nerror=locked\n\n");
+
+        let mut reader = io::Cursor::new(buffer);
+        let request = HelperMessage::read_from(&mut reader).unwrap().unwrap();
+        assert_eq!(request.get("operation"), Some("encrypt"));
+        assert_eq!(request.get_data("data").unwrap().unwrap().as_slice(), b"sk-123\n");
+        assert_eq!(HelperMessage::read_from(&mut reader).unwrap().unwrap().get("error"), Some("locked"));
+        assert!(HelperMessage::read_from(&mut reader).unwrap().is_none());
+
+        // Values that would break the framing are refused, truncated messages detected
+        assert!(HelperMessage::new().with("name", "a\nb").write_to(&mut Vec::new()).is_err());
+        assert!(HelperMessage::new().with("na=me", "ab").write_to(&mut Vec::new()).is_err());
+        assert!(HelperMessage::read_from(&mut io::Cursor::new(b"operation=hello\n")).is_err());
+        assert!(HelperMessage::read_from(&mut io::Cursor::new(b"no equals sign\n\n")).is_err());
+    }
+}
@@ -0,0 +1,882 @@
+use fuser::{
+    FileAttr, FileType, Filesystem, MountOption, ReplyAttr, ReplyData, ReplyDirectory,
+    ReplyEntry, Request,
+};
+use libc::ENOENT;
+use std::collections::HashMap;
+use std::env;
+use std::error::Error;
+use std::ffi::OsStr;
+use std::fmt;
+use std::io;
+use std::path::Path;
+use std::time::{Duration, UNIX_EPOCH};
+use zeroize::{Zeroize, Zeroizing};
+use crate::chunked_aead::read_range;
+use crate::encryption::{CipherRegistry, EncryptionError, SecretCipher, cipher_type_from_env};
+use crate::header::is_served_encrypted;
+use crate::secret_fetcher::{FetcherRegistry, SecretFetchConfig, SecretFetchError, SecretFetcher};
+use crate::secret_signature::SIGNATURE_FIELD;
+use crate::secure_memory::{MemoryProtectionStatus, SecureBuffer, disable_core_dumps, enable_memfd_secret};
+
+const TTL: Duration = Duration::from_secs(1);
+
+/// Error building or mounting a `SecretFS`
+#[derive(Debug)]
+pub enum SecretFsError {
+    CipherError(EncryptionError),
+    FetcherError(SecretFetchError),
+    MountError(io::Error),
+}
+
+impl fmt::Display for SecretFsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SecretFsError::CipherError(e) => write!(f, "{}", e),
+            SecretFsError::FetcherError(e) => write!(f, "{}", e),
+            SecretFsError::MountError(e) => write!(f, "Failed to mount: {}", e),
+        }
+    }
+}
+
+impl Error for SecretFsError {}
+
+impl From<EncryptionError> for SecretFsError {
+    fn from(error: EncryptionError) -> Self {
+        SecretFsError::CipherError(error)
+    }
+}
+
+impl From<SecretFetchError> for SecretFsError {
+    fn from(error: SecretFetchError) -> Self {
+        SecretFsError::FetcherError(error)
+    }
+}
+
+/// Builds and mounts a `SecretFS` with custom ciphers and fetchers
+///
+/// Ships a SecretFS binary of your own without forking `main.rs`: register
+/// your `SecretCipher`s and `SecretFetcher`s by name, and they become
+/// selectable through `SECRETFS_CIPHER_TYPE` / `SECRETFS_FETCHER_TYPE` next
+/// to the built-in ones, or select them directly. Everything not set on the
+/// builder comes from the environment, exactly as for the stock binary.
+///
+/// ```no_run
+/// use ephemfs::encryption::{SecretCipher, XChaCha20Cipher};
+/// use ephemfs::filesystem::SecretFsBuilder;
+///
+/// SecretFsBuilder::new()
+///     .register_cipher("team-kms", || {
+///         let key = [7u8; 32]; // fetch the key from your KMS instead
+///         Ok(Box::new(XChaCha20Cipher::new(&key)?) as Box<dyn SecretCipher>)
+///     })
+///     .cipher("team-kms")
+///     .secret("greeting", "hello")
+///     .mount("/mnt/secrets")
+///     .unwrap();
+/// ```
+pub struct SecretFsBuilder {
+    ciphers: CipherRegistry,
+    fetchers: FetcherRegistry,
+    cipher_type: Option<String>,
+    cipher_fallback: Option<String>,
+    fetcher_type: Option<String>,
+    fetch_config: Option<SecretFetchConfig>,
+    secrets: Vec<Secret>,
+    env_secrets: bool,
+    signature_sidecars: Option<bool>,
+    memfd_secret: Option<bool>,
+    harden_process: bool,
+}
+
+impl SecretFsBuilder {
+    /// Create a builder configured like the stock binary
+    pub fn new() -> Self {
+        Self {
+            ciphers: CipherRegistry::new(),
+            fetchers: FetcherRegistry::new(),
+            cipher_type: None,
+            cipher_fallback: None,
+            fetcher_type: None,
+            fetch_config: None,
+            secrets: Vec::new(),
+            env_secrets: true,
+            signature_sidecars: None,
+            memfd_secret: None,
+            harden_process: true,
+        }
+    }
+
+    /// Register a custom cipher as cipher type `name`
+    ///
+    /// The factory only runs when the cipher is selected, so it may read
+    /// its own configuration (environment, files, a KMS) at that point.
+    pub fn register_cipher<F>(mut self, name: &str, factory: F) -> Self
+    where
+        F: Fn() -> Result<Box<dyn SecretCipher>, EncryptionError> + Send + Sync + 'static,
+    {
+        self.ciphers.register(name, factory);
+        self
+    }
+
+    /// Register a custom fetcher as fetcher type `name`
+    pub fn register_fetcher<F>(mut self, name: &str, factory: F) -> Self
+    where
+        F: Fn() -> Result<Box<dyn SecretFetcher>, SecretFetchError> + Send + Sync + 'static,
+    {
+        self.fetchers.register(name, factory);
+        self
+    }
+
+    /// Select the cipher type instead of reading `SECRETFS_CIPHER_TYPE`
+    pub fn cipher(mut self, cipher_type: &str) -> Self {
+        self.cipher_type = Some(cipher_type.to_string());
+        self
+    }
+
+    /// Fall back to `cipher_type` instead of reading `SECRETFS_CIPHER_FALLBACK`
+    /// when the selected cipher cannot be set up
+    pub fn cipher_fallback(mut self, cipher_type: &str) -> Self {
+        self.cipher_fallback = Some(cipher_type.to_string());
+        self
+    }
+
+    /// Select the fetcher type instead of reading `SECRETFS_FETCHER_TYPE`
+    pub fn fetcher(mut self, fetcher_type: &str) -> Self {
+        self.fetcher_type = Some(fetcher_type.to_string());
+        self
+    }
+
+    /// Fetch external secrets with `config` instead of the `SECRETFS_URLS` configuration
+    ///
+    /// The config is passed to the fetcher as is, so custom fetchers may
+    /// use URLs other than `http(s)://`.
+    pub fn fetch_config(mut self, config: SecretFetchConfig) -> Self {
+        self.fetch_config = Some(config);
+        self
+    }
+
+    /// Add a secret
+    pub fn secret(mut self, name: &str, value: impl Into<String>) -> Self {
+        self.secrets.push(Secret {
+            name: name.to_string(),
+            content: Zeroizing::new(value.into()),
+            signature: None,
+        });
+        self
+    }
+
+    /// Whether to load secrets from environment variables (default: true)
+    ///
+    /// Covers `DATABASE_PASSWORD`, `API_KEY`, `SECRET_<NAME>` and the other
+    /// variables the stock binary reads.
+    pub fn env_secrets(mut self, enabled: bool) -> Self {
+        self.env_secrets = enabled;
+        self
+    }
+
+    /// Expose `<name>.sig` next to signed secrets instead of reading `SECRETFS_SIGNATURE_SIDECARS`
+    pub fn signature_sidecars(mut self, enabled: bool) -> Self {
+        self.signature_sidecars = Some(enabled);
+        self
+    }
+
+    /// Use memfd_secret instead of reading `SECRETFS_MEMFD_SECRET`
+    pub fn memfd_secret(mut self, enabled: bool) -> Self {
+        self.memfd_secret = Some(enabled);
+        self
+    }
+
+    /// Whether `build` marks the process non-dumpable (default: true)
+    pub fn harden_process(mut self, enabled: bool) -> Self {
+        self.harden_process = enabled;
+        self
+    }
+
+    /// Set up the process, cipher and fetcher and load the secrets
+    pub fn build(self) -> Result<SecretFS, SecretFsError> {
+        // Before any secret is loaded: no core dumps, no ptrace by other processes
+        if self.harden_process {
+            if let Err(e) = disable_core_dumps() {
+                eprintln!("⚠️  Failed to mark the process non-dumpable: {}", e);
+            }
+        }
+
+        // Opt-in: allocate secrets and cipher keys from memfd_secret
+        if self.memfd_secret.unwrap_or_else(|| env_flag("SECRETFS_MEMFD_SECRET")) {
+            if let Err(e) = enable_memfd_secret() {
+                eprintln!("⚠️  {}, falling back to mlock'd pages", e);
+            }
+        }
+
+        // Never mount with a cipher other than the one selected or one that
+        // fails its self-test
+        let cipher_type = self.cipher_type.clone().unwrap_or_else(cipher_type_from_env);
+        let fallback = self.cipher_fallback.clone().or_else(|| env::var("SECRETFS_CIPHER_FALLBACK").ok());
+        let cipher = self.ciphers.select(&cipher_type, fallback.as_deref())?;
+        let fetcher = match &self.fetcher_type {
+            Some(fetcher_type) => self.fetchers.create(fetcher_type)?,
+            None => self.fetchers.create_from_env()?,
+        };
+
+        let mut fs = SecretFS::new(cipher, fetcher);
+
+        let mut secrets = if self.env_secrets { SecretFS::get_secrets_from_env() } else { Vec::new() };
+        secrets.extend(self.secrets);
+        secrets.extend(fs.fetch_external_secrets(self.fetch_config));
+
+        let sidecars = self.signature_sidecars.unwrap_or_else(|| env_flag("SECRETFS_SIGNATURE_SIDECARS"));
+        fs.load_secrets(secrets, sidecars);
+
+        // Show security information
+        fs.security_info();
+
+        Ok(fs)
+    }
+
+    /// Build and mount at `mount_point`, blocking until it is unmounted
+    pub fn mount(self, mount_point: impl AsRef<Path>) -> Result<(), SecretFsError> {
+        self.build()?.mount(mount_point)
+    }
+}
+
+impl Default for SecretFsBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Read-only FUSE filesystem serving secrets from memory
+///
+/// Created with a `SecretFsBuilder`.
+pub struct SecretFS {
+    files: HashMap<u64, SecretFile>,
+    paths: HashMap<String, u64>,
+    next_inode: u64,
+    cipher: Box<dyn SecretCipher>,
+    fetcher: Box<dyn SecretFetcher>,
+}
+
+#[derive(Clone)]
+struct SecretFile {
+    #[allow(dead_code)]
+    inode: u64,
+    name: String,
+    content: SecureBuffer,  // ⚠️  SECURITY: Secrets stored ONLY in RAM (locked, never swapped or dumped)
+    attr: FileAttr,
+    parent: u64,
+    children: Vec<u64>,
+}
+
+// Security: Implement Drop to zero out memory when SecretFile is dropped
+impl Drop for SecretFile {
+    fn drop(&mut self) {
+        // The content is a SecureBuffer, which zeroes itself when dropped
+        // Also zero out the name to be extra secure
+        self.name.zeroize();
+    }
+}
+
+#[derive(Clone)]
+struct Secret {
+    name: String,
+    content: Zeroizing<String>,  // Zeroized when the secret has been added
+    signature: Option<String>,  // base64 Ed25519 signature from the secret service
+}
+
+impl SecretFS {
+    fn new(cipher: Box<dyn SecretCipher>, fetcher: Box<dyn SecretFetcher>) -> Self {
+        let mut fs = SecretFS {
+            files: HashMap::new(),
+            paths: HashMap::new(),
+            next_inode: 2, // Start from 2, as 1 is reserved for root
+            cipher,
+            fetcher,
+        };
+
+        // Create root directory
+        let root_attr = FileAttr {
+            ino: 1,
+            size: 0,
+            blocks: 0,
+            atime: UNIX_EPOCH,
+            mtime: UNIX_EPOCH,
+            ctime: UNIX_EPOCH,
+            crtime: UNIX_EPOCH,
+            kind: FileType::Directory,
+            perm: 0o755,
+            nlink: 2,
+            uid: 1000,
+            gid: 1000,
+            rdev: 0,
+            flags: 0,
+            blksize: 512,
+        };
+
+        let root_info = SecretFile {
+            inode: 1,
+            name: "/".to_string(),
+            content: SecureBuffer::default(),
+            attr: root_attr,
+            parent: 1,
+            children: Vec::new(),
+        };
+
+        fs.files.insert(1, root_info);
+        fs.paths.insert("/".to_string(), 1);
+
+        fs
+    }
+
+    /// Mount read-only at `mount_point`, blocking until it is unmounted
+    pub fn mount(self, mount_point: impl AsRef<Path>) -> Result<(), SecretFsError> {
+        let options = vec![
+            MountOption::RO,
+            MountOption::FSName("secretfs".to_string()),
+        ];
+
+        fuser::mount2(self, mount_point, &options).map_err(SecretFsError::MountError)
+    }
+
+    fn load_secrets(&mut self, secrets: Vec<Secret>, sidecars: bool) {
+        if secrets.is_empty() {
+            println!("⚠️  No secrets configured");
+        }
+
+        for secret in &secrets {
+            self.add_secret_file(secret);
+        }
+        if !sidecars {
+            return;
+        }
+
+        // Optionally expose `<name>.sig` next to signed secrets so apps can verify them,
+        // unless a secret already has that name
+        for secret in &secrets {
+            let Some(signature) = &secret.signature else { continue };
+            let name = format!("{}.sig", secret.name);
+            if self.paths.contains_key(&format!("/{}", name)) {
+                eprintln!("⚠️  Not exposing the signature of {} as {}: a secret has that name", secret.name, name);
+                continue;
+            }
+            self.add_secret_file(&Secret {
+                name,
+                content: Zeroizing::new(signature.clone()),
+                signature: None,
+            });
+        }
+    }
+
+    fn fetch_external_secrets(&self, config: Option<SecretFetchConfig>) -> Vec<Secret> {
+        let config = match config {
+            Some(config) => config,
+            None => {
+                // Check if external fetching is configured
+                if env::var("SECRETFS_URLS").is_err() {
+                    return Vec::new();
+                }
+
+                // Try to create fetch configuration
+                match SecretFetchConfig::from_env() {
+                    Ok(config) => {
+                        if let Err(e) = config.validate() {
+                            eprintln!("❌ Invalid secret fetch configuration: {}", e);
+                            return Vec::new();
+                        }
+                        config
+                    },
+                    Err(e) => {
+                        eprintln!("❌ Failed to create secret fetch configuration: {}", e);
+                        return Vec::new();
+                    }
+                }
+            }
+        };
+
+        // Fetch secrets using the configured fetcher
+        match self.fetcher.fetch_secrets(&config) {
+            Ok(fetched_secrets) => {
+                // Convert FetchedSecret to Secret
+                fetched_secrets.into_iter().map(|mut fs| Secret {
+                    signature: fs.metadata.remove(SIGNATURE_FIELD),
+                    name: fs.key,
+                    content: fs.value,
+                }).collect()
+            },
+            Err(e) => {
+                eprintln!("❌ Failed to fetch external secrets: {}", e);
+                Vec::new()
+            }
+        }
+    }
+
+    fn get_secrets_from_env() -> Vec<Secret> {
+        let mut secrets = Vec::new();
+
+        // Check for individual secret environment variables
+        if let Ok(db_pass) = env::var("DATABASE_PASSWORD") {
+            secrets.push(Secret {
+                name: "database_password".to_string(),
+                content: Zeroizing::new(db_pass),
+                signature: None,
+            });
+        }
+
+        if let Ok(api_key) = env::var("API_KEY") {
+            secrets.push(Secret {
+                name: "api_key".to_string(),
+                content: Zeroizing::new(api_key),
+                signature: None,
+            });
+        }
+
+        if let Ok(jwt_secret) = env::var("JWT_SECRET") {
+            secrets.push(Secret {
+                name: "jwt_secret".to_string(),
+                content: Zeroizing::new(jwt_secret),
+                signature: None,
+            });
+        }
+
+        if let Ok(redis_pass) = env::var("REDIS_PASSWORD") {
+            secrets.push(Secret {
+                name: "redis_password".to_string(),
+                content: Zeroizing::new(redis_pass),
+                signature: None,
+            });
+        }
+
+        if let Ok(vault_token) = env::var("VAULT_TOKEN") {
+            secrets.push(Secret {
+                name: "vault_token".to_string(),
+                content: Zeroizing::new(vault_token),
+                signature: None,
+            });
+        }
+
+        // Check for config file content
+        if let Ok(config_content) = env::var("CONFIG_JSON") {
+            secrets.push(Secret {
+                name: "config.json".to_string(),
+                content: Zeroizing::new(config_content),
+                signature: None,
+            });
+        }
+
+        // Check for custom secrets via SECRET_* pattern
+        for (key, value) in env::vars() {
+            if key.starts_with("SECRET_") {
+                let secret_name = key.strip_prefix("SECRET_")
+                    .unwrap()
+                    .to_lowercase()
+                    .replace('_', "-");
+                secrets.push(Secret {
+                    name: secret_name,
+                    content: Zeroizing::new(value),
+                    signature: None,
+                });
+            }
+        }
+
+
+
+        secrets
+    }
+
+    fn add_secret_file(&mut self, secret: &Secret) {
+        let inode = self.next_inode;
+        self.next_inode += 1;
+
+        // Encrypt the secret content before storing
+        let plaintext_bytes = secret.content.as_bytes();
+        let encrypted_content = match self.cipher.encrypt_secret(&secret.name, plaintext_bytes) {
+            Ok(encrypted) => Zeroizing::new(encrypted),
+            Err(e) => {
+                eprintln!("❌ Failed to encrypt secret '{}': {}", secret.name, e);
+                return;
+            }
+        };
+
+        // Secrets decrypted on read report the size of what `read` returns
+        let size = if is_served_encrypted(&encrypted_content) {
+            encrypted_content.len()
+        } else {
+            plaintext_bytes.len()
+        } as u64;
+
+        let attr = FileAttr {
+            ino: inode,
+            size,
+            blocks: size.div_ceil(512),
+            atime: UNIX_EPOCH,
+            mtime: UNIX_EPOCH,
+            ctime: UNIX_EPOCH,
+            crtime: UNIX_EPOCH,
+            kind: FileType::RegularFile,
+            perm: 0o600, // Read-only for owner only (secure)
+            nlink: 1,
+            uid: 1000,
+            gid: 1000,
+            rdev: 0,
+            flags: 0,
+            blksize: 512,
+        };
+
+        let secret_file = SecretFile {
+            inode,
+            name: secret.name.clone(),
+            content: SecureBuffer::from_slice(&encrypted_content),
+            attr,
+            parent: 1, // All secrets are in root directory
+            children: Vec::new(),
+        };
+
+        self.files.insert(inode, secret_file);
+        self.paths.insert(format!("/{}", secret.name), inode);
+
+        // Add to root directory's children
+        if let Some(root) = self.files.get_mut(&1) {
+            root.children.push(inode);
+        }
+    }
+
+    /// Attributes of inode `ino`
+    fn attr(&self, ino: u64) -> Option<&FileAttr> {
+        self.files.get(&ino).map(|file_info| &file_info.attr)
+    }
+
+    /// Read `size` bytes at `offset` of inode `ino`, or the errno to reply with
+    fn read_file(&self, ino: u64, offset: u64, size: usize) -> Result<Zeroizing<Vec<u8>>, libc::c_int> {
+        let file_info = self.files.get(&ino).filter(|file_info| file_info.attr.kind == FileType::RegularFile).ok_or(ENOENT)?;
+
+        // Secrets that need a private key (RSA, X25519, age) are
+        // served as stored; applications with the key decrypt them
+        if is_served_encrypted(&file_info.content) {
+            return Ok(Zeroizing::new(file_info.content[read_range(offset, size, file_info.content.len())].to_vec()));
+        }
+
+        // For other ciphers, decrypt the part being read before serving it
+        self.cipher.decrypt_secret_range(&file_info.name, &file_info.content, offset, size)
+            .map(Zeroizing::new)
+            .map_err(|e| {
+                eprintln!("❌ Failed to decrypt secret '{}': {}", file_info.name, e);
+                libc::EIO // I/O error
+            })
+    }
+
+    /// Security: Demonstrate that secrets exist only in memory
+    fn security_info(&self) {
+        let total_secrets = self.files.len() - 1; // Exclude root directory
+
+        println!("✅ Loaded {} secret(s) | Encryption: {} | Memory-only storage",
+                 total_secrets,
+                 self.cipher.cipher_info());
+
+        let memory = MemoryProtectionStatus::current();
+        println!("🧠 Memory protection: {}", memory);
+        for warning in memory.warnings() {
+            eprintln!("⚠️  {}", warning);
+        }
+    }
+}
+
+impl Filesystem for SecretFS {
+    fn lookup(&mut self, _req: &Request, parent: u64, name: &OsStr, reply: ReplyEntry) {
+
+        if let Some(parent_info) = self.files.get(&parent) {
+            for &child_inode in &parent_info.children {
+                if let Some(child_info) = self.files.get(&child_inode) {
+                    if child_info.name == name.to_string_lossy() {
+                        reply.entry(&TTL, &child_info.attr, 0);
+                        return;
+                    }
+                }
+            }
+        }
+        reply.error(ENOENT);
+    }
+
+    fn getattr(&mut self, _req: &Request, ino: u64, _fh: Option<u64>, reply: ReplyAttr) {
+
+        if let Some(attr) = self.attr(ino) {
+            reply.attr(&TTL, attr);
+        } else {
+            reply.error(ENOENT);
+        }
+    }
+
+    fn read(
+        &mut self,
+        _req: &Request,
+        ino: u64,
+        _fh: u64,
+        offset: i64,
+        size: u32,
+        _flags: i32,
+        _lock: Option<u64>,
+        reply: ReplyData,
+    ) {
+
+        match self.read_file(ino, offset as u64, size as usize) {
+            Ok(data) => reply.data(&data),
+            Err(errno) => reply.error(errno),
+        }
+    }
+
+    fn readdir(
+        &mut self,
+        _req: &Request,
+        ino: u64,
+        _fh: u64,
+        offset: i64,
+        mut reply: ReplyDirectory,
+    ) {
+
+        if let Some(dir_info) = self.files.get(&ino) {
+            if dir_info.attr.kind != FileType::Directory {
+                reply.error(ENOENT);
+                return;
+            }
+
+            let mut entries = vec![
+                (1, FileType::Directory, "."),
+                (dir_info.parent, FileType::Directory, ".."),
+            ];
+
+            for &child_inode in &dir_info.children {
+                if let Some(child_info) = self.files.get(&child_inode) {
+                    entries.push((child_inode, child_info.attr.kind, &child_info.name));
+                }
+            }
+
+            for (i, entry) in entries.iter().enumerate().skip(offset as usize) {
+                if reply.add(entry.0, (i + 1) as i64, entry.1, entry.2) {
+                    break;
+                }
+            }
+            reply.ok();
+        } else {
+            reply.error(ENOENT);
+        }
+    }
+
+    // Security: Explicitly prevent write operations to maintain memory-only guarantee
+    fn write(
+        &mut self,
+        _req: &Request,
+        _ino: u64,
+        _fh: u64,
+        _offset: i64,
+        _data: &[u8],
+        _write_flags: u32,
+        _flags: i32,
+        _lock_owner: Option<u64>,
+        reply: fuser::ReplyWrite,
+    ) {
+        println!("🚫 SECURITY: Write operation blocked - SecretFS is memory-only and read-only");
+        reply.error(libc::EROFS); // Read-only filesystem error
+    }
+
+    fn create(
+        &mut self,
+        _req: &Request,
+        _parent: u64,
+        _name: &OsStr,
+        _mode: u32,
+        _umask: u32,
+        _flags: i32,
+        reply: fuser::ReplyCreate,
+    ) {
+        println!("🚫 SECURITY: Create operation blocked - SecretFS is memory-only and read-only");
+        reply.error(libc::EROFS); // Read-only filesystem error
+    }
+
+    fn unlink(&mut self, _req: &Request, _parent: u64, _name: &OsStr, reply: fuser::ReplyEmpty) {
+        println!("🚫 SECURITY: Unlink operation blocked - SecretFS is memory-only and read-only");
+        reply.error(libc::EROFS); // Read-only filesystem error
+    }
+
+    fn mkdir(
+        &mut self,
+        _req: &Request,
+        _parent: u64,
+        _name: &OsStr,
+        _mode: u32,
+        _umask: u32,
+        reply: fuser::ReplyEntry,
+    ) {
+        println!("🚫 SECURITY: Mkdir operation blocked - SecretFS is memory-only and read-only");
+        reply.error(libc::EROFS); // Read-only filesystem error
+    }
+}
+
+fn env_flag(name: &str) -> bool {
+    env::var(name)
+        .map(|v| v == "true" || v == "1")
+        .unwrap_or(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encryption::{AesGcmCipher, PlaintextCipher};
+    use crate::secret_fetcher::MockSecretFetcher;
+
+    /// Reverses the bytes; decrypting reverses them back
+    struct ReverseCipher;
+
+    impl SecretCipher for ReverseCipher {
+        fn encrypt(&self, plaintext: &[u8]) -> Result<Vec<u8>, EncryptionError> {
+            Ok(plaintext.iter().rev().copied().collect())
+        }
+
+        fn decrypt(&self, ciphertext: &[u8]) -> Result<Vec<u8>, EncryptionError> {
+            Ok(ciphertext.iter().rev().copied().collect())
+        }
+    }
+
+    fn builder() -> SecretFsBuilder {
+        SecretFsBuilder::new()
+            .env_secrets(false)
+            .harden_process(false)
+            .memfd_secret(false)
+            .signature_sidecars(false)
+    }
+
+    fn stored(fs: &SecretFS, name: &str) -> Vec<u8> {
+        fs.files[&fs.paths[&format!("/{}", name)]].content.to_vec()
+    }
+
+    #[test]
+    fn test_builder_registers_custom_cipher_and_fetcher() {
+        let fs = builder()
+            .register_cipher("reverse", || Ok(Box::new(ReverseCipher) as Box<dyn SecretCipher>))
+            .register_fetcher("team-vault", || Ok(Box::new(MockSecretFetcher::new()) as Box<dyn SecretFetcher>))
+            .cipher("Reverse")
+            .fetcher("team-vault")
+            .fetch_config(SecretFetchConfig {
+                urls: vec!["vault://team/secrets".to_string()],
+                auth_token: None,
+                timeout_seconds: 1,
+                retry_attempts: 0,
+                user_agent: "test".to_string(),
+                headers: HashMap::new(),
+            })
+            .secret("greeting", "hello")
+            .build()
+            .unwrap();
+
+        assert_eq!(stored(&fs, "greeting"), b"olleh");
+        assert_eq!(fs.cipher.decrypt_secret("greeting", &stored(&fs, "greeting")).unwrap(), b"hello");
+        assert!(fs.paths.contains_key("/mock_api_key"));
+        assert_eq!(fs.files[&1].children.len(), 3);
+    }
+
+    #[test]
+    fn test_builder_fails_closed() {
+        // Unknown types are errors, not the default cipher or HTTP fetcher
+        let err = builder().cipher("reverse").build().err().unwrap();
+        assert!(matches!(err, SecretFsError::CipherError(EncryptionError::ConfigurationError(_))));
+        let err = builder().cipher("plaintext").fetcher("team-vault").build().err().unwrap();
+        assert!(matches!(err, SecretFsError::FetcherError(SecretFetchError::ConfigurationError(_))));
+
+        // unless a fallback is explicitly allowed
+        let fs = builder().cipher("reverse").cipher_fallback("plaintext").fetcher("mock").build().unwrap();
+        assert!(fs.cipher.cipher_info().starts_with("PlaintextCipher"));
+
+        // A custom cipher that fails its self-test is refused
+        let err = builder()
+            .register_cipher("lossy", || Ok(Box::new(LossyCipher) as Box<dyn SecretCipher>))
+            .cipher("lossy")
+            .build()
+            .err()
+            .unwrap();
+        assert!(matches!(err, SecretFsError::CipherError(EncryptionError::SelfTestFailed(_))));
+
+        // Built-in names can be replaced
+        let fs = builder()
+            .register_cipher("default", || Ok(Box::new(PlaintextCipher::new()) as Box<dyn SecretCipher>))
+            .cipher("default")
+            .fetcher("mock")
+            .build()
+            .unwrap();
+        assert!(fs.cipher.cipher_info().starts_with("PlaintextCipher"));
+    }
+
+    #[test]
+    fn test_signature_sidecar_does_not_replace_secret() {
+        let mut fs = builder().cipher("plaintext").fetcher("mock").build().unwrap();
+        let secret = |name: &str, value: &str, signature: Option<&str>| Secret {
+            name: name.to_string(),
+            content: Zeroizing::new(value.to_string()),
+            signature: signature.map(str::to_string),
+        };
+        fs.load_secrets(vec![
+            secret("api_key", "sk-123", Some("c2lnbmF0dXJl")),
+            secret("api_key.sig", "a secret of its own", None),
+            secret("db_pass", "hunter2", Some("ZGItc2ln")),
+        ], true);
+
+        assert_eq!(fs.cipher.decrypt_secret("api_key.sig", &stored(&fs, "api_key.sig")).unwrap(), b"a secret of its own");
+        assert_eq!(fs.cipher.decrypt_secret("db_pass.sig", &stored(&fs, "db_pass.sig")).unwrap(), b"ZGItc2ln");
+        assert_eq!(fs.files[&1].children.len(), 4);
+    }
+
+    #[test]
+    fn test_getattr_reports_plaintext_size() {
+        let plaintext = "-----BEGIN CERTIFICATE-----\nMIIB...\n-----END CERTIFICATE-----\n";
+        let fs = builder()
+            .register_cipher("aes-gcm", || Ok(Box::new(AesGcmCipher::new(&[7u8; 32])?) as Box<dyn SecretCipher>))
+            .cipher("aes-gcm")
+            .fetcher("mock")
+            .secret("ca.pem", plaintext)
+            .build()
+            .unwrap();
+
+        // The stored ciphertext is longer, but `stat` shows what `read` returns
+        let ino = fs.paths["/ca.pem"];
+        assert!(stored(&fs, "ca.pem").len() > plaintext.len());
+        assert_eq!(fs.attr(ino).unwrap().size, plaintext.len() as u64);
+        assert_eq!(fs.attr(ino).unwrap().blocks, (plaintext.len() as u64).div_ceil(512));
+        assert_eq!(&fs.read_file(ino, 0, 4096).unwrap()[..], plaintext.as_bytes());
+        assert_eq!(fs.read_file(1, 0, 4096).unwrap_err(), ENOENT);
+    }
+
+    #[test]
+    fn test_read_chunked_secret_up_to_reported_size() {
+        let plaintext: String = (0..100 * 1024).map(|i| char::from(b'a' + (i % 26) as u8)).collect();
+        let fs = builder()
+            .register_cipher("aes-gcm", || Ok(Box::new(AesGcmCipher::new(&[7u8; 32])?) as Box<dyn SecretCipher>))
+            .cipher("aes-gcm")
+            .fetcher("mock")
+            .secret("bundle.pem", &plaintext)
+            .build()
+            .unwrap();
+
+        let ino = fs.paths["/bundle.pem"];
+        let size = fs.attr(ino).unwrap().size;
+        assert_eq!(size, plaintext.len() as u64);
+
+        // Offsets straddling chunk boundaries, up to and past the end of the secret
+        for offset in (0..size).step_by(1021).chain([size - 1, size, size + 1]) {
+            let data = fs.read_file(ino, offset, 4096).unwrap();
+            let start = (offset as usize).min(plaintext.len());
+            let end = (start + 4096).min(plaintext.len());
+            assert_eq!(&data[..], &plaintext.as_bytes()[start..end], "offset {}", offset);
+        }
+        assert!(fs.read_file(ino, size, 4096).unwrap().is_empty());
+    }
+
+    /// Drops the last byte when decrypting
+    struct LossyCipher;
+
+    impl SecretCipher for LossyCipher {
+        fn encrypt(&self, plaintext: &[u8]) -> Result<Vec<u8>, EncryptionError> {
+            Ok(plaintext.to_vec())
+        }
+
+        fn decrypt(&self, ciphertext: &[u8]) -> Result<Vec<u8>, EncryptionError> {
+            Ok(ciphertext[..ciphertext.len().saturating_sub(1)].to_vec())
+        }
+    }
+}
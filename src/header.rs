@@ -0,0 +1,261 @@
+use std::fmt;
+use sha2::{Digest, Sha256};
+use crate::encryption::EncryptionError;
+use crate::jwe::is_jwe_compact;
+
+/// Magic bytes at the start of every stored secret
+///
+/// The first byte is outside the ASCII range so that plaintext secrets
+/// (passwords, tokens, JSON) cannot be mistaken for a header.
+pub const HEADER_MAGIC: &[u8; 4] = b"\x8aSFS";
+
+/// Current header format version
+pub const HEADER_VERSION: u8 = 1;
+
+/// First line of a binary age file (<https://age-encryption.org/v1>)
+/// 
+/// age output is stored without a `SecretHeader` so that the files can be
+/// decrypted by the standard `age` tools.
+pub const AGE_MAGIC: &[u8] = b"age-encryption.org/v1\n";
+
+/// Fixed part of the header: magic, version, cipher id and key id length
+const FIXED_HEADER_LEN: usize = HEADER_MAGIC.len() + 3;
+
+/// Number of bytes of the SHA-256 digest used as key id for symmetric keys
+const SYMMETRIC_KEY_ID_LEN: usize = 8;
+
+/// Identifies the cipher that produced a stored secret
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CipherId {
+    Plaintext,
+    Xor,
+    AesGcm,
+    XChaCha20,
+    Rsa,
+    X25519,
+    VaultTransit,
+    /// AES-256-GCM in the seekable chunked format (see `chunked_aead`)
+    AesGcmChunked,
+    /// XChaCha20-Poly1305 in the seekable chunked format
+    XChaCha20Chunked,
+}
+
+impl CipherId {
+    /// Numeric id written to the header
+    pub fn to_u8(self) -> u8 {
+        match self {
+            CipherId::Plaintext => 0,
+            CipherId::Xor => 1,
+            CipherId::AesGcm => 2,
+            CipherId::XChaCha20 => 3,
+            CipherId::Rsa => 4,
+            CipherId::X25519 => 5,
+            CipherId::VaultTransit => 6,
+            CipherId::AesGcmChunked => 7,
+            CipherId::XChaCha20Chunked => 8,
+        }
+    }
+
+    /// Parse a numeric id read from a header
+    pub fn from_u8(id: u8) -> Result<Self, EncryptionError> {
+        match id {
+            0 => Ok(CipherId::Plaintext),
+            1 => Ok(CipherId::Xor),
+            2 => Ok(CipherId::AesGcm),
+            3 => Ok(CipherId::XChaCha20),
+            4 => Ok(CipherId::Rsa),
+            5 => Ok(CipherId::X25519),
+            6 => Ok(CipherId::VaultTransit),
+            7 => Ok(CipherId::AesGcmChunked),
+            8 => Ok(CipherId::XChaCha20Chunked),
+            other => Err(EncryptionError::InvalidData(format!("Unknown cipher id {} in secret header", other))),
+        }
+    }
+
+    /// Human-readable cipher name
+    pub fn name(self) -> &'static str {
+        match self {
+            CipherId::Plaintext => "plaintext",
+            CipherId::Xor => "xor",
+            CipherId::AesGcm => "aes-256-gcm",
+            CipherId::XChaCha20 => "xchacha20-poly1305",
+            CipherId::Rsa => "rsa",
+            CipherId::X25519 => "x25519",
+            CipherId::VaultTransit => "vault-transit",
+            CipherId::AesGcmChunked => "aes-256-gcm-chunked",
+            CipherId::XChaCha20Chunked => "xchacha20-poly1305-chunked",
+        }
+    }
+
+    /// Whether decryption needs a private key held by the application
+    ///
+    /// SecretFS serves such secrets as stored; everything else is decrypted
+    /// by SecretFS before it is returned from `read`.
+    pub fn requires_private_key(self) -> bool {
+        matches!(self, CipherId::Rsa | CipherId::X25519)
+    }
+}
+
+impl fmt::Display for CipherId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Self-describing header written in front of every stored secret
+///
+/// Layout: `HEADER_MAGIC || version || cipher id || key id length || key id`.
+/// The key id identifies the key a secret was encrypted with without
+/// revealing it, e.g. a truncated SHA-256 of a symmetric key or the SPKI
+/// fingerprint of an RSA public key. It may be empty.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SecretHeader {
+    pub cipher_id: CipherId,
+    pub key_id: Vec<u8>,
+}
+
+impl SecretHeader {
+    pub fn new(cipher_id: CipherId, key_id: &[u8]) -> Self {
+        Self {
+            cipher_id,
+            key_id: key_id.to_vec(),
+        }
+    }
+
+    /// Serialize the header
+    pub fn encode(&self) -> Vec<u8> {
+        let key_id = &self.key_id[..self.key_id.len().min(u8::MAX as usize)];
+
+        let mut out = Vec::with_capacity(FIXED_HEADER_LEN + key_id.len());
+        out.extend_from_slice(HEADER_MAGIC);
+        out.push(HEADER_VERSION);
+        out.push(self.cipher_id.to_u8());
+        out.push(key_id.len() as u8);
+        out.extend_from_slice(key_id);
+        out
+    }
+
+    /// Whether `data` starts with the header magic
+    pub fn is_present(data: &[u8]) -> bool {
+        data.starts_with(HEADER_MAGIC)
+    }
+
+    /// Parse the header at the start of `data`
+    ///
+    /// Returns the header and its encoded length; the cipher-specific body
+    /// starts right after it.
+    pub fn parse(data: &[u8]) -> Result<(Self, usize), EncryptionError> {
+        if !Self::is_present(data) {
+            return Err(EncryptionError::InvalidData("Missing SecretFS header".to_string()));
+        }
+        if data.len() < FIXED_HEADER_LEN {
+            return Err(EncryptionError::InvalidData("Truncated SecretFS header".to_string()));
+        }
+
+        let version = data[HEADER_MAGIC.len()];
+        if version != HEADER_VERSION {
+            return Err(EncryptionError::InvalidData(format!("Unsupported SecretFS header version {}", version)));
+        }
+
+        let cipher_id = CipherId::from_u8(data[HEADER_MAGIC.len() + 1])?;
+        let key_id_len = data[HEADER_MAGIC.len() + 2] as usize;
+        let header_len = FIXED_HEADER_LEN + key_id_len;
+        if data.len() < header_len {
+            return Err(EncryptionError::InvalidData("Truncated SecretFS header key id".to_string()));
+        }
+
+        Ok((Self::new(cipher_id, &data[FIXED_HEADER_LEN..header_len]), header_len))
+    }
+
+    /// Parse the header if one is present, ignoring malformed data
+    pub fn peek(data: &[u8]) -> Option<Self> {
+        Self::parse(data).ok().map(|(header, _)| header)
+    }
+
+    /// Split a stored secret into its encoded header and body
+    ///
+    /// Fails if the header names a different cipher, or a non-empty key id
+    /// that does not match `key_id`.
+    pub fn split<'a>(data: &'a [u8], cipher_id: CipherId, key_id: &[u8]) -> Result<(&'a [u8], &'a [u8]), EncryptionError> {
+        let (header, header_len) = Self::parse(data)?;
+
+        if header.cipher_id != cipher_id {
+            return Err(EncryptionError::InvalidData(format!(
+                "Secret was encrypted with {}, not {}", header.cipher_id, cipher_id
+            )));
+        }
+        if !header.key_id.is_empty() && header.key_id != key_id {
+            return Err(EncryptionError::InvalidKey(format!(
+                "Secret was encrypted with key id {}, this cipher has key id {}",
+                header.key_id_hex(), format_key_id(key_id)
+            )));
+        }
+
+        Ok(data.split_at(header_len))
+    }
+
+    /// Key id as lowercase hex
+    pub fn key_id_hex(&self) -> String {
+        format_key_id(&self.key_id)
+    }
+}
+
+/// Whether `data` is an age file
+pub fn is_age_file(data: &[u8]) -> bool {
+    data.starts_with(AGE_MAGIC)
+}
+
+/// Whether a stored secret is served as-is and decrypted by applications
+/// 
+/// True for secrets that need an application's private key: those whose
+/// header says so, age files and JWE tokens.
+pub fn is_served_encrypted(data: &[u8]) -> bool {
+    is_age_file(data) || is_jwe_compact(data) || SecretHeader::peek(data).is_some_and(|header| header.cipher_id.requires_private_key())
+}
+
+/// Derive a key id for symmetric key material (truncated SHA-256)
+pub fn symmetric_key_id(key: &[u8]) -> Vec<u8> {
+    Sha256::digest(key)[..SYMMETRIC_KEY_ID_LEN].to_vec()
+}
+
+/// Format a key id as lowercase hex
+pub fn format_key_id(key_id: &[u8]) -> String {
+    key_id.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_header_round_trip() {
+        let header = SecretHeader::new(CipherId::AesGcm, &[1, 2, 3, 4]);
+        let mut data = header.encode();
+        data.extend_from_slice(b"body");
+
+        let (parsed, len) = SecretHeader::parse(&data).unwrap();
+        assert_eq!(parsed, header);
+        assert_eq!(&data[len..], b"body");
+        assert_eq!(parsed.key_id_hex(), "01020304");
+    }
+
+    #[test]
+    fn test_split_checks_cipher_and_key_id() {
+        let data = SecretHeader::new(CipherId::XChaCha20, b"key-a").encode();
+
+        assert!(SecretHeader::split(&data, CipherId::XChaCha20, b"key-a").is_ok());
+        assert!(SecretHeader::split(&data, CipherId::AesGcm, b"key-a").is_err());
+        assert!(matches!(
+            SecretHeader::split(&data, CipherId::XChaCha20, b"key-b"),
+            Err(EncryptionError::InvalidKey(_))
+        ));
+    }
+
+    #[test]
+    fn test_plaintext_is_not_a_header() {
+        assert!(SecretHeader::peek(b"super-secret-password").is_none());
+        assert!(SecretHeader::peek(b"").is_none());
+        assert!(SecretHeader::parse(&HEADER_MAGIC[..]).is_err());
+        assert!(!is_served_encrypted(b"super-secret-password"));
+    }
+}
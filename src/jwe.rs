@@ -0,0 +1,450 @@
+use std::env;
+use std::fs;
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, Payload};
+use base64::{Engine as _, engine::general_purpose::URL_SAFE_NO_PAD};
+use rand::rngs::OsRng;
+use rand::RngCore;
+use rsa::RsaPublicKey;
+use rsa::traits::PublicKeyParts;
+use sha2::{Digest, Sha256};
+use x25519_dalek::{EphemeralSecret, PublicKey as X25519PublicKey, StaticSecret};
+use zeroize::Zeroizing;
+use crate::asymmetric_encryption::{AsymmetricError, RsaPadding, decode_public_key_pem, spki_fingerprint};
+use crate::header::format_key_id;
+use crate::pkcs11::{Pkcs11Config, pkcs11_configured};
+use crate::x25519_encryption::{decode_x25519_public_key_pem, x25519_fingerprint};
+
+/// `alg` for content keys wrapped with RSAES-OAEP using SHA-256 and MGF1 with SHA-256 (RFC 7518, 4.3)
+pub const JWE_ALG_RSA_OAEP_256: &str = "RSA-OAEP-256";
+
+/// `alg` for content keys agreed with ephemeral-static ECDH (RFC 7518, 4.6), here over X25519 (RFC 8037)
+pub const JWE_ALG_ECDH_ES: &str = "ECDH-ES";
+
+/// `enc` of all tokens: AES-256-GCM with a 96-bit IV and a 128-bit tag (RFC 7518, 5.3)
+pub const JWE_ENC_A256GCM: &str = "A256GCM";
+
+/// Length of the A256GCM content encryption key
+const CONTENT_KEY_LEN: usize = 32;
+
+/// Length of the A256GCM authentication tag
+const TAG_LEN: usize = 16;
+
+/// Public key a JWE is encrypted to
+#[derive(Clone)]
+pub enum JweRecipient {
+    /// Wrapped with `RSA-OAEP-256`
+    Rsa(RsaPublicKey),
+    /// Agreed with `ECDH-ES`, the ephemeral key sent as an `OKP` `X25519` JWK
+    X25519(X25519PublicKey),
+}
+
+impl JweRecipient {
+    /// Decode an RSA or X25519 public key PEM; `source` names it in errors
+    pub fn from_pem(public_key_pem: &str, source: &str) -> Result<Self, AsymmetricError> {
+        match decode_public_key_pem(public_key_pem, source) {
+            Ok(public_key) => Ok(JweRecipient::Rsa(public_key)),
+            Err(rsa_error) => decode_x25519_public_key_pem(public_key_pem)
+                .map(JweRecipient::X25519)
+                .map_err(|_| rsa_error),
+        }
+    }
+
+    /// Read and decode an RSA or X25519 public key PEM file
+    pub fn from_file(public_key_path: &str) -> Result<Self, AsymmetricError> {
+        let public_key_pem = fs::read_to_string(public_key_path)
+            .map_err(|e| AsymmetricError::FileError(format!("Failed to read public key file {}: {}", public_key_path, e)))?;
+
+        Self::from_pem(&public_key_pem, public_key_path)
+    }
+
+    /// The `alg` used for this key
+    pub fn alg(&self) -> &'static str {
+        match self {
+            JweRecipient::Rsa(_) => JWE_ALG_RSA_OAEP_256,
+            JweRecipient::X25519(_) => JWE_ALG_ECDH_ES,
+        }
+    }
+
+    /// SPKI fingerprint of the key, the same key id as in the SecretFS header
+    fn key_id(&self) -> Result<Vec<u8>, AsymmetricError> {
+        match self {
+            JweRecipient::Rsa(public_key) => spki_fingerprint(public_key),
+            JweRecipient::X25519(public_key) => x25519_fingerprint(public_key),
+        }
+    }
+}
+
+/// Writes secrets as JWE compact serializations (RFC 7516, 7.1)
+///
+/// Every token has a single recipient, so only one public key can be
+/// configured. The `kid` header is the hex SPKI fingerprint of that key, as
+/// printed by `secretfs-keygen info`.
+pub struct JweEncryption {
+    recipient: JweRecipient,
+    kid: String,
+    key_info: String,
+}
+
+impl JweEncryption {
+    /// Encrypt to `recipient`
+    pub fn new(recipient: JweRecipient) -> Result<Self, AsymmetricError> {
+        let kid = format_key_id(&recipient.key_id()?);
+        let key_info = match &recipient {
+            JweRecipient::Rsa(public_key) => format!("JWE {} RSA-{} + {}", JWE_ALG_RSA_OAEP_256, public_key.size() * 8, JWE_ENC_A256GCM),
+            JweRecipient::X25519(_) => format!("JWE {} X25519 + {}", JWE_ALG_ECDH_ES, JWE_ENC_A256GCM),
+        };
+
+        Ok(Self {
+            recipient,
+            kid,
+            key_info,
+        })
+    }
+
+    /// Load the public key from environment configuration
+    ///
+    /// Reads the same variables as `AsymmetricEncryption::from_env`
+    /// (`SECRETFS_PUBLIC_KEY_PEM`, `SECRETFS_PUBLIC_KEY_FILE`,
+    /// `SECRETFS_PUBLIC_KEY_FILES` or a PKCS#11 token key), which must name
+    /// exactly one RSA or X25519 key.
+    pub fn from_env() -> Result<Self, AsymmetricError> {
+        let mut recipients = Vec::new();
+
+        if let Ok(public_key_pem) = env::var("SECRETFS_PUBLIC_KEY_PEM") {
+            recipients.push(JweRecipient::from_pem(&public_key_pem, "environment")?);
+        }
+        if let Ok(public_key_path) = env::var("SECRETFS_PUBLIC_KEY_FILE") {
+            recipients.push(JweRecipient::from_file(&public_key_path)?);
+        }
+        if let Ok(public_key_files) = env::var("SECRETFS_PUBLIC_KEY_FILES") {
+            for public_key_path in public_key_files.split(',').map(str::trim).filter(|path| !path.is_empty()) {
+                recipients.push(JweRecipient::from_file(public_key_path)?);
+            }
+        }
+        if pkcs11_configured() {
+            recipients.push(JweRecipient::Rsa(Pkcs11Config::from_env()?.read_public_key()?));
+        }
+
+        match recipients.len() {
+            1 => Self::new(recipients.remove(0)),
+            0 => Err(AsymmetricError::ConfigurationError(
+                "No public key configuration found. Set SECRETFS_PUBLIC_KEY_PEM, SECRETFS_PUBLIC_KEY_FILE or SECRETFS_PKCS11_MODULE".to_string()
+            )),
+            count => Err(AsymmetricError::ConfigurationError(format!(
+                "A JWE compact token has a single recipient, but {} public keys are configured", count
+            ))),
+        }
+    }
+
+    /// Encrypt `plaintext` into a compact token
+    ///
+    /// `BASE64URL(header) . BASE64URL(encrypted key) . BASE64URL(iv) .
+    /// BASE64URL(ciphertext) . BASE64URL(tag)`; the encrypted key is empty
+    /// for `ECDH-ES`. The encoded header is the additional authenticated data.
+    pub fn encrypt(&self, plaintext: &[u8]) -> Result<String, AsymmetricError> {
+        let mut header = serde_json::json!({
+            "alg": self.recipient.alg(),
+            "enc": JWE_ENC_A256GCM,
+            "kid": self.kid,
+        });
+
+        let (content_key, encrypted_key) = match &self.recipient {
+            JweRecipient::Rsa(public_key) => {
+                let mut content_key = Zeroizing::new([0u8; CONTENT_KEY_LEN]);
+                OsRng.fill_bytes(content_key.as_mut_slice());
+                let encrypted_key = RsaPadding::OaepSha256.encrypt_block(public_key, content_key.as_slice())?;
+                (content_key, encrypted_key)
+            },
+            JweRecipient::X25519(public_key) => {
+                let ephemeral_secret = EphemeralSecret::random_from_rng(OsRng);
+                let ephemeral_public = X25519PublicKey::from(&ephemeral_secret);
+                let shared_secret = ephemeral_secret.diffie_hellman(public_key);
+                if !shared_secret.was_contributory() {
+                    return Err(AsymmetricError::EncryptionError("X25519 recipient key is a low-order point".to_string()));
+                }
+
+                header["epk"] = serde_json::json!({
+                    "kty": "OKP",
+                    "crv": "X25519",
+                    "x": URL_SAFE_NO_PAD.encode(ephemeral_public.as_bytes()),
+                });
+                (concat_kdf(shared_secret.as_bytes(), JWE_ENC_A256GCM, &[], &[]), Vec::new())
+            },
+        };
+
+        let protected = URL_SAFE_NO_PAD.encode(header.to_string());
+        let iv = Aes256Gcm::generate_nonce(&mut OsRng);
+        let mut sealed = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(content_key.as_slice()))
+            .encrypt(&iv, Payload { msg: plaintext, aad: protected.as_bytes() })
+            .map_err(|_| AsymmetricError::EncryptionError("A256GCM encryption failed".to_string()))?;
+        let tag = sealed.split_off(sealed.len() - TAG_LEN);
+
+        Ok(format!(
+            "{}.{}.{}.{}.{}",
+            protected,
+            URL_SAFE_NO_PAD.encode(encrypted_key),
+            URL_SAFE_NO_PAD.encode(iv),
+            URL_SAFE_NO_PAD.encode(sealed),
+            URL_SAFE_NO_PAD.encode(tag),
+        ))
+    }
+
+    /// The `kid` written to every token
+    pub fn kid(&self) -> &str {
+        &self.kid
+    }
+
+    /// The public key tokens are encrypted to
+    pub fn recipient(&self) -> &JweRecipient {
+        &self.recipient
+    }
+
+    /// Get encryption info
+    pub fn encryption_info(&self) -> &str {
+        &self.key_info
+    }
+}
+
+/// The protected header of a JWE
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JweHeader {
+    pub alg: String,
+    pub enc: String,
+    pub kid: Option<String>,
+    epk: Option<[u8; 32]>,
+    apu: Vec<u8>,
+    apv: Vec<u8>,
+}
+
+impl JweHeader {
+    /// Parse the header of a compact token, ignoring malformed data
+    ///
+    /// Only decodes the first segment, so this is cheap enough to tell
+    /// tokens apart from other stored secrets.
+    pub fn peek(data: &[u8]) -> Option<Self> {
+        let token = std::str::from_utf8(data).ok()?.trim_end();
+        if !token.starts_with("eyJ") || token.split('.').count() != 5 {
+            return None;
+        }
+        Self::decode(token.split('.').next()?).ok()
+    }
+
+    fn decode(protected: &str) -> Result<Self, AsymmetricError> {
+        let invalid = |reason: &str| AsymmetricError::DecryptionError(format!("Invalid JWE header: {}", reason));
+
+        let json = Zeroizing::new(URL_SAFE_NO_PAD.decode(protected).map_err(|_| invalid("not base64url"))?);
+        let header: serde_json::Value = serde_json::from_slice(&json).map_err(|_| invalid("not JSON"))?;
+        let field = |name: &str| header.get(name).and_then(serde_json::Value::as_str);
+        let binary = |name: &str| match field(name) {
+            Some(value) => URL_SAFE_NO_PAD.decode(value).map_err(|_| invalid(name)),
+            None => Ok(Vec::new()),
+        };
+
+        let epk = match header.get("epk") {
+            None => None,
+            Some(epk) if epk["kty"] == "OKP" && epk["crv"] == "X25519" => {
+                let x = URL_SAFE_NO_PAD.decode(epk["x"].as_str().unwrap_or_default()).map_err(|_| invalid("epk"))?;
+                Some(x.try_into().map_err(|_| invalid("epk must be a 32-byte X25519 key"))?)
+            },
+            Some(_) => return Err(invalid("only X25519 ephemeral keys are supported")),
+        };
+        if header.get("crit").is_some() || header.get("zip").is_some() {
+            return Err(invalid("'crit' and 'zip' are not supported"));
+        }
+
+        Ok(Self {
+            alg: field("alg").ok_or_else(|| invalid("missing alg"))?.to_string(),
+            enc: field("enc").ok_or_else(|| invalid("missing enc"))?.to_string(),
+            kid: field("kid").map(str::to_string),
+            epk,
+            apu: binary("apu")?,
+            apv: binary("apv")?,
+        })
+    }
+}
+
+/// Whether `data` is a JWE compact token
+pub fn is_jwe_compact(data: &[u8]) -> bool {
+    JweHeader::peek(data).is_some()
+}
+
+/// A JWE compact token split into its parts
+pub struct JweToken<'a> {
+    pub header: JweHeader,
+    protected: &'a str,
+    encrypted_key: Vec<u8>,
+    iv: Vec<u8>,
+    ciphertext: Vec<u8>,
+    tag: Vec<u8>,
+}
+
+impl<'a> JweToken<'a> {
+    /// Parse a compact token; trailing whitespace is ignored
+    pub fn parse(data: &'a [u8]) -> Result<Self, AsymmetricError> {
+        let invalid = |reason: &str| AsymmetricError::DecryptionError(format!("Invalid JWE token: {}", reason));
+
+        let token = std::str::from_utf8(data).map_err(|_| invalid("not ASCII"))?.trim_end();
+        let parts: Vec<&str> = token.split('.').collect();
+        let [protected, encrypted_key, iv, ciphertext, tag] = parts[..] else {
+            return Err(invalid("a compact token has five parts"));
+        };
+        let decode = |part: &str, name: &str| URL_SAFE_NO_PAD.decode(part).map_err(|_| invalid(name));
+
+        let header = JweHeader::decode(protected)?;
+        if header.enc != JWE_ENC_A256GCM {
+            return Err(invalid(&format!("unsupported enc '{}', expected {}", header.enc, JWE_ENC_A256GCM)));
+        }
+
+        let token = Self {
+            header,
+            protected,
+            encrypted_key: decode(encrypted_key, "encrypted key")?,
+            iv: decode(iv, "iv")?,
+            ciphertext: decode(ciphertext, "ciphertext")?,
+            tag: decode(tag, "tag")?,
+        };
+        if token.iv.len() != 12 || token.tag.len() != TAG_LEN {
+            return Err(invalid("A256GCM needs a 96-bit iv and a 128-bit tag"));
+        }
+        Ok(token)
+    }
+
+    /// Fail unless the token is for the key with SPKI fingerprint `key_id`
+    ///
+    /// Tokens without `kid` are accepted.
+    pub(crate) fn check_kid(&self, key_id: &[u8]) -> Result<(), AsymmetricError> {
+        match &self.header.kid {
+            Some(kid) if !kid.eq_ignore_ascii_case(&format_key_id(key_id)) => Err(AsymmetricError::DecryptionError(format!(
+                "Token was encrypted for key id {}, this key has key id {}", kid, format_key_id(key_id)
+            ))),
+            _ => Ok(()),
+        }
+    }
+
+    /// The RSA-OAEP-256 encrypted content key
+    pub(crate) fn rsa_encrypted_key(&self) -> Result<&[u8], AsymmetricError> {
+        self.expect_alg(JWE_ALG_RSA_OAEP_256)?;
+        Ok(&self.encrypted_key)
+    }
+
+    /// Derive the ECDH-ES content key with an X25519 private key
+    pub(crate) fn agree_content_key(&self, private_key: &StaticSecret) -> Result<Zeroizing<[u8; CONTENT_KEY_LEN]>, AsymmetricError> {
+        self.expect_alg(JWE_ALG_ECDH_ES)?;
+        if !self.encrypted_key.is_empty() {
+            return Err(AsymmetricError::DecryptionError("ECDH-ES tokens must have an empty encrypted key".to_string()));
+        }
+
+        let epk = self.header.epk.ok_or_else(|| AsymmetricError::DecryptionError("ECDH-ES token without epk".to_string()))?;
+        let shared_secret = private_key.diffie_hellman(&X25519PublicKey::from(epk));
+        if !shared_secret.was_contributory() {
+            return Err(AsymmetricError::DecryptionError("JWE epk is a low-order point".to_string()));
+        }
+        Ok(concat_kdf(shared_secret.as_bytes(), &self.header.enc, &self.header.apu, &self.header.apv))
+    }
+
+    fn expect_alg(&self, alg: &str) -> Result<(), AsymmetricError> {
+        if self.header.alg == alg {
+            Ok(())
+        } else {
+            Err(AsymmetricError::DecryptionError(format!(
+                "Token uses alg '{}', this key decrypts {} tokens", self.header.alg, alg
+            )))
+        }
+    }
+
+    /// Decrypt and authenticate the content with the A256GCM content key
+    pub(crate) fn decrypt(&self, content_key: &[u8]) -> Result<Vec<u8>, AsymmetricError> {
+        if content_key.len() != CONTENT_KEY_LEN {
+            return Err(AsymmetricError::DecryptionError("JWE content key has an invalid length".to_string()));
+        }
+
+        let mut sealed = Vec::with_capacity(self.ciphertext.len() + TAG_LEN);
+        sealed.extend_from_slice(&self.ciphertext);
+        sealed.extend_from_slice(&self.tag);
+        Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(content_key))
+            .decrypt(Nonce::from_slice(&self.iv), Payload { msg: &sealed, aad: self.protected.as_bytes() })
+            .map_err(|_| AsymmetricError::DecryptionError("JWE authentication failed (wrong key or tampered token)".to_string()))
+    }
+}
+
+/// Concat KDF (NIST SP 800-56A, RFC 7518 4.6.2) for direct key agreement
+///
+/// A single SHA-256 round suffices for a 256-bit key. `AlgorithmID` is the
+/// `enc` value; `PartyUInfo` and `PartyVInfo` are `apu` and `apv`.
+fn concat_kdf(shared_secret: &[u8], enc: &str, apu: &[u8], apv: &[u8]) -> Zeroizing<[u8; CONTENT_KEY_LEN]> {
+    let mut digest = Sha256::new();
+    digest.update(1u32.to_be_bytes());
+    digest.update(shared_secret);
+    for field in [enc.as_bytes(), apu, apv] {
+        digest.update((field.len() as u32).to_be_bytes());
+        digest.update(field);
+    }
+    digest.update(((CONTENT_KEY_LEN * 8) as u32).to_be_bytes());
+    Zeroizing::new(digest.finalize().into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asymmetric_encryption::{AsymmetricDecryption, RsaKeyPair};
+    use crate::header::is_served_encrypted;
+    use crate::keyring::Keyring;
+    use crate::x25519_encryption::X25519KeyPair;
+
+    /// ECDH-ES token with `apu`/`apv` written by an independent implementation
+    /// (Python `cryptography`, ConcatKDFHash) for the X25519 key 0x01..0x20
+    const INTEROP_TOKEN: &str = "eyJhbGciOiJFQ0RILUVTIiwiZW5jIjoiQTI1NkdDTSIsImFwdSI6IlFXeHBZMlUiLCJhcHYiOiJRbTlpIiwiZXBrIjp7Imt0eSI6Ik9LUCIsImNydiI6IlgyNTUxOSIsIngiOiJWeFIyblJGcjkyUTJyblM4ZVQwc01LMFpBOFdheFNjNEJjZmlhWXRCRERZIn19..AAAAAAAAAAAAAAAA.mKsNos3dk-4-xPExaw.BJhWLLyiAXykWipZeMMngQ";
+
+    #[test]
+    fn test_jwe_round_trip() {
+        let rsa = RsaKeyPair::generate(1024).unwrap();
+        let x25519 = X25519KeyPair::generate();
+        let keyring = {
+            let mut keyring = Keyring::from(AsymmetricDecryption::new_with_private_key(rsa.private_key.clone()));
+            keyring.add(AsymmetricDecryption::new_with_x25519_key(x25519.private_key.clone()));
+            keyring
+        };
+
+        for (recipient, alg) in [
+            (JweRecipient::Rsa(rsa.public_key.clone()), JWE_ALG_RSA_OAEP_256),
+            (JweRecipient::X25519(x25519.public_key), JWE_ALG_ECDH_ES),
+        ] {
+            let encryption = JweEncryption::new(recipient).unwrap();
+            let token = encryption.encrypt(b"sk-1234567890").unwrap();
+            assert!(is_served_encrypted(token.as_bytes()));
+
+            let header = JweHeader::peek(token.as_bytes()).unwrap();
+            assert_eq!((header.alg.as_str(), header.enc.as_str()), (alg, JWE_ENC_A256GCM));
+            assert_eq!(header.kid.as_deref(), Some(encryption.kid()));
+            assert_eq!(keyring.decrypt(token.as_bytes()).unwrap(), b"sk-1234567890");
+
+            // Every part is authenticated
+            let parts: Vec<&str> = token.split('.').collect();
+            for index in [0, 2, 3, 4] {
+                let flipped = format!("{}{}", if parts[index].starts_with('A') { 'B' } else { 'A' }, &parts[index][1..]);
+                let mut tampered = parts.clone();
+                tampered[index] = &flipped;
+                assert!(keyring.decrypt(tampered.join(".").as_bytes()).is_err(), "part {} of {}", index, alg);
+            }
+        }
+
+        // A token for another key is refused by kid
+        let other = JweEncryption::new(JweRecipient::X25519(X25519KeyPair::generate().public_key)).unwrap();
+        let err = AsymmetricDecryption::new_with_x25519_key(x25519.private_key)
+            .decrypt(other.encrypt(b"x").unwrap().as_bytes())
+            .unwrap_err();
+        assert!(err.to_string().contains("encrypted for key id"), "{}", err);
+    }
+
+    #[test]
+    fn test_jwe_interop_token() {
+        let private_key = StaticSecret::from(std::array::from_fn::<u8, 32, _>(|i| i as u8 + 1));
+        let decryption = AsymmetricDecryption::new_with_x25519_key(private_key);
+        assert_eq!(decryption.decrypt(INTEROP_TOKEN.as_bytes()).unwrap(), b"sk-1234567890");
+        assert_eq!(decryption.decrypt(format!("{}\n", INTEROP_TOKEN).as_bytes()).unwrap(), b"sk-1234567890");
+
+        assert!(!is_jwe_compact(b"eyJhbGciOiJIUzI1NiJ9.e30.signature"));
+        assert!(!is_jwe_compact(b"super-secret-password"));
+    }
+}
@@ -0,0 +1,321 @@
+use std::env;
+use std::fs;
+use std::sync::atomic::{AtomicU64, Ordering};
+use argon2::{Algorithm, Argon2, Params, Version};
+use base64::{Engine as _, engine::general_purpose};
+use hkdf::Hkdf;
+use rand::RngCore;
+use rand::rngs::OsRng;
+use sha2::Sha256;
+use zeroize::Zeroizing;
+use crate::asymmetric_encryption::read_passphrase_file;
+use crate::encryption::EncryptionError;
+use crate::header::CipherId;
+use crate::secure_memory::SecureBuffer;
+
+/// Length of the keys used by the symmetric ciphers
+pub const SYMMETRIC_KEY_LEN: usize = 32;
+
+/// Minimum salt length accepted for passphrase-derived keys
+pub const MIN_SALT_LEN: usize = 16;
+
+/// Argon2id memory cost in KiB (OWASP recommendation: 19 MiB)
+const ARGON2_MEMORY_KIB: u32 = 19 * 1024;
+
+/// Argon2id iterations
+const ARGON2_ITERATIONS: u32 = 2;
+
+/// Argon2id lanes
+const ARGON2_PARALLELISM: u32 = 1;
+
+/// PBKDF2-HMAC-SHA256 iterations (OWASP recommendation)
+const PBKDF2_ITERATIONS: u32 = 600_000;
+
+/// Length of the secret version stored in front of every symmetric ciphertext
+pub const SECRET_VERSION_LEN: usize = 8;
+
+/// HKDF info prefix for per-secret subkeys; the cipher name follows
+const SUBKEY_INFO: &[u8] = b"secretfs secret key v1 ";
+
+/// Password-based key derivation function for the symmetric ciphers
+///
+/// The parameters are fixed: SecretFS and every application reading its
+/// secrets must derive the same key from the same passphrase and salt.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum KeyDerivation {
+    /// Argon2id, 19 MiB, 2 iterations, 1 lane
+    #[default]
+    Argon2id,
+    /// PBKDF2-HMAC-SHA256 with 600 000 iterations, for FIPS environments
+    Pbkdf2Sha256,
+}
+
+impl KeyDerivation {
+    /// Read the function from `SECRETFS_KDF` (`argon2id` or `pbkdf2`)
+    ///
+    /// Defaults to Argon2id when unset.
+    pub fn from_env() -> Result<Self, EncryptionError> {
+        match env::var("SECRETFS_KDF") {
+            Ok(value) => Self::parse(&value),
+            Err(_) => Ok(Self::default()),
+        }
+    }
+
+    /// Parse a function name
+    pub fn parse(value: &str) -> Result<Self, EncryptionError> {
+        match value.trim().to_lowercase().as_str() {
+            "argon2id" | "argon2" => Ok(KeyDerivation::Argon2id),
+            "pbkdf2" | "pbkdf2-sha256" => Ok(KeyDerivation::Pbkdf2Sha256),
+            other => Err(EncryptionError::InvalidKey(
+                format!("Unknown key derivation function '{}', expected 'argon2id' or 'pbkdf2'", other)
+            )),
+        }
+    }
+
+    /// Derive a 256-bit key from a passphrase and salt
+    pub fn derive_key(&self, passphrase: &[u8], salt: &[u8]) -> Result<Zeroizing<Vec<u8>>, EncryptionError> {
+        if passphrase.is_empty() {
+            return Err(EncryptionError::InvalidKey("Encryption passphrase cannot be empty".to_string()));
+        }
+        if salt.len() < MIN_SALT_LEN {
+            return Err(EncryptionError::InvalidKey(
+                format!("Key derivation salt must be at least {} bytes, got {}", MIN_SALT_LEN, salt.len())
+            ));
+        }
+
+        let mut key = Zeroizing::new(vec![0u8; SYMMETRIC_KEY_LEN]);
+        match self {
+            KeyDerivation::Argon2id => {
+                let params = Params::new(ARGON2_MEMORY_KIB, ARGON2_ITERATIONS, ARGON2_PARALLELISM, Some(SYMMETRIC_KEY_LEN))
+                    .map_err(|e| EncryptionError::InvalidKey(format!("Invalid Argon2id parameters: {}", e)))?;
+                Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+                    .hash_password_into(passphrase, salt, &mut key)
+                    .map_err(|e| EncryptionError::InvalidKey(format!("Argon2id key derivation failed: {}", e)))?;
+            },
+            KeyDerivation::Pbkdf2Sha256 => {
+                pbkdf2::pbkdf2_hmac::<Sha256>(passphrase, salt, PBKDF2_ITERATIONS, &mut key);
+            },
+        }
+        Ok(key)
+    }
+
+    /// Name used in `SECRETFS_KDF` and log output
+    pub fn name(&self) -> &'static str {
+        match self {
+            KeyDerivation::Argon2id => "argon2id",
+            KeyDerivation::Pbkdf2Sha256 => "pbkdf2",
+        }
+    }
+}
+
+/// Master key of a symmetric cipher, handing out one subkey per secret
+///
+/// Subkeys are HKDF-SHA256 of the master key, salted with the secret name
+/// and a version, so no two secrets (or two encryptions of the same secret)
+/// share key material and a recovered subkey reveals neither the master key
+/// nor any other subkey. The version is stored in the ciphertext; it comes
+/// from a counter seeded from the OS RNG, so versions do not repeat across
+/// restarts either. The master key is kept in a `SecureBuffer` and every
+/// subkey is zeroized on drop.
+pub struct SecretKeys {
+    master_key: SecureBuffer,
+    next_version: AtomicU64,
+}
+
+impl SecretKeys {
+    /// Create from master key material
+    pub fn new(master_key: &[u8]) -> Self {
+        Self {
+            master_key: SecureBuffer::from_slice(master_key),
+            next_version: AtomicU64::new(OsRng.next_u64()),
+        }
+    }
+
+    /// Whether the master key is empty
+    pub fn is_empty(&self) -> bool {
+        self.master_key.is_empty()
+    }
+
+    /// Version to encrypt the next secret under
+    pub fn next_version(&self) -> u64 {
+        self.next_version.fetch_add(1, Ordering::Relaxed)
+    }
+
+    /// Derive the subkey of `cipher_id` for version `version` of secret `name`
+    pub fn derive(&self, cipher_id: CipherId, name: &str, version: u64) -> Zeroizing<[u8; SYMMETRIC_KEY_LEN]> {
+        // Length-prefixed name, so that no two (name, version) pairs give the same salt
+        let mut salt = Vec::with_capacity(4 + name.len() + SECRET_VERSION_LEN);
+        salt.extend_from_slice(&(name.len() as u32).to_be_bytes());
+        salt.extend_from_slice(name.as_bytes());
+        salt.extend_from_slice(&version.to_be_bytes());
+
+        let mut info = SUBKEY_INFO.to_vec();
+        info.extend_from_slice(cipher_id.name().as_bytes());
+
+        let mut subkey = Zeroizing::new([0u8; SYMMETRIC_KEY_LEN]);
+        Hkdf::<Sha256>::new(Some(&salt), &self.master_key)
+            .expand(&info, subkey.as_mut_slice())
+            .expect("32 bytes is a valid HKDF-SHA256 output length");
+        subkey
+    }
+}
+
+/// Split the version off the body of a symmetric ciphertext
+pub fn split_secret_version(body: &[u8]) -> Result<(u64, &[u8]), EncryptionError> {
+    if body.len() < SECRET_VERSION_LEN {
+        return Err(EncryptionError::InvalidData("Ciphertext is missing the secret version".to_string()));
+    }
+    let (version, rest) = body.split_at(SECRET_VERSION_LEN);
+    Ok((u64::from_be_bytes(version.try_into().expect("8-byte version")), rest))
+}
+
+/// Load the symmetric key from environment configuration
+///
+/// Sources, in order:
+/// - `SECRETFS_ENCRYPTION_KEY_FILE`: a high-entropy key file, either 32 raw
+///   bytes (`head -c 32 /dev/urandom`) or their base64 encoding
+/// - `SECRETFS_ENCRYPTION_KEY`: a base64-encoded 32-byte key
+/// - `SECRETFS_ENCRYPTION_PASSPHRASE` or `SECRETFS_ENCRYPTION_PASSPHRASE_FILE`
+///   together with `SECRETFS_ENCRYPTION_SALT`: a key derived with the
+///   function in `SECRETFS_KDF`. The salt is used as the literal string,
+///   so a hex salt is used as its hex digits, not decoded
+///
+/// There is no built-in default key; without configuration this fails.
+pub fn symmetric_key_from_env() -> Result<Zeroizing<Vec<u8>>, EncryptionError> {
+    if let Ok(key_path) = env::var("SECRETFS_ENCRYPTION_KEY_FILE") {
+        let key = Zeroizing::new(fs::read(&key_path)
+            .map_err(|e| EncryptionError::InvalidKey(format!("Failed to read key file {}: {}", key_path, e)))?);
+        return decode_key_file(&key)
+            .map_err(|e| EncryptionError::InvalidKey(format!("Key file {}: {}", key_path, e)));
+    }
+
+    if let Ok(key_base64) = env::var("SECRETFS_ENCRYPTION_KEY").map(Zeroizing::new) {
+        return decode_env_key(key_base64.as_bytes());
+    }
+
+    let passphrase = match env::var("SECRETFS_ENCRYPTION_PASSPHRASE") {
+        Ok(passphrase) => Some(Zeroizing::new(passphrase.into_bytes())),
+        Err(_) => match env::var("SECRETFS_ENCRYPTION_PASSPHRASE_FILE") {
+            Ok(passphrase_path) => Some(read_passphrase_file(&passphrase_path)
+                .map_err(|e| EncryptionError::InvalidKey(e.to_string()))?),
+            Err(_) => None,
+        },
+    };
+
+    if let Some(passphrase) = passphrase {
+        let salt = env::var("SECRETFS_ENCRYPTION_SALT").map_err(|_| EncryptionError::InvalidKey(
+            "SECRETFS_ENCRYPTION_SALT must be set with a passphrase (generate one with: openssl rand -hex 16; the string is used as is, not hex-decoded)".to_string()
+        ))?;
+        return KeyDerivation::from_env()?.derive_key(&passphrase, salt.as_bytes());
+    }
+
+    Err(EncryptionError::InvalidKey(
+        "No encryption key configured. Set SECRETFS_ENCRYPTION_KEY_FILE, SECRETFS_ENCRYPTION_KEY (base64, 32 bytes) or SECRETFS_ENCRYPTION_PASSPHRASE with SECRETFS_ENCRYPTION_SALT".to_string()
+    ))
+}
+
+/// Whether any symmetric key source read by `symmetric_key_from_env` is set
+pub fn symmetric_key_configured() -> bool {
+    [
+        "SECRETFS_ENCRYPTION_KEY_FILE",
+        "SECRETFS_ENCRYPTION_KEY",
+        "SECRETFS_ENCRYPTION_PASSPHRASE",
+        "SECRETFS_ENCRYPTION_PASSPHRASE_FILE",
+    ].iter().any(|name| env::var_os(name).is_some())
+}
+
+/// Decode a key file holding 32 raw bytes or their base64 encoding
+fn decode_key_file(content: &[u8]) -> Result<Zeroizing<Vec<u8>>, String> {
+    if content.len() == SYMMETRIC_KEY_LEN {
+        return Ok(Zeroizing::new(content.to_vec()));
+    }
+    decode_base64_key(content)
+}
+
+/// Decode `SECRETFS_ENCRYPTION_KEY`
+///
+/// Earlier versions used the raw string as the key, so the error points
+/// deployments still holding a passphrase there to the new variables.
+fn decode_env_key(encoded: &[u8]) -> Result<Zeroizing<Vec<u8>>, EncryptionError> {
+    decode_base64_key(encoded).map_err(|e| EncryptionError::InvalidKey(format!(
+        "SECRETFS_ENCRYPTION_KEY {}. It must be a base64-encoded {}-byte key (generate one with: openssl rand -base64 32); \
+         passphrases are no longer accepted there, set SECRETFS_ENCRYPTION_PASSPHRASE and SECRETFS_ENCRYPTION_SALT instead",
+        e, SYMMETRIC_KEY_LEN
+    )))
+}
+
+fn decode_base64_key(encoded: &[u8]) -> Result<Zeroizing<Vec<u8>>, String> {
+    let key = Zeroizing::new(general_purpose::STANDARD.decode(encoded.trim_ascii())
+        .map_err(|e| format!("is not valid base64: {}", e))?);
+    if key.len() != SYMMETRIC_KEY_LEN {
+        return Err(format!("must hold a {}-byte key, got {} bytes", SYMMETRIC_KEY_LEN, key.len()));
+    }
+    Ok(key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_key_derivation() {
+        let salt = b"0123456789abcdef";
+        let argon2 = KeyDerivation::Argon2id.derive_key(b"passphrase", salt).unwrap();
+        let pbkdf2 = KeyDerivation::Pbkdf2Sha256.derive_key(b"passphrase", salt).unwrap();
+
+        assert_eq!(argon2.len(), SYMMETRIC_KEY_LEN);
+        assert_eq!(argon2, KeyDerivation::Argon2id.derive_key(b"passphrase", salt).unwrap());
+        assert_ne!(argon2, KeyDerivation::Argon2id.derive_key(b"passphrase", b"fedcba9876543210").unwrap());
+        assert_ne!(argon2, pbkdf2);
+
+        // PBKDF2-HMAC-SHA256 is standard; check against a known answer
+        assert_eq!(
+            hex::encode(&pbkdf2),
+            "74aa45ddb1effafe9e51ae198645fb2a60581ea764d02df6751e7a055453604a"
+        );
+
+        assert!(KeyDerivation::Argon2id.derive_key(b"", salt).is_err());
+        assert!(KeyDerivation::Argon2id.derive_key(b"passphrase", b"short").is_err());
+        assert_eq!(KeyDerivation::parse("PBKDF2").unwrap(), KeyDerivation::Pbkdf2Sha256);
+        assert!(KeyDerivation::parse("md5").is_err());
+    }
+
+    #[test]
+    fn test_secret_subkeys() {
+        let keys = SecretKeys::new(&[1u8; SYMMETRIC_KEY_LEN]);
+        let subkey = keys.derive(CipherId::AesGcm, "api_key", 1);
+
+        assert_eq!(subkey, SecretKeys::new(&[1u8; SYMMETRIC_KEY_LEN]).derive(CipherId::AesGcm, "api_key", 1));
+        assert_ne!(subkey, keys.derive(CipherId::AesGcm, "api_key", 2));
+        assert_ne!(subkey, keys.derive(CipherId::AesGcm, "jwt_secret", 1));
+        assert_ne!(subkey, keys.derive(CipherId::XChaCha20, "api_key", 1));
+        assert_ne!(subkey, SecretKeys::new(&[2u8; SYMMETRIC_KEY_LEN]).derive(CipherId::AesGcm, "api_key", 1));
+        assert_ne!(subkey.as_slice(), [1u8; SYMMETRIC_KEY_LEN].as_slice());
+
+        let version = keys.next_version();
+        assert_eq!(keys.next_version(), version.wrapping_add(1));
+    }
+
+    #[test]
+    fn test_decode_key_file() {
+        let raw = [7u8; SYMMETRIC_KEY_LEN];
+        assert_eq!(*decode_key_file(&raw).unwrap(), raw);
+
+        let encoded = format!("{}\n", general_purpose::STANDARD.encode(raw));
+        assert_eq!(*decode_key_file(encoded.as_bytes()).unwrap(), raw);
+
+        assert!(decode_key_file(b"my-secure-key-2024").is_err());
+        assert!(decode_key_file(general_purpose::STANDARD.encode([7u8; 16]).as_bytes()).is_err());
+    }
+
+    #[test]
+    fn test_env_key_error_explains_migration() {
+        let raw = [7u8; SYMMETRIC_KEY_LEN];
+        assert_eq!(*decode_env_key(general_purpose::STANDARD.encode(raw).as_bytes()).unwrap(), raw);
+
+        // A passphrase left over from when the variable held the raw key
+        let err = decode_env_key(b"my-secure-key-2024").unwrap_err().to_string();
+        assert!(err.contains("openssl rand -base64 32"), "{}", err);
+        assert!(err.contains("SECRETFS_ENCRYPTION_PASSPHRASE"), "{}", err);
+    }
+}
@@ -0,0 +1,281 @@
+use std::env;
+use std::fs;
+use std::path::Path;
+use crate::asymmetric_encryption::{AsymmetricDecryption, AsymmetricError, EnvPassphrase};
+use crate::pkcs11::{Pkcs11RsaKey, pkcs11_configured};
+use crate::header::{SecretHeader, format_key_id};
+use crate::jwe::JweHeader;
+
+/// A set of private keys for decrypting secrets during key rotation
+///
+/// Every ciphertext names the key it was encrypted for: the `SecretHeader`
+/// carries the SPKI fingerprint of a single recipient, and multi-recipient
+/// envelopes list the fingerprint of every recipient slot. The keyring uses
+/// these key ids to pick the right private key, so applications can hold the
+/// old and the new key while SecretFS is switched over.
+#[derive(Default)]
+pub struct Keyring {
+    keys: Vec<AsymmetricDecryption>,
+}
+
+impl Keyring {
+    /// Create an empty keyring
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a key, ignoring keys that are already present
+    pub fn add(&mut self, key: AsymmetricDecryption) {
+        if !self.keys.iter().any(|k| k.cipher_id() == key.cipher_id() && k.key_id() == key.key_id()) {
+            self.keys.push(key);
+        }
+    }
+
+    /// Load private keys from PEM files
+    ///
+    /// `passphrase` decrypts the passphrase-protected keys among them.
+    pub fn from_files<S: AsRef<str>>(private_key_paths: &[S], passphrase: Option<&[u8]>) -> Result<Self, AsymmetricError> {
+        Self::load_files(private_key_paths, |path| AsymmetricDecryption::from_file_with_passphrase(path, passphrase))
+    }
+
+    fn load_files<S: AsRef<str>>(
+        private_key_paths: &[S],
+        load: impl Fn(&str) -> Result<AsymmetricDecryption, AsymmetricError>,
+    ) -> Result<Self, AsymmetricError> {
+        let mut keyring = Self::new();
+        for path in private_key_paths {
+            keyring.add(load(path.as_ref())?);
+        }
+        Ok(keyring)
+    }
+
+    /// Load every private key PEM file in a directory
+    ///
+    /// Files that do not contain a private key (public keys, READMEs) are
+    /// skipped; a private key that fails to decode is an error.
+    /// `passphrase` decrypts the passphrase-protected keys among them.
+    pub fn from_dir(dir: &str, passphrase: Option<&[u8]>) -> Result<Self, AsymmetricError> {
+        Self::load_files(&private_key_paths(dir)?, |path| AsymmetricDecryption::from_file_with_passphrase(path, passphrase))
+    }
+
+    /// Like `from_dir`, reading the passphrase from the environment once a protected key needs it
+    pub(crate) fn from_dir_with_env_passphrase(dir: &str, passphrase: &EnvPassphrase) -> Result<Self, AsymmetricError> {
+        Self::load_files(&private_key_paths(dir)?, |path| AsymmetricDecryption::from_file_with_env_passphrase(path, passphrase))
+    }
+
+    /// Load from environment configuration (for applications)
+    ///
+    /// Collects keys from `SECRETFS_PRIVATE_KEY_PEM`, `SECRETFS_PRIVATE_KEY_FILE`,
+    /// the comma-separated list in `SECRETFS_PRIVATE_KEY_FILES` and every
+    /// private key in `SECRETFS_PRIVATE_KEY_DIR`, plus the PKCS#11 token key
+    /// when `SECRETFS_PKCS11_MODULE` is set. The passphrase for protected
+    /// keys is read once, when the first one is found, see `passphrase_from_env`.
+    pub fn from_env() -> Result<Self, AsymmetricError> {
+        let mut keyring = Self::new();
+        let passphrase = EnvPassphrase::default();
+
+        if let Ok(private_key_pem) = env::var("SECRETFS_PRIVATE_KEY_PEM") {
+            keyring.add(AsymmetricDecryption::from_pem_with_passphrase(&private_key_pem, "environment", passphrase.for_pem(&private_key_pem)?)?);
+        }
+
+        if let Ok(private_key_path) = env::var("SECRETFS_PRIVATE_KEY_FILE") {
+            keyring.add(AsymmetricDecryption::from_file_with_env_passphrase(&private_key_path, &passphrase)?);
+        }
+
+        if let Ok(private_key_files) = env::var("SECRETFS_PRIVATE_KEY_FILES") {
+            let paths: Vec<&str> = private_key_files.split(',')
+                .map(str::trim)
+                .filter(|path| !path.is_empty())
+                .collect();
+            keyring.extend(Self::load_files(&paths, |path| AsymmetricDecryption::from_file_with_env_passphrase(path, &passphrase))?);
+        }
+
+        if let Ok(private_key_dir) = env::var("SECRETFS_PRIVATE_KEY_DIR") {
+            keyring.extend(Self::from_dir_with_env_passphrase(&private_key_dir, &passphrase)?);
+        }
+
+        if pkcs11_configured() {
+            keyring.add(AsymmetricDecryption::new_with_pkcs11_key(Pkcs11RsaKey::from_env()?));
+        }
+
+        if keyring.is_empty() {
+            return Err(AsymmetricError::ConfigurationError(
+                "No private key configuration found. Set SECRETFS_PRIVATE_KEY_PEM, SECRETFS_PRIVATE_KEY_FILE, SECRETFS_PRIVATE_KEY_FILES, SECRETFS_PRIVATE_KEY_DIR or SECRETFS_PKCS11_MODULE".to_string()
+            ));
+        }
+
+        Ok(keyring)
+    }
+
+    fn extend(&mut self, other: Keyring) {
+        for key in other.keys {
+            self.add(key);
+        }
+    }
+
+    /// Decrypt with the key the ciphertext was encrypted for
+    ///
+    /// A header naming a single key id selects that key directly, as does
+    /// the `kid` of a JWE token. For multi-recipient envelopes, keys whose id
+    /// appears in a recipient slot are tried first; legacy ciphertexts
+    /// without key ids are tried with every RSA key.
+    pub fn decrypt(&self, ciphertext: &[u8]) -> Result<Vec<u8>, AsymmetricError> {
+        if self.keys.is_empty() {
+            return Err(AsymmetricError::ConfigurationError("Keyring holds no private keys".to_string()));
+        }
+
+        if let Some(kid) = JweHeader::peek(ciphertext).and_then(|header| header.kid) {
+            let key = self.keys.iter()
+                .find(|key| kid.eq_ignore_ascii_case(&format_key_id(key.key_id())))
+                .ok_or_else(|| AsymmetricError::DecryptionError(format!(
+                    "No key in the keyring matches JWE kid {} (keyring holds: {})", kid, self.key_ids_hex().join(", ")
+                )))?;
+            return key.decrypt(ciphertext);
+        }
+
+        let header = SecretHeader::peek(ciphertext);
+        if let Some(header) = header.as_ref().filter(|header| !header.key_id.is_empty()) {
+            let key = self.keys.iter()
+                .find(|key| key.cipher_id() == header.cipher_id && key.key_id() == header.key_id.as_slice())
+                .ok_or_else(|| AsymmetricError::DecryptionError(format!(
+                    "No key in the keyring matches {} key id {} (keyring holds: {})",
+                    header.cipher_id, header.key_id_hex(), self.key_ids_hex().join(", ")
+                )))?;
+            return key.decrypt(ciphertext);
+        }
+
+        let mut candidates: Vec<&AsymmetricDecryption> = self.keys.iter()
+            .filter(|key| header.as_ref().is_none_or(|header| key.cipher_id() == header.cipher_id))
+            .collect();
+        // Keys named in a recipient slot first
+        candidates.sort_by_key(|key| !contains(ciphertext, key.key_id()));
+
+        let mut last_error = AsymmetricError::DecryptionError("No key in the keyring can decrypt this secret".to_string());
+        for key in candidates {
+            match key.decrypt(ciphertext) {
+                Ok(plaintext) => return Ok(plaintext),
+                Err(e) => last_error = e,
+            }
+        }
+        Err(last_error)
+    }
+
+    /// Key ids (SPKI fingerprints) of all keys
+    pub fn key_ids(&self) -> Vec<&[u8]> {
+        self.keys.iter().map(|key| key.key_id()).collect()
+    }
+
+    fn key_ids_hex(&self) -> Vec<String> {
+        self.keys.iter().map(|key| format_key_id(key.key_id())).collect()
+    }
+
+    /// Number of keys in the keyring
+    pub fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    /// Whether the keyring holds no keys
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    /// Get decryption info
+    pub fn decryption_info(&self) -> String {
+        match self.keys.as_slice() {
+            [key] => key.decryption_info().to_string(),
+            keys => format!("Keyring with {} private keys", keys.len()),
+        }
+    }
+}
+
+impl From<AsymmetricDecryption> for Keyring {
+    fn from(key: AsymmetricDecryption) -> Self {
+        Self { keys: vec![key] }
+    }
+}
+
+/// Whether a file looks like a PEM private key
+/// The private key files in `dir`, sorted
+fn private_key_paths(dir: &str) -> Result<Vec<String>, AsymmetricError> {
+    let entries = fs::read_dir(dir)
+        .map_err(|e| AsymmetricError::FileError(format!("Failed to read key directory {}: {}", dir, e)))?;
+
+    let mut paths: Vec<_> = entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.is_file() && is_private_key_file(path))
+        .collect();
+    paths.sort();
+    Ok(paths.iter().map(|path| path.to_string_lossy().into_owned()).collect())
+}
+
+fn is_private_key_file(path: &Path) -> bool {
+    fs::read_to_string(path)
+        .map(|content| content.contains("-----BEGIN") && content.contains("PRIVATE KEY-----"))
+        .unwrap_or(false)
+}
+
+fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+    !needle.is_empty() && haystack.windows(needle.len()).any(|window| window == needle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asymmetric_encryption::{AsymmetricEncryption, RsaKeyPair};
+    use crate::encryption::{RsaCipher, SecretCipher, X25519Cipher};
+    use crate::recipient_policy::RecipientPolicy;
+    use crate::x25519_encryption::{X25519Encryption, X25519KeyPair};
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_keyring_picks_key_by_id() {
+        let old = RsaKeyPair::generate(1024).unwrap();
+        let new = X25519KeyPair::generate();
+
+        let temp_dir = TempDir::new().unwrap();
+        fs::write(temp_dir.path().join("old.pem"), old.private_key_pem().unwrap()).unwrap();
+        fs::write(temp_dir.path().join("old.pub.pem"), old.public_key_pem().unwrap()).unwrap();
+        fs::write(temp_dir.path().join("new.pem"), new.private_key_pem().unwrap()).unwrap();
+        fs::write(temp_dir.path().join("README"), "rotated 2026-10").unwrap();
+
+        let keyring = Keyring::from_dir(temp_dir.path().to_str().unwrap(), None).unwrap();
+        assert_eq!(keyring.len(), 2);
+
+        let old_cipher = RsaCipher::with_recipients(
+            Some(AsymmetricEncryption::new_with_public_key(old.public_key.clone()).unwrap()),
+            RecipientPolicy::new(),
+        ).unwrap();
+        let new_cipher = X25519Cipher::with_encryption(X25519Encryption::new_with_public_key(new.public_key).unwrap());
+
+        let before = old_cipher.encrypt(b"before rotation").unwrap();
+        let after = new_cipher.encrypt(b"after rotation").unwrap();
+        assert_eq!(keyring.decrypt(&before).unwrap(), b"before rotation");
+        assert_eq!(keyring.decrypt(&after).unwrap(), b"after rotation");
+
+        // A key id the keyring does not hold is reported as such
+        let unknown = X25519KeyPair::generate();
+        let unknown_cipher = X25519Cipher::with_encryption(X25519Encryption::new_with_public_key(unknown.public_key).unwrap());
+        let err = keyring.decrypt(&unknown_cipher.encrypt(b"x").unwrap()).unwrap_err();
+        assert!(err.to_string().contains("No key in the keyring matches"));
+    }
+
+    #[test]
+    fn test_keyring_multi_recipient_and_legacy() {
+        let first = RsaKeyPair::generate(1024).unwrap();
+        let second = RsaKeyPair::generate(1024).unwrap();
+        let other = RsaKeyPair::generate(1024).unwrap();
+
+        let mut keyring = Keyring::from(AsymmetricDecryption::new_with_private_key(first.private_key.clone()));
+        keyring.add(AsymmetricDecryption::new_with_private_key(second.private_key.clone()));
+        keyring.add(AsymmetricDecryption::new_with_private_key(second.private_key));
+        assert_eq!(keyring.len(), 2);
+
+        // Header carries no key id with several recipients; the slot names the key
+        let encryption = AsymmetricEncryption::new_with_public_keys(vec![other.public_key, second.public_key.clone()]).unwrap();
+        assert_eq!(keyring.decrypt(&encryption.encrypt(b"multi").unwrap()).unwrap(), b"multi");
+
+        // Headerless chunked output is tried with every RSA key
+        let chunked = AsymmetricEncryption::new_with_public_key(second.public_key).unwrap().encrypt_chunked(b"legacy").unwrap();
+        assert_eq!(keyring.decrypt(&chunked).unwrap(), b"legacy");
+    }
+}
@@ -1,4 +1,18 @@
 pub mod encryption;
+pub mod chunked_aead;
+pub mod key_derivation;
+pub mod secure_memory;
+pub mod header;
+pub mod self_test;
 pub mod secret_fetcher;
+pub mod secret_signature;
 pub mod asymmetric_encryption;
+pub mod x25519_encryption;
+pub mod jwe;
+pub mod external_cipher;
+pub mod vault_transit;
+pub mod recipient_policy;
+pub mod pkcs11;
+pub mod keyring;
 pub mod secret_client;
+pub mod filesystem;
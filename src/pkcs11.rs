@@ -0,0 +1,1092 @@
+use std::env;
+use std::ffi::{CStr, CString, c_void};
+use std::fmt;
+use std::mem;
+use std::ptr;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard, OnceLock, Weak};
+use std::thread;
+use rsa::{BigUint, RsaPublicKey};
+use rsa::traits::PublicKeyParts;
+use zeroize::Zeroizing;
+use crate::asymmetric_encryption::{AsymmetricError, RsaPadding, read_passphrase_file};
+
+// The subset of the PKCS#11 v2.40 C API used by SecretFS. Only the types and
+// function list entries SecretFS calls are spelled out; the function list is
+// read through a pointer, so declaring a prefix of it is enough.
+
+type CkUlong = libc::c_ulong;
+type CkRv = CkUlong;
+type CkSlotId = CkUlong;
+type CkSessionHandle = CkUlong;
+type CkObjectHandle = CkUlong;
+
+const CKR_OK: CkRv = 0x0;
+const CKR_PIN_INCORRECT: CkRv = 0xa0;
+const CKR_USER_ALREADY_LOGGED_IN: CkRv = 0x100;
+const CKR_CRYPTOKI_ALREADY_INITIALIZED: CkRv = 0x191;
+
+/// Names of the return values a misconfigured token typically produces
+const RV_NAMES: &[(CkRv, &str)] = &[
+    (0x5, "CKR_GENERAL_ERROR"),
+    (0x6, "CKR_FUNCTION_FAILED"),
+    (0x7, "CKR_ARGUMENTS_BAD"),
+    (0x12, "CKR_ATTRIBUTE_TYPE_INVALID"),
+    (0x30, "CKR_DEVICE_ERROR"),
+    (0x40, "CKR_ENCRYPTED_DATA_INVALID"),
+    (0x41, "CKR_ENCRYPTED_DATA_LEN_RANGE"),
+    (0x60, "CKR_KEY_HANDLE_INVALID"),
+    (0x68, "CKR_KEY_FUNCTION_NOT_PERMITTED"),
+    (0x70, "CKR_MECHANISM_INVALID"),
+    (0x71, "CKR_MECHANISM_PARAM_INVALID"),
+    (CKR_PIN_INCORRECT, "CKR_PIN_INCORRECT"),
+    (0xa2, "CKR_PIN_LEN_RANGE"),
+    (0xa4, "CKR_PIN_LOCKED"),
+    (0xb3, "CKR_SESSION_HANDLE_INVALID"),
+    (0xe0, "CKR_TOKEN_NOT_PRESENT"),
+    (CKR_USER_ALREADY_LOGGED_IN, "CKR_USER_ALREADY_LOGGED_IN"),
+    (0x101, "CKR_USER_NOT_LOGGED_IN"),
+    (0x102, "CKR_USER_PIN_NOT_INITIALIZED"),
+    (0x150, "CKR_BUFFER_TOO_SMALL"),
+    (0x190, "CKR_CRYPTOKI_NOT_INITIALIZED"),
+    (CKR_CRYPTOKI_ALREADY_INITIALIZED, "CKR_CRYPTOKI_ALREADY_INITIALIZED"),
+];
+
+const CKF_OS_LOCKING_OK: CkUlong = 0x2;
+const CKF_SERIAL_SESSION: CkUlong = 0x4;
+const CKU_USER: CkUlong = 1;
+
+const CKA_CLASS: CkUlong = 0x0;
+const CKA_LABEL: CkUlong = 0x3;
+const CKA_KEY_TYPE: CkUlong = 0x100;
+const CKA_MODULUS: CkUlong = 0x120;
+const CKA_PUBLIC_EXPONENT: CkUlong = 0x122;
+
+const CKO_PUBLIC_KEY: CkUlong = 2;
+const CKO_PRIVATE_KEY: CkUlong = 3;
+const CKK_RSA: CkUlong = 0;
+
+const CKM_RSA_PKCS: CkUlong = 0x1;
+const CKM_RSA_PKCS_OAEP: CkUlong = 0x9;
+const CKM_SHA256: CkUlong = 0x250;
+const CKG_MGF1_SHA256: CkUlong = 0x2;
+const CKZ_DATA_SPECIFIED: CkUlong = 0x1;
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+struct CkVersion {
+    major: u8,
+    minor: u8,
+}
+
+#[repr(C)]
+struct CkInitializeArgs {
+    create_mutex: *mut c_void,
+    destroy_mutex: *mut c_void,
+    lock_mutex: *mut c_void,
+    unlock_mutex: *mut c_void,
+    flags: CkUlong,
+    reserved: *mut c_void,
+}
+
+#[repr(C)]
+struct CkTokenInfo {
+    label: [u8; 32],
+    manufacturer_id: [u8; 32],
+    model: [u8; 16],
+    serial_number: [u8; 16],
+    flags: CkUlong,
+    max_session_count: CkUlong,
+    session_count: CkUlong,
+    max_rw_session_count: CkUlong,
+    rw_session_count: CkUlong,
+    max_pin_len: CkUlong,
+    min_pin_len: CkUlong,
+    total_public_memory: CkUlong,
+    free_public_memory: CkUlong,
+    total_private_memory: CkUlong,
+    free_private_memory: CkUlong,
+    hardware_version: CkVersion,
+    firmware_version: CkVersion,
+    utc_time: [u8; 16],
+}
+
+#[repr(C)]
+struct CkAttribute {
+    attribute_type: CkUlong,
+    value: *mut c_void,
+    value_len: CkUlong,
+}
+
+#[repr(C)]
+struct CkMechanism {
+    mechanism: CkUlong,
+    parameter: *mut c_void,
+    parameter_len: CkUlong,
+}
+
+#[repr(C)]
+struct CkRsaPkcsOaepParams {
+    hash_alg: CkUlong,
+    mgf: CkUlong,
+    source: CkUlong,
+    source_data: *mut c_void,
+    source_data_len: CkUlong,
+}
+
+/// Entry of the function list SecretFS does not call
+type Unused = Option<unsafe extern "C" fn()>;
+
+/// The first 35 entries of `CK_FUNCTION_LIST`, up to `C_Decrypt`
+#[repr(C)]
+struct CkFunctionList {
+    version: CkVersion,
+    initialize: Option<unsafe extern "C" fn(*mut c_void) -> CkRv>,
+    finalize: Option<unsafe extern "C" fn(*mut c_void) -> CkRv>,
+    get_info: Unused,
+    get_function_list: Unused,
+    get_slot_list: Option<unsafe extern "C" fn(u8, *mut CkSlotId, *mut CkUlong) -> CkRv>,
+    get_slot_info: Unused,
+    get_token_info: Option<unsafe extern "C" fn(CkSlotId, *mut CkTokenInfo) -> CkRv>,
+    get_mechanism_list: Unused,
+    get_mechanism_info: Unused,
+    init_token: Unused,
+    init_pin: Unused,
+    set_pin: Unused,
+    open_session: Option<unsafe extern "C" fn(CkSlotId, CkUlong, *mut c_void, *mut c_void, *mut CkSessionHandle) -> CkRv>,
+    close_session: Option<unsafe extern "C" fn(CkSessionHandle) -> CkRv>,
+    close_all_sessions: Unused,
+    get_session_info: Unused,
+    get_operation_state: Unused,
+    set_operation_state: Unused,
+    login: Option<unsafe extern "C" fn(CkSessionHandle, CkUlong, *const u8, CkUlong) -> CkRv>,
+    logout: Option<unsafe extern "C" fn(CkSessionHandle) -> CkRv>,
+    create_object: Unused,
+    copy_object: Unused,
+    destroy_object: Unused,
+    get_object_size: Unused,
+    get_attribute_value: Option<unsafe extern "C" fn(CkSessionHandle, CkObjectHandle, *mut CkAttribute, CkUlong) -> CkRv>,
+    set_attribute_value: Unused,
+    find_objects_init: Option<unsafe extern "C" fn(CkSessionHandle, *mut CkAttribute, CkUlong) -> CkRv>,
+    find_objects: Option<unsafe extern "C" fn(CkSessionHandle, *mut CkObjectHandle, CkUlong, *mut CkUlong) -> CkRv>,
+    find_objects_final: Option<unsafe extern "C" fn(CkSessionHandle) -> CkRv>,
+    encrypt_init: Unused,
+    encrypt: Unused,
+    encrypt_update: Unused,
+    encrypt_final: Unused,
+    decrypt_init: Option<unsafe extern "C" fn(CkSessionHandle, *mut CkMechanism, CkObjectHandle) -> CkRv>,
+    decrypt: Option<unsafe extern "C" fn(CkSessionHandle, *const u8, CkUlong, *mut u8, *mut CkUlong) -> CkRv>,
+}
+
+type GetFunctionList = unsafe extern "C" fn(*mut *const CkFunctionList) -> CkRv;
+
+/// Name of a PKCS#11 return value for error messages
+fn rv_name(rv: CkRv) -> String {
+    match RV_NAMES.iter().find(|(value, _)| *value == rv) {
+        Some((_, name)) => format!("{} (0x{:x})", name, rv),
+        None => format!("CKR 0x{:x}", rv),
+    }
+}
+
+fn check(rv: CkRv, operation: &str) -> Result<(), AsymmetricError> {
+    if rv == CKR_OK {
+        Ok(())
+    } else {
+        Err(AsymmetricError::Pkcs11Error(format!("{} failed: {}", operation, rv_name(rv))))
+    }
+}
+
+/// Where the RSA key is: module, token and key label, and the user PIN
+pub struct Pkcs11Config {
+    /// Path of the PKCS#11 module, e.g. `/usr/lib/softhsm/libsofthsm2.so`
+    pub module: String,
+    /// Slot holding the token; when unset the token is found by label
+    pub slot: Option<u64>,
+    /// Label of the token; when neither slot nor label is set, the only token present is used
+    pub token_label: Option<String>,
+    /// `CKA_LABEL` of the RSA key
+    pub key_label: String,
+    /// User PIN, zeroized on drop; `None` for tokens without login
+    pub pin: Option<Zeroizing<Vec<u8>>>,
+}
+
+impl Pkcs11Config {
+    pub fn new(module: &str, key_label: &str) -> Self {
+        Self {
+            module: module.to_string(),
+            slot: None,
+            token_label: None,
+            key_label: key_label.to_string(),
+            pin: None,
+        }
+    }
+
+    pub fn with_slot(mut self, slot: u64) -> Self {
+        self.slot = Some(slot);
+        self
+    }
+
+    pub fn with_token_label(mut self, token_label: &str) -> Self {
+        self.token_label = Some(token_label.to_string());
+        self
+    }
+
+    pub fn with_pin(mut self, pin: &[u8]) -> Self {
+        self.pin = Some(Zeroizing::new(pin.to_vec()));
+        self
+    }
+
+    /// Read the configuration from environment variables
+    ///
+    /// - `SECRETFS_PKCS11_MODULE`: path of the PKCS#11 module
+    /// - `SECRETFS_PKCS11_SLOT` or `SECRETFS_PKCS11_TOKEN_LABEL`: the token
+    /// - `SECRETFS_PKCS11_KEY_LABEL`: label of the RSA key on the token
+    /// - `SECRETFS_PKCS11_PIN_FILE`: file holding the user PIN
+    pub fn from_env() -> Result<Self, AsymmetricError> {
+        let module = env::var("SECRETFS_PKCS11_MODULE")
+            .map_err(|_| AsymmetricError::ConfigurationError("SECRETFS_PKCS11_MODULE is not set".to_string()))?;
+        let key_label = env::var("SECRETFS_PKCS11_KEY_LABEL")
+            .map_err(|_| AsymmetricError::ConfigurationError("SECRETFS_PKCS11_KEY_LABEL is not set".to_string()))?;
+
+        let mut config = Self::new(&module, &key_label);
+        if let Ok(slot) = env::var("SECRETFS_PKCS11_SLOT") {
+            config.slot = Some(slot.trim().parse().map_err(|_| AsymmetricError::ConfigurationError(
+                format!("SECRETFS_PKCS11_SLOT must be a slot number, got '{}'", slot)
+            ))?);
+        }
+        if let Ok(token_label) = env::var("SECRETFS_PKCS11_TOKEN_LABEL") {
+            config.token_label = Some(token_label);
+        }
+        if let Ok(pin_path) = env::var("SECRETFS_PKCS11_PIN_FILE") {
+            config.pin = Some(read_passphrase_file(&pin_path)?);
+        }
+        Ok(config)
+    }
+
+    /// Read the public key from the token without logging in
+    ///
+    /// Encrypting needs only the `CKO_PUBLIC_KEY` object, which tokens show
+    /// to sessions without login, so hosts that only encrypt need no PIN.
+    pub fn read_public_key(&self) -> Result<RsaPublicKey, AsymmetricError> {
+        self.read_public_key_from(Pkcs11Module::load(&self.module)?)
+    }
+
+    fn read_public_key_from(&self, module: Arc<Pkcs11Module>) -> Result<RsaPublicKey, AsymmetricError> {
+        let (slot, token_label) = module.select_slot(self)?;
+        let session = Pkcs11Session::open(module, slot, &token_label, None)?;
+        match session.find_rsa_keys(CKO_PUBLIC_KEY, &self.key_label)?.as_slice() {
+            [public_key] => session.rsa_public_key(*public_key),
+            [] => Err(AsymmetricError::KeyLoadError(format!(
+                "No RSA public key labelled '{}' on token '{}'", self.key_label, token_label
+            ))),
+            _ => Err(AsymmetricError::KeyLoadError(format!(
+                "Several RSA public keys labelled '{}' on token '{}'", self.key_label, token_label
+            ))),
+        }
+    }
+}
+
+/// Whether a PKCS#11 module is configured in the environment
+pub fn pkcs11_configured() -> bool {
+    env::var_os("SECRETFS_PKCS11_MODULE").is_some()
+}
+
+/// A loaded and initialized PKCS#11 module
+///
+/// `C_Initialize` and `C_Finalize` are process-wide, so every key of the
+/// process shares one module per library path: it is finalized and the
+/// library unloaded only when the last key using it is dropped. Logins hold
+/// for the whole token too, so the module counts the keys using each one.
+pub struct Pkcs11Module {
+    path: String,
+    library: *mut c_void,
+    functions: *const CkFunctionList,
+    finalize: bool,
+    shared: bool,
+    logins: Mutex<HashMap<CkSlotId, Login>>,
+}
+
+/// A login to a token and the sessions relying on it
+struct Login {
+    sessions: usize,
+    pin: Zeroizing<Vec<u8>>,
+}
+
+/// The modules in use, by library path
+fn loaded_modules() -> MutexGuard<'static, HashMap<String, Weak<Pkcs11Module>>> {
+    static MODULES: OnceLock<Mutex<HashMap<String, Weak<Pkcs11Module>>>> = OnceLock::new();
+    MODULES.get_or_init(Default::default).lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+// The module is initialized with CKF_OS_LOCKING_OK, so it may be called from
+// any thread; each session is serialized by its own lock
+unsafe impl Send for Pkcs11Module {}
+unsafe impl Sync for Pkcs11Module {}
+
+impl Pkcs11Module {
+    /// The module at `path`, loaded and initialized unless it already is
+    pub fn load(path: &str) -> Result<Arc<Self>, AsymmetricError> {
+        Self::shared(path, || Self::load_library(path))
+    }
+
+    /// The module in use under `name`, or a new one from `load`
+    fn shared(name: &str, load: impl FnOnce() -> Result<Self, AsymmetricError>) -> Result<Arc<Self>, AsymmetricError> {
+        loop {
+            let mut modules = loaded_modules();
+            match modules.get(name).map(Weak::upgrade) {
+                Some(Some(module)) => return Ok(module),
+                // The last user is dropping it; wait until it is finalized
+                Some(None) => {
+                    drop(modules);
+                    thread::yield_now();
+                },
+                None => {
+                    let mut module = load()?;
+                    module.shared = true;
+                    let module = Arc::new(module);
+                    modules.insert(name.to_string(), Arc::downgrade(&module));
+                    return Ok(module);
+                },
+            }
+        }
+    }
+
+    fn load_library(path: &str) -> Result<Self, AsymmetricError> {
+        let c_path = CString::new(path)
+            .map_err(|_| AsymmetricError::ConfigurationError(format!("Invalid PKCS#11 module path {}", path)))?;
+
+        let library = unsafe { libc::dlopen(c_path.as_ptr(), libc::RTLD_NOW | libc::RTLD_LOCAL) };
+        if library.is_null() {
+            return Err(AsymmetricError::Pkcs11Error(format!("Failed to load PKCS#11 module {}: {}", path, dl_error())));
+        }
+
+        let symbol = unsafe { libc::dlsym(library, c"C_GetFunctionList".as_ptr()) };
+        if symbol.is_null() {
+            unsafe { libc::dlclose(library) };
+            return Err(AsymmetricError::Pkcs11Error(format!("{} is not a PKCS#11 module (no C_GetFunctionList)", path)));
+        }
+        let get_function_list: GetFunctionList = unsafe { mem::transmute::<*mut c_void, GetFunctionList>(symbol) };
+
+        let mut functions = ptr::null();
+        let rv = unsafe { get_function_list(&mut functions) };
+        if rv != CKR_OK || functions.is_null() {
+            unsafe { libc::dlclose(library) };
+            return Err(AsymmetricError::Pkcs11Error(format!("C_GetFunctionList of {} failed: {}", path, rv_name(rv))));
+        }
+
+        let mut module = Self { path: path.to_string(), library, functions, finalize: false, shared: false, logins: Mutex::default() };
+        module.initialize()?;
+        Ok(module)
+    }
+
+    /// Use a function list that is not loaded from a library
+    ///
+    /// # Safety
+    ///
+    /// `functions` must point to a valid function list that outlives the module.
+    #[cfg(test)]
+    unsafe fn from_function_list(name: &str, functions: *const CkFunctionList) -> Result<Self, AsymmetricError> {
+        let mut module = Self { path: name.to_string(), library: ptr::null_mut(), functions, finalize: false, shared: false, logins: Mutex::default() };
+        module.initialize()?;
+        Ok(module)
+    }
+
+    fn initialize(&mut self) -> Result<(), AsymmetricError> {
+        let mut args = CkInitializeArgs {
+            create_mutex: ptr::null_mut(),
+            destroy_mutex: ptr::null_mut(),
+            lock_mutex: ptr::null_mut(),
+            unlock_mutex: ptr::null_mut(),
+            flags: CKF_OS_LOCKING_OK,
+            reserved: ptr::null_mut(),
+        };
+        let initialize = self.function(self.functions().initialize, "C_Initialize")?;
+        match unsafe { initialize((&mut args as *mut CkInitializeArgs).cast()) } {
+            CKR_OK => self.finalize = true,
+            // Someone else in this process initialized it and will finalize it
+            CKR_CRYPTOKI_ALREADY_INITIALIZED => {},
+            rv => return check(rv, "C_Initialize"),
+        }
+        Ok(())
+    }
+
+    /// Log in to the token in `slot` through `session`, unless already logged in with `pin`
+    ///
+    /// A token is logged in for every session of the process, so a second key
+    /// reuses the login of the first; a different PIN is refused, as it could
+    /// not be checked. The login ends when the last session using it logs out.
+    fn login(&self, session: CkSessionHandle, slot: CkSlotId, token_label: &str, pin: &[u8]) -> Result<(), AsymmetricError> {
+        let mut logins = self.logins.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        if let Some(login) = logins.get_mut(&slot) {
+            if login.pin.as_slice() != pin {
+                return Err(AsymmetricError::Pkcs11Error(format!(
+                    "Token '{}' is already logged in with a different PIN", token_label
+                )));
+            }
+            login.sessions += 1;
+            return Ok(());
+        }
+
+        let login = self.function(self.functions().login, "C_Login")?;
+        match unsafe { login(session, CKU_USER, pin.as_ptr(), pin.len() as CkUlong) } {
+            CKR_OK => {},
+            // Logged in by someone else in this process, the PIN was not checked
+            CKR_USER_ALREADY_LOGGED_IN => return Err(AsymmetricError::Pkcs11Error(format!(
+                "Token '{}' is already logged in outside SecretFS, so the PIN cannot be checked", token_label
+            ))),
+            rv => return Err(AsymmetricError::Pkcs11Error(format!("Login to token '{}' failed: {}", token_label, rv_name(rv)))),
+        }
+        logins.insert(slot, Login { sessions: 1, pin: Zeroizing::new(pin.to_vec()) });
+        Ok(())
+    }
+
+    /// Release the login to `slot` of one session, logging out after the last
+    fn logout(&self, session: CkSessionHandle, slot: CkSlotId) {
+        let mut logins = self.logins.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let Some(login) = logins.get_mut(&slot) else { return };
+        login.sessions -= 1;
+        if login.sessions == 0 {
+            logins.remove(&slot);
+            if let Some(logout) = self.functions().logout {
+                unsafe { logout(session) };
+            }
+        }
+    }
+
+    fn functions(&self) -> &CkFunctionList {
+        unsafe { &*self.functions }
+    }
+
+    fn function<F>(&self, function: Option<F>, name: &str) -> Result<F, AsymmetricError> {
+        function.ok_or_else(|| AsymmetricError::Pkcs11Error(format!("{} does not implement {}", self.path, name)))
+    }
+
+    /// Slots with a token present
+    fn slots(&self) -> Result<Vec<CkSlotId>, AsymmetricError> {
+        let get_slot_list = self.function(self.functions().get_slot_list, "C_GetSlotList")?;
+        let mut count: CkUlong = 0;
+        check(unsafe { get_slot_list(1, ptr::null_mut(), &mut count) }, "C_GetSlotList")?;
+        let mut slots = vec![0; count as usize];
+        check(unsafe { get_slot_list(1, slots.as_mut_ptr(), &mut count) }, "C_GetSlotList")?;
+        slots.truncate(count as usize);
+        Ok(slots)
+    }
+
+    /// Label of the token in `slot`, without its blank padding
+    fn token_label(&self, slot: CkSlotId) -> Result<String, AsymmetricError> {
+        let get_token_info = self.function(self.functions().get_token_info, "C_GetTokenInfo")?;
+        let mut info: CkTokenInfo = unsafe { mem::zeroed() };
+        check(unsafe { get_token_info(slot, &mut info) }, "C_GetTokenInfo")?;
+        Ok(String::from_utf8_lossy(&info.label).trim_end_matches([' ', '\0']).to_string())
+    }
+
+    /// Find the slot `config` names
+    fn select_slot(&self, config: &Pkcs11Config) -> Result<(CkSlotId, String), AsymmetricError> {
+        let slots = self.slots()?;
+        if let Some(slot) = config.slot.map(|slot| slot as CkSlotId) {
+            if !slots.contains(&slot) {
+                return Err(AsymmetricError::Pkcs11Error(format!("No token present in slot {} of {}", slot, self.path)));
+            }
+            return Ok((slot, self.token_label(slot)?));
+        }
+
+        let mut tokens = Vec::new();
+        for slot in slots {
+            tokens.push((slot, self.token_label(slot)?));
+        }
+        match &config.token_label {
+            Some(label) => tokens.into_iter()
+                .find(|(_, token_label)| token_label == label)
+                .ok_or_else(|| AsymmetricError::Pkcs11Error(format!("No token labelled '{}' in {}", label, self.path))),
+            None if tokens.len() == 1 => Ok(tokens.remove(0)),
+            None if tokens.is_empty() => Err(AsymmetricError::Pkcs11Error(format!("No token present in {}", self.path))),
+            None => Err(AsymmetricError::ConfigurationError(format!(
+                "{} has {} tokens; set SECRETFS_PKCS11_SLOT or SECRETFS_PKCS11_TOKEN_LABEL", self.path, tokens.len()
+            ))),
+        }
+    }
+}
+
+impl Drop for Pkcs11Module {
+    fn drop(&mut self) {
+        // Hold the registry until finalized, so `load` cannot initialize it again meanwhile
+        let modules = self.shared.then(loaded_modules);
+        if self.finalize {
+            if let Some(finalize) = self.functions().finalize {
+                unsafe { finalize(ptr::null_mut()) };
+            }
+        }
+        if !self.library.is_null() {
+            unsafe { libc::dlclose(self.library) };
+        }
+        if let Some(mut modules) = modules {
+            modules.remove(&self.path);
+        }
+    }
+}
+
+impl fmt::Debug for Pkcs11Module {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Pkcs11Module").field("path", &self.path).finish()
+    }
+}
+
+fn dl_error() -> String {
+    let error = unsafe { libc::dlerror() };
+    if error.is_null() {
+        "unknown error".to_string()
+    } else {
+        unsafe { CStr::from_ptr(error) }.to_string_lossy().into_owned()
+    }
+}
+
+/// A read-only session on a token, closed on drop
+///
+/// PKCS#11 sessions must not be used from two threads at once, so every
+/// operation holds the handle's lock.
+struct Pkcs11Session {
+    module: Arc<Pkcs11Module>,
+    handle: Mutex<CkSessionHandle>,
+    /// Slot of the token whose login the session relies on
+    login: Option<CkSlotId>,
+}
+
+impl Pkcs11Session {
+    fn open(module: Arc<Pkcs11Module>, slot: CkSlotId, token_label: &str, pin: Option<&[u8]>) -> Result<Self, AsymmetricError> {
+        let open_session = module.function(module.functions().open_session, "C_OpenSession")?;
+        let mut handle = 0;
+        check(unsafe { open_session(slot, CKF_SERIAL_SESSION, ptr::null_mut(), ptr::null_mut(), &mut handle) }, "C_OpenSession")?;
+        let mut session = Self { module, handle: Mutex::new(handle), login: None };
+
+        if let Some(pin) = pin {
+            session.module.login(handle, slot, token_label, pin)?;
+            session.login = Some(slot);
+        }
+        Ok(session)
+    }
+
+    fn lock(&self) -> MutexGuard<'_, CkSessionHandle> {
+        self.handle.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Handles of the RSA keys of `class` labelled `label` (at most two)
+    fn find_rsa_keys(&self, class: CkUlong, label: &str) -> Result<Vec<CkObjectHandle>, AsymmetricError> {
+        let functions = self.module.functions();
+        let find_objects_init = self.module.function(functions.find_objects_init, "C_FindObjectsInit")?;
+        let find_objects = self.module.function(functions.find_objects, "C_FindObjects")?;
+        let find_objects_final = self.module.function(functions.find_objects_final, "C_FindObjectsFinal")?;
+
+        let mut class = class;
+        let mut key_type = CKK_RSA;
+        let mut label = label.as_bytes().to_vec();
+        let mut template = [
+            attribute(CKA_CLASS, (&mut class as *mut CkUlong).cast(), mem::size_of::<CkUlong>()),
+            attribute(CKA_KEY_TYPE, (&mut key_type as *mut CkUlong).cast(), mem::size_of::<CkUlong>()),
+            attribute(CKA_LABEL, label.as_mut_ptr().cast(), label.len()),
+        ];
+
+        let session = self.lock();
+        check(unsafe { find_objects_init(*session, template.as_mut_ptr(), template.len() as CkUlong) }, "C_FindObjectsInit")?;
+        let mut handles = vec![0; 2];
+        let mut count = 0;
+        let found = check(unsafe { find_objects(*session, handles.as_mut_ptr(), handles.len() as CkUlong, &mut count) }, "C_FindObjects");
+        check(unsafe { find_objects_final(*session) }, "C_FindObjectsFinal")?;
+        found?;
+
+        handles.truncate(count as usize);
+        Ok(handles)
+    }
+
+    fn attribute_value(&self, object: CkObjectHandle, attribute_type: CkUlong, name: &str) -> Result<Vec<u8>, AsymmetricError> {
+        let get_attribute_value = self.module.function(self.module.functions().get_attribute_value, "C_GetAttributeValue")?;
+        let session = self.lock();
+
+        // First call for the length, second for the value
+        let mut template = [attribute(attribute_type, ptr::null_mut(), 0)];
+        check(unsafe { get_attribute_value(*session, object, template.as_mut_ptr(), 1) }, &format!("Reading {}", name))?;
+        let mut value = vec![0u8; template[0].value_len as usize];
+        template[0].value = value.as_mut_ptr().cast();
+        check(unsafe { get_attribute_value(*session, object, template.as_mut_ptr(), 1) }, &format!("Reading {}", name))?;
+        value.truncate(template[0].value_len as usize);
+        Ok(value)
+    }
+
+    fn rsa_public_key(&self, object: CkObjectHandle) -> Result<RsaPublicKey, AsymmetricError> {
+        let modulus = self.attribute_value(object, CKA_MODULUS, "CKA_MODULUS")?;
+        let exponent = self.attribute_value(object, CKA_PUBLIC_EXPONENT, "CKA_PUBLIC_EXPONENT")?;
+        RsaPublicKey::new(BigUint::from_bytes_be(&modulus), BigUint::from_bytes_be(&exponent))
+            .map_err(|e| AsymmetricError::InvalidKeyFormat(format!("Not a valid RSA key: {}", e)))
+    }
+
+    /// Decrypt `block` with `key` into a buffer of `max_len` bytes
+    fn decrypt(&self, mechanism: &mut CkMechanism, key: CkObjectHandle, block: &[u8], max_len: usize) -> Result<Vec<u8>, AsymmetricError> {
+        let functions = self.module.functions();
+        let decrypt_init = self.module.function(functions.decrypt_init, "C_DecryptInit")?;
+        let decrypt = self.module.function(functions.decrypt, "C_Decrypt")?;
+
+        let mut plaintext = Zeroizing::new(vec![0u8; max_len]);
+        let mut plaintext_len = plaintext.len() as CkUlong;
+
+        let session = self.lock();
+        check(unsafe { decrypt_init(*session, mechanism, key) }, "C_DecryptInit")?;
+        check(unsafe { decrypt(*session, block.as_ptr(), block.len() as CkUlong, plaintext.as_mut_ptr(), &mut plaintext_len) }, "C_Decrypt")?;
+        drop(session);
+
+        plaintext.truncate(plaintext_len as usize);
+        Ok(mem::take(&mut *plaintext))
+    }
+}
+
+impl Drop for Pkcs11Session {
+    fn drop(&mut self) {
+        let handle = *self.lock();
+        if let Some(slot) = self.login {
+            self.module.logout(handle, slot);
+        }
+        if let Some(close_session) = self.module.functions().close_session {
+            unsafe { close_session(handle) };
+        }
+    }
+}
+
+/// An RSA private key on a PKCS#11 token
+///
+/// The private key never leaves the token: RSA decryption runs there with
+/// `CKM_RSA_PKCS_OAEP` (SHA-256, MGF1-SHA256) or `CKM_RSA_PKCS`. The public
+/// key is read from the token once, so secrets can be encrypted to it
+/// without any PEM files. The token stays logged in until the last key on
+/// it is dropped; keys on one token must be opened with the same PIN.
+pub struct Pkcs11RsaKey {
+    session: Pkcs11Session,
+    private_key: CkObjectHandle,
+    public_key: RsaPublicKey,
+    token_label: String,
+    key_label: String,
+}
+
+impl Pkcs11RsaKey {
+    /// Load the module, log in to the token and find the key `config` names
+    pub fn open(config: &Pkcs11Config) -> Result<Self, AsymmetricError> {
+        Self::open_with_module(Pkcs11Module::load(&config.module)?, config)
+    }
+
+    /// Log in to a token of an already loaded module and find the key
+    pub fn open_with_module(module: Arc<Pkcs11Module>, config: &Pkcs11Config) -> Result<Self, AsymmetricError> {
+        let (slot, token_label) = module.select_slot(config)?;
+        let session = Pkcs11Session::open(module, slot, &token_label, config.pin.as_deref().map(Vec::as_slice))?;
+        let key_label = &config.key_label;
+
+        let private_key = match session.find_rsa_keys(CKO_PRIVATE_KEY, key_label)?.as_slice() {
+            [private_key] => *private_key,
+            [] => return Err(AsymmetricError::KeyLoadError(format!(
+                "No RSA private key labelled '{}' on token '{}'{}", key_label, token_label,
+                if config.pin.is_none() { " (private keys are only visible after login, set SECRETFS_PKCS11_PIN_FILE)" } else { "" }
+            ))),
+            _ => return Err(AsymmetricError::KeyLoadError(format!(
+                "Several RSA private keys labelled '{}' on token '{}'", key_label, token_label
+            ))),
+        };
+
+        // Most tokens expose the public parts on the private key, others only on the public key object
+        let public_key = match session.rsa_public_key(private_key) {
+            Ok(public_key) => public_key,
+            Err(e) => match session.find_rsa_keys(CKO_PUBLIC_KEY, key_label)?.first() {
+                Some(public_key) => session.rsa_public_key(*public_key)?,
+                None => return Err(AsymmetricError::KeyLoadError(format!(
+                    "Cannot read the public key of '{}' on token '{}': {}", key_label, token_label, e
+                ))),
+            },
+        };
+
+        Ok(Self { session, private_key, public_key, token_label, key_label: key_label.clone() })
+    }
+
+    /// Create from the configuration in the environment, see `Pkcs11Config::from_env`
+    pub fn from_env() -> Result<Self, AsymmetricError> {
+        Self::open(&Pkcs11Config::from_env()?)
+    }
+
+    /// The public key matching the private key on the token
+    pub fn public_key(&self) -> &RsaPublicKey {
+        &self.public_key
+    }
+
+    pub fn token_label(&self) -> &str {
+        &self.token_label
+    }
+
+    pub fn key_label(&self) -> &str {
+        &self.key_label
+    }
+
+    /// Modulus size in bytes
+    pub fn size(&self) -> usize {
+        self.public_key.size()
+    }
+
+    /// Decrypt one RSA block on the token
+    pub fn decrypt_block(&self, padding: RsaPadding, block: &[u8]) -> Result<Vec<u8>, AsymmetricError> {
+        let mut oaep_params = CkRsaPkcsOaepParams {
+            hash_alg: CKM_SHA256,
+            mgf: CKG_MGF1_SHA256,
+            source: CKZ_DATA_SPECIFIED,
+            source_data: ptr::null_mut(),
+            source_data_len: 0,
+        };
+        let mut mechanism = match padding {
+            RsaPadding::OaepSha256 => CkMechanism {
+                mechanism: CKM_RSA_PKCS_OAEP,
+                parameter: (&mut oaep_params as *mut CkRsaPkcsOaepParams).cast(),
+                parameter_len: mem::size_of::<CkRsaPkcsOaepParams>() as CkUlong,
+            },
+            RsaPadding::Pkcs1v15 => CkMechanism { mechanism: CKM_RSA_PKCS, parameter: ptr::null_mut(), parameter_len: 0 },
+        };
+
+        self.session.decrypt(&mut mechanism, self.private_key, block, self.size())
+            .map_err(|e| AsymmetricError::DecryptionError(format!(
+                "RSA {} decryption on token '{}' failed: {}", padding.name(), self.token_label, e
+            )))
+    }
+}
+
+impl fmt::Debug for Pkcs11RsaKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Pkcs11RsaKey")
+            .field("module", &self.session.module)
+            .field("token_label", &self.token_label)
+            .field("key_label", &self.key_label)
+            .finish()
+    }
+}
+
+fn attribute(attribute_type: CkUlong, value: *mut c_void, value_len: usize) -> CkAttribute {
+    CkAttribute { attribute_type, value, value_len: value_len as CkUlong }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+    use rsa::{Oaep, Pkcs1v15Encrypt, RsaPrivateKey};
+    use sha2::Sha256;
+    use crate::asymmetric_encryption::{AsymmetricDecryption, AsymmetricEncryption};
+
+    // A one-slot token holding one RSA key pair labelled "app-key", user PIN
+    // "1234". Like many HSMs it exposes the modulus on the public key only.
+    // As in PKCS#11, a login holds for every session on the token; tests using
+    // it take `token()` so they do not see each other's logins.
+
+    const SLOT: CkSlotId = 7;
+    const PRIVATE_KEY: CkObjectHandle = 100;
+    const PUBLIC_KEY: CkObjectHandle = 200;
+    const CKR_ATTRIBUTE_TYPE_INVALID: CkRv = 0x12;
+    const CKR_MECHANISM_INVALID: CkRv = 0x70;
+    const CKR_USER_NOT_LOGGED_IN: CkRv = 0x101;
+    const CKR_CRYPTOKI_NOT_INITIALIZED: CkRv = 0x190;
+
+    static INITIALIZED: AtomicBool = AtomicBool::new(false);
+    static LOGGED_IN: AtomicBool = AtomicBool::new(false);
+
+    fn token() -> MutexGuard<'static, ()> {
+        static TOKEN: Mutex<()> = Mutex::new(());
+        TOKEN.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    #[derive(Default)]
+    struct Session {
+        found: Vec<CkObjectHandle>,
+        mechanism: Option<CkUlong>,
+    }
+
+    fn mock_key() -> &'static RsaPrivateKey {
+        static KEY: OnceLock<RsaPrivateKey> = OnceLock::new();
+        KEY.get_or_init(|| RsaPrivateKey::new(&mut rand::rngs::OsRng, 1024).unwrap())
+    }
+
+    fn sessions() -> MutexGuard<'static, HashMap<CkSessionHandle, Session>> {
+        static SESSIONS: OnceLock<Mutex<HashMap<CkSessionHandle, Session>>> = OnceLock::new();
+        SESSIONS.get_or_init(Default::default).lock().unwrap()
+    }
+
+    unsafe fn attribute_value(template: &CkAttribute) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(template.value.cast::<u8>(), template.value_len as usize) }
+    }
+
+    unsafe extern "C" fn initialize(args: *mut c_void) -> CkRv {
+        let args = unsafe { &*args.cast::<CkInitializeArgs>() };
+        if args.flags & CKF_OS_LOCKING_OK == 0 {
+            0x7
+        } else if INITIALIZED.swap(true, Ordering::SeqCst) {
+            CKR_CRYPTOKI_ALREADY_INITIALIZED
+        } else {
+            CKR_OK
+        }
+    }
+
+    unsafe extern "C" fn finalize(_reserved: *mut c_void) -> CkRv {
+        INITIALIZED.store(false, Ordering::SeqCst);
+        LOGGED_IN.store(false, Ordering::SeqCst);
+        CKR_OK
+    }
+
+    unsafe extern "C" fn get_slot_list(_token_present: u8, slots: *mut CkSlotId, count: *mut CkUlong) -> CkRv {
+        unsafe {
+            if !slots.is_null() {
+                *slots = SLOT;
+            }
+            *count = 1;
+        }
+        CKR_OK
+    }
+
+    unsafe extern "C" fn get_token_info(slot: CkSlotId, info: *mut CkTokenInfo) -> CkRv {
+        assert_eq!(slot, SLOT);
+        let info = unsafe { &mut *info };
+        info.label = [b' '; 32];
+        info.label[..13].copy_from_slice(b"secretfs-test");
+        CKR_OK
+    }
+
+    unsafe extern "C" fn open_session(slot: CkSlotId, flags: CkUlong, _: *mut c_void, _: *mut c_void, session: *mut CkSessionHandle) -> CkRv {
+        static NEXT_SESSION: AtomicU64 = AtomicU64::new(1);
+        assert_eq!((slot, flags & CKF_SERIAL_SESSION), (SLOT, CKF_SERIAL_SESSION));
+        if !INITIALIZED.load(Ordering::SeqCst) {
+            return CKR_CRYPTOKI_NOT_INITIALIZED;
+        }
+        let handle = NEXT_SESSION.fetch_add(1, Ordering::Relaxed) as CkSessionHandle;
+        sessions().insert(handle, Session::default());
+        unsafe { *session = handle };
+        CKR_OK
+    }
+
+    unsafe extern "C" fn close_session(session: CkSessionHandle) -> CkRv {
+        let mut sessions = sessions();
+        sessions.remove(&session);
+        // Closing the last session logs out
+        if sessions.is_empty() {
+            LOGGED_IN.store(false, Ordering::SeqCst);
+        }
+        CKR_OK
+    }
+
+    unsafe extern "C" fn login(session: CkSessionHandle, user: CkUlong, pin: *const u8, pin_len: CkUlong) -> CkRv {
+        assert_eq!(user, CKU_USER);
+        assert!(sessions().contains_key(&session));
+        if LOGGED_IN.load(Ordering::SeqCst) {
+            return CKR_USER_ALREADY_LOGGED_IN;
+        }
+        if unsafe { std::slice::from_raw_parts(pin, pin_len as usize) } != b"1234" {
+            return CKR_PIN_INCORRECT;
+        }
+        LOGGED_IN.store(true, Ordering::SeqCst);
+        CKR_OK
+    }
+
+    unsafe extern "C" fn logout(session: CkSessionHandle) -> CkRv {
+        assert!(sessions().contains_key(&session));
+        if !LOGGED_IN.swap(false, Ordering::SeqCst) {
+            return CKR_USER_NOT_LOGGED_IN;
+        }
+        CKR_OK
+    }
+
+    unsafe extern "C" fn find_objects_init(session: CkSessionHandle, template: *mut CkAttribute, count: CkUlong) -> CkRv {
+        let template = unsafe { std::slice::from_raw_parts(template, count as usize) };
+        let mut class = None;
+        let mut label = None;
+        for attribute in template {
+            let value = unsafe { attribute_value(attribute) };
+            match attribute.attribute_type {
+                CKA_CLASS => class = Some(CkUlong::from_ne_bytes(value.try_into().unwrap())),
+                CKA_KEY_TYPE => assert_eq!(CkUlong::from_ne_bytes(value.try_into().unwrap()), CKK_RSA),
+                CKA_LABEL => label = Some(value.to_vec()),
+                _ => {},
+            }
+        }
+
+        let mut sessions = sessions();
+        let session = sessions.get_mut(&session).unwrap();
+        session.found = match (class, label.as_deref()) {
+            (Some(CKO_PRIVATE_KEY), Some(b"app-key")) if LOGGED_IN.load(Ordering::SeqCst) => vec![PRIVATE_KEY],
+            (Some(CKO_PUBLIC_KEY), Some(b"app-key")) => vec![PUBLIC_KEY],
+            _ => Vec::new(),
+        };
+        CKR_OK
+    }
+
+    unsafe extern "C" fn find_objects(session: CkSessionHandle, objects: *mut CkObjectHandle, max: CkUlong, count: *mut CkUlong) -> CkRv {
+        let found = std::mem::take(&mut sessions().get_mut(&session).unwrap().found);
+        let found = &found[..found.len().min(max as usize)];
+        unsafe {
+            ptr::copy_nonoverlapping(found.as_ptr(), objects, found.len());
+            *count = found.len() as CkUlong;
+        }
+        CKR_OK
+    }
+
+    unsafe extern "C" fn find_objects_final(_session: CkSessionHandle) -> CkRv {
+        CKR_OK
+    }
+
+    unsafe extern "C" fn get_attribute_value(_session: CkSessionHandle, object: CkObjectHandle, template: *mut CkAttribute, count: CkUlong) -> CkRv {
+        let template = unsafe { &mut *template };
+        assert_eq!(count, 1);
+        let value = match (object, template.attribute_type) {
+            (PUBLIC_KEY, CKA_MODULUS) => mock_key().n().to_bytes_be(),
+            (PUBLIC_KEY, CKA_PUBLIC_EXPONENT) => mock_key().e().to_bytes_be(),
+            _ => return CKR_ATTRIBUTE_TYPE_INVALID,
+        };
+        if !template.value.is_null() {
+            assert!(template.value_len as usize >= value.len());
+            unsafe { ptr::copy_nonoverlapping(value.as_ptr(), template.value.cast::<u8>(), value.len()) };
+        }
+        template.value_len = value.len() as CkUlong;
+        CKR_OK
+    }
+
+    unsafe extern "C" fn decrypt_init(session: CkSessionHandle, mechanism: *mut CkMechanism, key: CkObjectHandle) -> CkRv {
+        let mechanism = unsafe { &*mechanism };
+        assert_eq!(key, PRIVATE_KEY);
+        if mechanism.mechanism == CKM_RSA_PKCS_OAEP {
+            let params = unsafe { &*mechanism.parameter.cast::<CkRsaPkcsOaepParams>() };
+            assert_eq!(mechanism.parameter_len as usize, mem::size_of::<CkRsaPkcsOaepParams>());
+            assert_eq!((params.hash_alg, params.mgf, params.source_data_len), (CKM_SHA256, CKG_MGF1_SHA256, 0));
+        }
+        sessions().get_mut(&session).unwrap().mechanism = Some(mechanism.mechanism);
+        CKR_OK
+    }
+
+    unsafe extern "C" fn decrypt(session: CkSessionHandle, data: *const u8, data_len: CkUlong, out: *mut u8, out_len: *mut CkUlong) -> CkRv {
+        if !INITIALIZED.load(Ordering::SeqCst) {
+            return CKR_CRYPTOKI_NOT_INITIALIZED;
+        }
+        if !LOGGED_IN.load(Ordering::SeqCst) {
+            return CKR_USER_NOT_LOGGED_IN;
+        }
+        let data = unsafe { std::slice::from_raw_parts(data, data_len as usize) };
+        let plaintext = match sessions().get_mut(&session).unwrap().mechanism.take() {
+            Some(CKM_RSA_PKCS_OAEP) => mock_key().decrypt(Oaep::new::<Sha256>(), data),
+            Some(CKM_RSA_PKCS) => mock_key().decrypt(Pkcs1v15Encrypt, data),
+            _ => return CKR_MECHANISM_INVALID,
+        };
+        let Ok(plaintext) = plaintext else { return 0x40 };
+        unsafe {
+            assert!(*out_len as usize >= plaintext.len());
+            ptr::copy_nonoverlapping(plaintext.as_ptr(), out, plaintext.len());
+            *out_len = plaintext.len() as CkUlong;
+        }
+        CKR_OK
+    }
+
+    static MOCK_TOKEN: CkFunctionList = CkFunctionList {
+        version: CkVersion { major: 2, minor: 40 },
+        initialize: Some(initialize),
+        finalize: Some(finalize),
+        get_info: None,
+        get_function_list: None,
+        get_slot_list: Some(get_slot_list),
+        get_slot_info: None,
+        get_token_info: Some(get_token_info),
+        get_mechanism_list: None,
+        get_mechanism_info: None,
+        init_token: None,
+        init_pin: None,
+        set_pin: None,
+        open_session: Some(open_session),
+        close_session: Some(close_session),
+        close_all_sessions: None,
+        get_session_info: None,
+        get_operation_state: None,
+        set_operation_state: None,
+        login: Some(login),
+        logout: Some(logout),
+        create_object: None,
+        copy_object: None,
+        destroy_object: None,
+        get_object_size: None,
+        get_attribute_value: Some(get_attribute_value),
+        set_attribute_value: None,
+        find_objects_init: Some(find_objects_init),
+        find_objects: Some(find_objects),
+        find_objects_final: Some(find_objects_final),
+        encrypt_init: None,
+        encrypt: None,
+        encrypt_update: None,
+        encrypt_final: None,
+        decrypt_init: Some(decrypt_init),
+        decrypt: Some(decrypt),
+    };
+
+    fn open_mock_key(config: Pkcs11Config) -> Result<Pkcs11RsaKey, AsymmetricError> {
+        let module = Pkcs11Module::shared("mock-token", || unsafe { Pkcs11Module::from_function_list("mock-token", &MOCK_TOKEN) })?;
+        Pkcs11RsaKey::open_with_module(module, &config)
+    }
+
+    #[test]
+    fn test_pkcs11_token_decryption() {
+        let _token = token();
+        let key = open_mock_key(Pkcs11Config::new("mock-token", "app-key").with_token_label("secretfs-test").with_pin(b"1234")).unwrap();
+        assert_eq!(key.token_label(), "secretfs-test");
+        assert_eq!(key.public_key(), &mock_key().to_public_key());
+
+        let oaep = AsymmetricEncryption::new_with_public_key(key.public_key().clone()).unwrap().encrypt(b"sk-1234567890").unwrap();
+        let pkcs1 = AsymmetricEncryption::new_with_public_key(key.public_key().clone()).unwrap()
+            .with_padding(RsaPadding::Pkcs1v15).unwrap()
+            .encrypt_chunked(b"legacy chunked secret")
+            .unwrap();
+
+        let decryption = AsymmetricDecryption::new_with_pkcs11_key(key);
+        assert!(decryption.decryption_info().contains("PKCS#11 token 'secretfs-test'"));
+        assert_eq!(decryption.decrypt(&oaep).unwrap(), b"sk-1234567890");
+        assert_eq!(decryption.decrypt(&pkcs1).unwrap(), b"legacy chunked secret");
+    }
+
+    #[test]
+    fn test_pkcs11_public_key_without_login() {
+        let _token = token();
+        let module = Pkcs11Module::shared("mock-token", || unsafe { Pkcs11Module::from_function_list("mock-token", &MOCK_TOKEN) }).unwrap();
+        let public_key = Pkcs11Config::new("mock-token", "app-key").read_public_key_from(module.clone()).unwrap();
+        assert_eq!(public_key, mock_key().to_public_key());
+
+        let missing = Pkcs11Config::new("mock-token", "other-key").read_public_key_from(module).unwrap_err();
+        assert!(matches!(missing, AsymmetricError::KeyLoadError(_)));
+    }
+
+    #[test]
+    fn test_pkcs11_module_outlives_first_key() {
+        let _token = token();
+        let config = || Pkcs11Config::new("mock-token", "app-key").with_pin(b"1234");
+        let first = open_mock_key(config()).unwrap();
+        let second = open_mock_key(config()).unwrap();
+        assert!(Arc::ptr_eq(&first.session.module, &second.session.module));
+
+        // Another PIN cannot be checked while the token is logged in
+        let other_pin = open_mock_key(Pkcs11Config::new("mock-token", "app-key").with_pin(b"0000")).unwrap_err();
+        assert!(other_pin.to_string().contains("different PIN"), "{}", other_pin);
+
+        // Dropping the first key must neither finalize the module nor log out the token under the second
+        drop(first);
+        let ciphertext = AsymmetricEncryption::new_with_public_key(second.public_key().clone()).unwrap().encrypt(b"still here").unwrap();
+        assert_eq!(AsymmetricDecryption::new_with_pkcs11_key(second).decrypt(&ciphertext).unwrap(), b"still here");
+        assert!(!LOGGED_IN.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn test_pkcs11_errors() {
+        let _token = token();
+        let wrong_pin = open_mock_key(Pkcs11Config::new("mock-token", "app-key").with_pin(b"0000")).unwrap_err();
+        assert!(wrong_pin.to_string().contains("CKR_PIN_INCORRECT"), "{}", wrong_pin);
+
+        // Private keys are only visible after login
+        let no_pin = open_mock_key(Pkcs11Config::new("mock-token", "app-key")).unwrap_err();
+        assert!(matches!(no_pin, AsymmetricError::KeyLoadError(_)));
+
+        assert!(open_mock_key(Pkcs11Config::new("mock-token", "app-key").with_token_label("other").with_pin(b"1234")).is_err());
+        assert!(open_mock_key(Pkcs11Config::new("mock-token", "app-key").with_slot(1).with_pin(b"1234")).is_err());
+        assert!(open_mock_key(Pkcs11Config::new("mock-token", "other-key").with_pin(b"1234")).is_err());
+
+        let missing = Pkcs11Module::load("/nonexistent/libpkcs11.so").unwrap_err();
+        assert!(matches!(missing, AsymmetricError::Pkcs11Error(_)));
+        assert_eq!(rv_name(CKR_PIN_INCORRECT), "CKR_PIN_INCORRECT (0xa0)");
+    }
+}
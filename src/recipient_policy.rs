@@ -0,0 +1,239 @@
+use std::env;
+use std::fs;
+use rsa::RsaPublicKey;
+use crate::asymmetric_encryption::{AsymmetricEncryption, AsymmetricError, RsaPadding, load_public_key_file};
+
+/// A glob pattern and the recipients of the secrets it matches
+struct RecipientRule {
+    pattern: String,
+    encryption: AsymmetricEncryption,
+}
+
+/// Maps secret names to the public keys they are encrypted for
+///
+/// Rules are checked in order and the first matching glob wins, so more
+/// specific patterns should come first. Patterns support `*` (any run of
+/// characters) and `?` (exactly one character).
+///
+/// # Format
+///
+/// One rule per line (or separated by `;` when given inline), with the
+/// public key files of the rule as a comma-separated list:
+///
+/// ```text
+/// # secret glob     = public key file(s)
+/// database_password = /keys/api.pem
+/// stripe-*          = /keys/billing.pem
+/// shared-*          = /keys/api.pem, /keys/billing.pem
+/// ```
+#[derive(Default)]
+pub struct RecipientPolicy {
+    rules: Vec<RecipientRule>,
+}
+
+impl RecipientPolicy {
+    /// Create an empty policy
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a rule encrypting secrets matching `pattern` to `public_keys`
+    pub fn add_rule(&mut self, pattern: &str, public_keys: Vec<RsaPublicKey>, padding: RsaPadding) -> Result<(), AsymmetricError> {
+        let pattern = pattern.trim();
+        if pattern.is_empty() {
+            return Err(AsymmetricError::ConfigurationError("Recipient policy pattern cannot be empty".to_string()));
+        }
+
+        let encryption = AsymmetricEncryption::new_with_public_keys(public_keys)?.with_padding(padding)?;
+        self.rules.push(RecipientRule {
+            pattern: pattern.to_string(),
+            encryption,
+        });
+        Ok(())
+    }
+
+    /// Parse a policy, loading the referenced public key files
+    pub fn parse(spec: &str, padding: RsaPadding) -> Result<Self, AsymmetricError> {
+        let mut policy = Self::new();
+
+        for rule in spec.split(['\n', ';']) {
+            let rule = rule.trim();
+            if rule.is_empty() || rule.starts_with('#') {
+                continue;
+            }
+
+            let (pattern, key_files) = rule.split_once('=').ok_or_else(|| AsymmetricError::ConfigurationError(
+                format!("Invalid recipient policy rule '{}', expected '<glob> = <public key file>[, ...]'", rule)
+            ))?;
+
+            let public_keys = key_files.split(',')
+                .map(str::trim)
+                .filter(|path| !path.is_empty())
+                .map(load_public_key_file)
+                .collect::<Result<Vec<_>, _>>()?;
+
+            if public_keys.is_empty() {
+                return Err(AsymmetricError::ConfigurationError(
+                    format!("Recipient policy rule for '{}' lists no public keys", pattern.trim())
+                ));
+            }
+
+            policy.add_rule(pattern, public_keys, padding)?;
+        }
+
+        Ok(policy)
+    }
+
+    /// Load the policy from `SECRETFS_RECIPIENT_POLICY` (inline rules) or
+    /// `SECRETFS_RECIPIENT_POLICY_FILE`
+    ///
+    /// Returns an empty policy when neither is set, and an error when both are.
+    pub fn from_env() -> Result<Self, AsymmetricError> {
+        Self::from_config(
+            env::var("SECRETFS_RECIPIENT_POLICY").ok(),
+            env::var("SECRETFS_RECIPIENT_POLICY_FILE").ok(),
+            RsaPadding::from_env()?,
+        )
+    }
+
+    fn from_config(spec: Option<String>, policy_path: Option<String>, padding: RsaPadding) -> Result<Self, AsymmetricError> {
+        match (spec, policy_path) {
+            (Some(_), Some(_)) => Err(AsymmetricError::ConfigurationError(
+                "Both SECRETFS_RECIPIENT_POLICY and SECRETFS_RECIPIENT_POLICY_FILE are set; set only one".to_string()
+            )),
+            (Some(spec), None) => Self::parse(&spec, padding),
+            (None, Some(policy_path)) => {
+                let spec = fs::read_to_string(&policy_path)
+                    .map_err(|e| AsymmetricError::FileError(format!("Failed to read recipient policy file {}: {}", policy_path, e)))?;
+                Self::parse(&spec, padding)
+            },
+            (None, None) => Ok(Self::new()),
+        }
+    }
+
+    /// Encryption for the first rule matching `secret_name`
+    pub fn encryption_for(&self, secret_name: &str) -> Option<&AsymmetricEncryption> {
+        self.rules.iter()
+            .find(|rule| glob_match(&rule.pattern, secret_name))
+            .map(|rule| &rule.encryption)
+    }
+
+    /// Encryptions of all rules, in order
+    pub fn encryptions(&self) -> impl Iterator<Item = &AsymmetricEncryption> {
+        self.rules.iter().map(|rule| &rule.encryption)
+    }
+
+    /// Number of rules in the policy
+    pub fn len(&self) -> usize {
+        self.rules.len()
+    }
+
+    /// Whether the policy has no rules
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+}
+
+/// Match `name` against a glob supporting `*` and `?`
+pub fn glob_match(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+
+    let (mut p, mut n) = (0, 0);
+    // Position of the last `*` in the pattern and the name index it was tried at
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while n < name.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == name[n]) {
+            p += 1;
+            n += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            backtrack = Some((p, n));
+            p += 1;
+        } else if let Some((star, matched)) = backtrack {
+            // Let the last `*` absorb one more character
+            p = star + 1;
+            n = matched + 1;
+            backtrack = Some((star, n));
+        } else {
+            return false;
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asymmetric_encryption::{AsymmetricDecryption, RsaKeyPair};
+    use crate::encryption::{RsaCipher, SecretCipher};
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("database_password", "database_password"));
+        assert!(!glob_match("database_password", "database_password2"));
+        assert!(glob_match("stripe-*", "stripe-key"));
+        assert!(glob_match("stripe-*", "stripe-"));
+        assert!(!glob_match("stripe-*", "stripe"));
+        assert!(glob_match("*_password", "redis_password"));
+        assert!(glob_match("a*b*c", "axxbyyc"));
+        assert!(!glob_match("a*b*c", "axxbyy"));
+        assert!(glob_match("api_key?", "api_key2"));
+        assert!(!glob_match("api_key?", "api_key"));
+        assert!(glob_match("*", "anything"));
+    }
+
+    #[test]
+    fn test_policy_routes_secrets_to_their_consumers() {
+        let api = RsaKeyPair::generate(1024).unwrap();
+        let billing = RsaKeyPair::generate(1024).unwrap();
+
+        let temp_dir = TempDir::new().unwrap();
+        let api_pub = temp_dir.path().join("api.pem");
+        let billing_pub = temp_dir.path().join("billing.pem");
+        fs::write(&api_pub, api.public_key_pem().unwrap()).unwrap();
+        fs::write(&billing_pub, billing.public_key_pem().unwrap()).unwrap();
+
+        let spec = format!(
+            "# comment\ndatabase_password = {}\nstripe-* = {}\nshared-* = {}, {}\n",
+            api_pub.display(), billing_pub.display(), api_pub.display(), billing_pub.display()
+        );
+        let policy = RecipientPolicy::parse(&spec, RsaPadding::default()).unwrap();
+        assert_eq!(policy.len(), 3);
+        assert!(policy.encryption_for("unrelated").is_none());
+
+        let cipher = RsaCipher::with_recipients(None, policy).unwrap();
+        let api_decryption = AsymmetricDecryption::new_with_private_key(api.private_key);
+        let billing_decryption = AsymmetricDecryption::new_with_private_key(billing.private_key);
+
+        let db = cipher.encrypt_secret("database_password", b"db").unwrap();
+        assert_eq!(api_decryption.decrypt(&db).unwrap(), b"db");
+        assert!(billing_decryption.decrypt(&db).is_err());
+
+        let stripe = cipher.encrypt_secret("stripe-key", b"sk").unwrap();
+        assert_eq!(billing_decryption.decrypt(&stripe).unwrap(), b"sk");
+        assert!(api_decryption.decrypt(&stripe).is_err());
+
+        let shared = cipher.encrypt_secret("shared-token", b"both").unwrap();
+        assert_eq!(api_decryption.decrypt(&shared).unwrap(), b"both");
+        assert_eq!(billing_decryption.decrypt(&shared).unwrap(), b"both");
+
+        // No rule and no default recipients: refuse instead of guessing
+        assert!(cipher.encrypt_secret("unrelated", b"x").is_err());
+    }
+
+    #[test]
+    fn test_policy_parse_errors() {
+        assert!(RecipientPolicy::parse("no-equals-sign", RsaPadding::default()).is_err());
+        assert!(RecipientPolicy::parse("api_key = ", RsaPadding::default()).is_err());
+        assert!(RecipientPolicy::parse("api_key = /nonexistent/key.pem", RsaPadding::default()).is_err());
+        assert!(RecipientPolicy::parse("  \n# only comments\n", RsaPadding::default()).unwrap().is_empty());
+
+        // An inline policy does not silently override the policy file
+        let both = RecipientPolicy::from_config(Some(String::new()), Some("/etc/secretfs/policy".to_string()), RsaPadding::default());
+        assert!(matches!(both, Err(AsymmetricError::ConfigurationError(_))));
+        assert!(RecipientPolicy::from_config(None, None, RsaPadding::default()).unwrap().is_empty());
+    }
+}
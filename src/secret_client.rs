@@ -1,11 +1,16 @@
 use std::fs;
 use std::path::Path;
 use std::collections::HashMap;
-use crate::asymmetric_encryption::AsymmetricDecryption;
+use crate::asymmetric_encryption::{AsymmetricDecryption, EnvPassphrase};
+use crate::keyring::Keyring;
+use crate::encryption::{PlaintextCipher, SecretCipher};
+use crate::header::{CipherId, SecretHeader, is_age_file};
+use zeroize::{Zeroize, Zeroizing};
 
 /// Client for reading and decrypting secrets from SecretFS
 pub struct SecretClient {
-    decryption: Option<AsymmetricDecryption>,
+    decryption: Option<Keyring>,
+    cipher: Option<Box<dyn SecretCipher>>,
     mount_path: String,
 }
 
@@ -33,68 +38,151 @@ impl std::error::Error for SecretClientError {}
 
 impl SecretClient {
     /// Create a new secret client with RSA decryption capability
+    /// 
+    /// Private keys are loaded with `Keyring::from_env`, so besides a single
+    /// RSA or X25519 key this accepts a list (`SECRETFS_PRIVATE_KEY_FILES`)
+    /// or a directory of keys (`SECRETFS_PRIVATE_KEY_DIR`) during rotation.
     pub fn new_with_rsa_decryption(mount_path: &str) -> Result<Self, SecretClientError> {
-        let decryption = AsymmetricDecryption::from_env()
+        let keyring = Keyring::from_env()
             .map_err(|e| SecretClientError::ConfigurationError(format!("RSA decryption setup failed: {}", e)))?;
         
-        Ok(SecretClient {
-            decryption: Some(decryption),
+        Ok(Self::new_with_keyring(mount_path, keyring))
+    }
+    
+    /// Create a new secret client with an already loaded private key
+    pub fn new_with_decryption(mount_path: &str, decryption: AsymmetricDecryption) -> Self {
+        Self::new_with_keyring(mount_path, Keyring::from(decryption))
+    }
+    
+    /// Create a new secret client that decrypts with any key of a keyring
+    pub fn new_with_keyring(mount_path: &str, keyring: Keyring) -> Self {
+        SecretClient {
+            decryption: Some(keyring),
+            cipher: None,
+            mount_path: mount_path.to_string(),
+        }
+    }
+    
+    /// Create a new secret client with the private keys in `key_dir`
+    /// 
+    /// Passphrase-protected keys use the passphrase from the environment
+    /// (`SECRETFS_PRIVATE_KEY_PASSPHRASE`, `_FILE` or `_FD`), which is only
+    /// read if the directory holds one.
+    pub fn new_with_key_dir(mount_path: &str, key_dir: &str) -> Result<Self, SecretClientError> {
+        let keyring = Keyring::from_dir_with_env_passphrase(key_dir, &EnvPassphrase::default())
+            .map_err(|e| SecretClientError::ConfigurationError(format!("Failed to load keys from {}: {}", key_dir, e)))?;
+        if keyring.is_empty() {
+            return Err(SecretClientError::ConfigurationError(format!("No private keys found in {}", key_dir)));
+        }
+        
+        Ok(Self::new_with_keyring(mount_path, keyring))
+    }
+    
+    /// Create a new secret client that decrypts with a symmetric `SecretCipher`
+    /// 
+    /// Use this with `AesGcmCipher` or `XChaCha20Cipher` (configured with the
+    /// same key as SecretFS) to read secrets that are served or exported in
+    /// encrypted form. The secret name is passed to the cipher, so name-bound
+    /// ciphertexts are verified against the file they were read from.
+    /// An `AgeCipher` with identities reads mounts written in age format.
+    pub fn new_with_cipher(mount_path: &str, cipher: Box<dyn SecretCipher>) -> Self {
+        SecretClient {
+            decryption: None,
+            cipher: Some(cipher),
             mount_path: mount_path.to_string(),
-        })
+        }
     }
     
     /// Create a new secret client without decryption (for plaintext secrets)
     pub fn new_plaintext(mount_path: &str) -> Self {
         SecretClient {
             decryption: None,
+            cipher: None,
             mount_path: mount_path.to_string(),
         }
     }
     
     /// Read and decrypt a secret by name
-    pub fn get_secret(&self, secret_name: &str) -> Result<String, SecretClientError> {
-        let secret_path = format!("{}/{}", self.mount_path, secret_name);
-        
-        if !Path::new(&secret_path).exists() {
-            return Err(SecretClientError::NotFound(format!("Secret '{}' not found at {}", secret_name, secret_path)));
-        }
+    /// 
+    /// The returned value is zeroized when dropped.
+    pub fn get_secret(&self, secret_name: &str) -> Result<Zeroizing<String>, SecretClientError> {
+        let mut bytes = self.get_secret_bytes(secret_name)?;
         
-        let encrypted_content = fs::read(&secret_path)
-            .map_err(|e| SecretClientError::FileError(format!("Failed to read secret file {}: {}", secret_path, e)))?;
-        
-        if let Some(ref decryption) = self.decryption {
-            // Decrypt the content
-            let decrypted_bytes = decryption.decrypt(&encrypted_content)
-                .map_err(|e| SecretClientError::DecryptionError(format!("Failed to decrypt secret '{}': {}", secret_name, e)))?;
-            
-            String::from_utf8(decrypted_bytes)
-                .map_err(|e| SecretClientError::DecryptionError(format!("Decrypted content is not valid UTF-8: {}", e)))
-        } else {
-            // Return as plaintext
-            String::from_utf8(encrypted_content)
-                .map_err(|e| SecretClientError::FileError(format!("Secret content is not valid UTF-8: {}", e)))
+        match String::from_utf8(std::mem::take(&mut *bytes)) {
+            Ok(value) => Ok(Zeroizing::new(value)),
+            Err(e) => {
+                let error = e.utf8_error();
+                e.into_bytes().zeroize();
+                Err(if self.has_decryption() {
+                    SecretClientError::DecryptionError(format!("Decrypted content is not valid UTF-8: {}", error))
+                } else {
+                    SecretClientError::FileError(format!("Secret content is not valid UTF-8: {}", error))
+                })
+            }
         }
     }
     
     /// Read and decrypt a secret as bytes
-    pub fn get_secret_bytes(&self, secret_name: &str) -> Result<Vec<u8>, SecretClientError> {
+    /// 
+    /// The `SecretHeader` at the start of the file decides how the content is
+    /// decrypted. Files without a header are decrypted with whatever this
+    /// client is configured with (legacy RSA mounts, custom ciphers) or
+    /// returned as-is. The returned bytes are zeroized when dropped.
+    pub fn get_secret_bytes(&self, secret_name: &str) -> Result<Zeroizing<Vec<u8>>, SecretClientError> {
         let secret_path = format!("{}/{}", self.mount_path, secret_name);
         
         if !Path::new(&secret_path).exists() {
             return Err(SecretClientError::NotFound(format!("Secret '{}' not found at {}", secret_name, secret_path)));
         }
         
-        let encrypted_content = fs::read(&secret_path)
-            .map_err(|e| SecretClientError::FileError(format!("Failed to read secret file {}: {}", secret_path, e)))?;
+        // Plaintext mounts serve the secret itself
+        let encrypted_content = Zeroizing::new(fs::read(&secret_path)
+            .map_err(|e| SecretClientError::FileError(format!("Failed to read secret file {}: {}", secret_path, e)))?);
         
-        if let Some(ref decryption) = self.decryption {
-            // Decrypt the content
-            decryption.decrypt(&encrypted_content)
-                .map_err(|e| SecretClientError::DecryptionError(format!("Failed to decrypt secret '{}': {}", secret_name, e)))
-        } else {
-            // Return as plaintext
-            Ok(encrypted_content)
-        }
+        let decrypt_error = |e: &dyn std::fmt::Display| {
+            SecretClientError::DecryptionError(format!("Failed to decrypt secret '{}': {}", secret_name, e))
+        };
+        
+        let decrypted = match SecretHeader::peek(&encrypted_content) {
+            Some(header) if header.cipher_id.requires_private_key() => {
+                let decryption = self.decryption.as_ref().ok_or_else(|| SecretClientError::ConfigurationError(format!(
+                    "Secret '{}' is encrypted with {} (key id {}) - configure a private key",
+                    secret_name, header.cipher_id, header.key_id_hex()
+                )))?;
+                decryption.decrypt(&encrypted_content).map_err(|e| decrypt_error(&e))
+            },
+            Some(header) if header.cipher_id == CipherId::Plaintext && self.cipher.is_none() => {
+                PlaintextCipher::new().decrypt(&encrypted_content).map_err(|e| decrypt_error(&e))
+            },
+            Some(header) => {
+                let cipher = self.cipher.as_ref().ok_or_else(|| SecretClientError::ConfigurationError(format!(
+                    "Secret '{}' is encrypted with {} (key id {}) - configure a matching cipher",
+                    secret_name, header.cipher_id, header.key_id_hex()
+                )))?;
+                cipher.decrypt_secret(secret_name, &encrypted_content).map_err(|e| decrypt_error(&e))
+            },
+            None if is_age_file(&encrypted_content) => {
+                let cipher = self.cipher.as_ref().ok_or_else(|| SecretClientError::ConfigurationError(format!(
+                    "Secret '{}' is an age file - configure an AgeCipher with an identity or use `age -d`",
+                    secret_name
+                )))?;
+                cipher.decrypt_secret(secret_name, &encrypted_content).map_err(|e| decrypt_error(&e))
+            },
+            None => {
+                if let Some(ref decryption) = self.decryption {
+                    // A JWE token, or a legacy RSA mount without header. Without a private
+                    // key, JWE tokens are returned as they are, like any headerless content:
+                    // apps may keep tokens they decrypt themselves on a plaintext mount
+                    decryption.decrypt(&encrypted_content).map_err(|e| decrypt_error(&e))
+                } else if let Some(ref cipher) = self.cipher {
+                    cipher.decrypt_secret(secret_name, &encrypted_content).map_err(|e| decrypt_error(&e))
+                } else {
+                    // Return as plaintext
+                    return Ok(encrypted_content);
+                }
+            }
+        };
+        decrypted.map(Zeroizing::new)
     }
     
     /// List all available secrets
@@ -124,7 +212,7 @@ impl SecretClient {
     }
     
     /// Get all secrets as a HashMap
-    pub fn get_all_secrets(&self) -> Result<HashMap<String, String>, SecretClientError> {
+    pub fn get_all_secrets(&self) -> Result<HashMap<String, Zeroizing<String>>, SecretClientError> {
         let secret_names = self.list_secrets()?;
         let mut secrets = HashMap::new();
         
@@ -144,20 +232,22 @@ impl SecretClient {
     
     /// Check if the client has decryption capability
     pub fn has_decryption(&self) -> bool {
-        self.decryption.is_some()
+        self.decryption.is_some() || self.cipher.is_some()
     }
     
     /// Get decryption info
     pub fn decryption_info(&self) -> String {
         if let Some(ref decryption) = self.decryption {
-            decryption.decryption_info().to_string()
+            decryption.decryption_info()
+        } else if let Some(ref cipher) = self.cipher {
+            cipher.cipher_info()
         } else {
             "No decryption - plaintext mode".to_string()
         }
     }
     
     /// Wait for a secret to become available (useful for initialization)
-    pub fn wait_for_secret(&self, secret_name: &str, timeout_seconds: u64) -> Result<String, SecretClientError> {
+    pub fn wait_for_secret(&self, secret_name: &str, timeout_seconds: u64) -> Result<Zeroizing<String>, SecretClientError> {
         use std::time::{Duration, Instant};
         
         let start = Instant::now();
@@ -188,7 +278,7 @@ pub mod convenience {
     use std::env;
     
     /// Get a secret with automatic client configuration
-    pub fn get_secret(secret_name: &str) -> Result<String, SecretClientError> {
+    pub fn get_secret(secret_name: &str) -> Result<Zeroizing<String>, SecretClientError> {
         let mount_path = env::var("SECRETFS_MOUNT_PATH")
             .unwrap_or_else(|_| "/mnt/secrets".to_string());
         
@@ -203,7 +293,7 @@ pub mod convenience {
     }
     
     /// Get all secrets with automatic client configuration
-    pub fn get_all_secrets() -> Result<HashMap<String, String>, SecretClientError> {
+    pub fn get_all_secrets() -> Result<HashMap<String, Zeroizing<String>>, SecretClientError> {
         let mount_path = env::var("SECRETFS_MOUNT_PATH")
             .unwrap_or_else(|_| "/mnt/secrets".to_string());
         
@@ -218,7 +308,7 @@ pub mod convenience {
     }
     
     /// Wait for a secret with automatic client configuration
-    pub fn wait_for_secret(secret_name: &str, timeout_seconds: u64) -> Result<String, SecretClientError> {
+    pub fn wait_for_secret(secret_name: &str, timeout_seconds: u64) -> Result<Zeroizing<String>, SecretClientError> {
         let mount_path = env::var("SECRETFS_MOUNT_PATH")
             .unwrap_or_else(|_| "/mnt/secrets".to_string());
         
@@ -252,7 +342,7 @@ mod tests {
         
         // Test getting secret
         let value = client.get_secret("test_secret").unwrap();
-        assert_eq!(value, "test_value");
+        assert_eq!(value.as_str(), "test_value");
         
         // Test listing secrets
         let secrets = client.list_secrets().unwrap();
@@ -260,7 +350,99 @@ mod tests {
         
         // Test getting all secrets
         let all_secrets = client.get_all_secrets().unwrap();
-        assert_eq!(all_secrets.get("test_secret"), Some(&"test_value".to_string()));
+        assert_eq!(all_secrets.get("test_secret").map(|value| value.as_str()), Some("test_value"));
+    }
+    
+    #[test]
+    fn test_cipher_client_decrypts_xchacha20() {
+        use crate::encryption::XChaCha20Cipher;
+        
+        let temp_dir = TempDir::new().unwrap();
+        let mount_path = temp_dir.path().to_str().unwrap();
+        
+        let key = [3u8; 32];
+        let encrypted = XChaCha20Cipher::new(&key).unwrap()
+            .encrypt_secret("test_secret", b"test_value")
+            .unwrap();
+        fs::write(temp_dir.path().join("test_secret"), &encrypted).unwrap();
+        fs::write(temp_dir.path().join("moved_secret"), &encrypted).unwrap();
+        
+        let client = SecretClient::new_with_cipher(mount_path, Box::new(XChaCha20Cipher::new(&key).unwrap()));
+        assert!(client.has_decryption());
+        assert_eq!(client.get_secret("test_secret").unwrap().as_str(), "test_value");
+        
+        // Ciphertext copied to another name does not authenticate
+        assert!(matches!(client.get_secret("moved_secret"), Err(SecretClientError::DecryptionError(_))));
+    }
+    
+    #[test]
+    fn test_header_dispatch() {
+        use crate::encryption::AesGcmCipher;
+        
+        let temp_dir = TempDir::new().unwrap();
+        let mount_path = temp_dir.path().to_str().unwrap();
+        
+        let encrypted = AesGcmCipher::new(&[5u8; 32]).unwrap()
+            .encrypt_secret("aes_secret", b"aes_value")
+            .unwrap();
+        fs::write(temp_dir.path().join("aes_secret"), &encrypted).unwrap();
+        
+        let wrapped = PlaintextCipher::new().encrypt(b"plain_value").unwrap();
+        fs::write(temp_dir.path().join("plain_secret"), &wrapped).unwrap();
+        
+        // Header says AES-256-GCM: a plaintext client reports what is missing
+        let client = SecretClient::new_plaintext(mount_path);
+        let err = client.get_secret("aes_secret").unwrap_err();
+        assert!(matches!(err, SecretClientError::ConfigurationError(_)));
+        assert!(err.to_string().contains("aes-256-gcm"));
+        assert_eq!(client.get_secret("plain_secret").unwrap().as_str(), "plain_value");
+        
+        let client = SecretClient::new_with_cipher(mount_path, Box::new(AesGcmCipher::new(&[5u8; 32]).unwrap()));
+        assert_eq!(client.get_secret("aes_secret").unwrap().as_str(), "aes_value");
+    }
+    
+    #[test]
+    fn test_x25519_client() {
+        use crate::encryption::X25519Cipher;
+        use crate::x25519_encryption::{X25519Encryption, X25519KeyPair};
+        
+        let temp_dir = TempDir::new().unwrap();
+        let mount_path = temp_dir.path().to_str().unwrap();
+        
+        let key_pair = X25519KeyPair::generate();
+        let cipher = X25519Cipher::with_encryption(X25519Encryption::new_with_public_key(key_pair.public_key).unwrap());
+        fs::write(temp_dir.path().join("api_key"), cipher.encrypt_secret("api_key", b"sk-test").unwrap()).unwrap();
+        
+        let client = SecretClient::new_plaintext(mount_path);
+        assert!(matches!(client.get_secret("api_key"), Err(SecretClientError::ConfigurationError(_))));
+        
+        let decryption = AsymmetricDecryption::new_with_x25519_key(key_pair.private_key);
+        let client = SecretClient::new_with_decryption(mount_path, decryption);
+        assert_eq!(client.get_secret("api_key").unwrap().as_str(), "sk-test");
+    }
+    
+    #[test]
+    fn test_jwe_client() {
+        use crate::encryption::JweCipher;
+        use crate::jwe::{JweEncryption, JweRecipient};
+        use crate::x25519_encryption::X25519KeyPair;
+        
+        let temp_dir = TempDir::new().unwrap();
+        let mount_path = temp_dir.path().to_str().unwrap();
+        
+        let key_pair = X25519KeyPair::generate();
+        let cipher = JweCipher::with_encryption(JweEncryption::new(JweRecipient::X25519(key_pair.public_key)).unwrap());
+        cipher.self_test().unwrap();
+        let token = cipher.encrypt_secret("api_key", b"sk-test").unwrap();
+        fs::write(temp_dir.path().join("api_key"), &token).unwrap();
+        
+        // A plaintext client returns the token itself
+        let client = SecretClient::new_plaintext(mount_path);
+        assert_eq!(client.get_secret("api_key").unwrap().as_bytes(), token.as_slice());
+        
+        let decryption = AsymmetricDecryption::new_with_x25519_key(key_pair.private_key);
+        let client = SecretClient::new_with_decryption(mount_path, decryption);
+        assert_eq!(client.get_secret("api_key").unwrap().as_str(), "sk-test");
     }
     
     #[test]
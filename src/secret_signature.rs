@@ -0,0 +1,247 @@
+use std::env;
+use std::fs;
+use base64::{Engine as _, engine::general_purpose};
+use ed25519_dalek::{Signature, Signer, SigningKey, VerifyingKey, PUBLIC_KEY_LENGTH};
+use ed25519_dalek::pkcs8::DecodePublicKey;
+use sha2::{Digest, Sha256};
+use zeroize::Zeroizing;
+use crate::header::format_key_id;
+use crate::secret_fetcher::{FetchedSecret, SecretFetchError};
+
+/// Response header carrying a signature over the whole response body
+pub const SIGNATURE_HEADER: &str = "X-SecretFS-Signature";
+
+/// Field of a secret object (and `FetchedSecret::metadata` key) carrying its signature
+pub const SIGNATURE_FIELD: &str = "signature";
+
+/// Domain separation prefix of per-secret signatures
+const SECRET_SIGNATURE_CONTEXT: &[u8] = b"secretfs-secret-v1\n";
+
+/// The message a per-secret signature covers
+///
+/// `"secretfs-secret-v1\n" || key || "\n" || value`, so a signature cannot be
+/// moved to another secret name. Keys containing a newline cannot be signed.
+pub fn signed_secret_message(key: &str, value: &str) -> Result<Zeroizing<Vec<u8>>, SecretFetchError> {
+    if key.contains('\n') {
+        return Err(SecretFetchError::SignatureError(format!("Secret name {:?} contains a newline and cannot be signed", key)));
+    }
+
+    let mut message = Zeroizing::new(Vec::with_capacity(SECRET_SIGNATURE_CONTEXT.len() + key.len() + 1 + value.len()));
+    message.extend_from_slice(SECRET_SIGNATURE_CONTEXT);
+    message.extend_from_slice(key.as_bytes());
+    message.push(b'\n');
+    message.extend_from_slice(value.as_bytes());
+    Ok(message)
+}
+
+/// Sign a secret the way the secret service does, returning the base64 signature
+pub fn sign_secret(signing_key: &SigningKey, key: &str, value: &str) -> Result<String, SecretFetchError> {
+    let signature = signing_key.sign(&signed_secret_message(key, value)?);
+    Ok(general_purpose::STANDARD.encode(signature.to_bytes()))
+}
+
+/// Ed25519 public keys of the secret services SecretFS accepts secrets from
+///
+/// A payload is accepted when any of the keys verifies it, so the old and
+/// the new signing key can both be trusted while the service rotates keys.
+#[derive(Default)]
+pub struct TrustedSigningKeys {
+    keys: Vec<VerifyingKey>,
+}
+
+impl TrustedSigningKeys {
+    /// Create an empty set of trusted keys
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Trust a key, ignoring keys that are already present
+    pub fn add(&mut self, key: VerifyingKey) {
+        if !self.keys.contains(&key) {
+            self.keys.push(key);
+        }
+    }
+
+    /// Trust a key given as an SPKI PEM (`openssl pkey -pubout`)
+    pub fn add_pem(&mut self, pem: &str, source: &str) -> Result<(), SecretFetchError> {
+        let key = VerifyingKey::from_public_key_pem(pem)
+            .map_err(|e| SecretFetchError::ConfigurationError(format!("Invalid Ed25519 public key in {}: {}", source, e)))?;
+        self.add(key);
+        Ok(())
+    }
+
+    /// Trust a key given as the base64 of its 32 raw bytes
+    pub fn add_base64(&mut self, encoded: &str) -> Result<(), SecretFetchError> {
+        let bytes = general_purpose::STANDARD.decode(encoded.trim())
+            .map_err(|e| SecretFetchError::ConfigurationError(format!("Invalid base64 in trusted signing key: {}", e)))?;
+        let bytes: [u8; PUBLIC_KEY_LENGTH] = bytes.try_into()
+            .map_err(|bytes: Vec<u8>| SecretFetchError::ConfigurationError(format!(
+                "Trusted signing key must be {} bytes, got {}", PUBLIC_KEY_LENGTH, bytes.len()
+            )))?;
+        let key = VerifyingKey::from_bytes(&bytes)
+            .map_err(|e| SecretFetchError::ConfigurationError(format!("Invalid Ed25519 public key: {}", e)))?;
+        self.add(key);
+        Ok(())
+    }
+
+    /// Load from environment configuration
+    ///
+    /// Collects the PEM files listed in `SECRETFS_TRUSTED_SIGNING_KEY_FILES`
+    /// and the base64 raw keys listed in `SECRETFS_TRUSTED_SIGNING_KEYS`
+    /// (both comma-separated). Returns `None` when neither is set, in which
+    /// case fetched secrets are not verified.
+    pub fn from_env() -> Result<Option<Self>, SecretFetchError> {
+        let files = env::var("SECRETFS_TRUSTED_SIGNING_KEY_FILES").ok();
+        let keys = env::var("SECRETFS_TRUSTED_SIGNING_KEYS").ok();
+        if files.is_none() && keys.is_none() {
+            return Ok(None);
+        }
+
+        let mut trusted = Self::new();
+        for path in files.iter().flat_map(|files| list_items(files)) {
+            let pem = fs::read_to_string(path)
+                .map_err(|e| SecretFetchError::ConfigurationError(format!("Failed to read trusted signing key {}: {}", path, e)))?;
+            trusted.add_pem(&pem, path)?;
+        }
+        for key in keys.iter().flat_map(|keys| list_items(keys)) {
+            trusted.add_base64(key)?;
+        }
+
+        if trusted.is_empty() {
+            return Err(SecretFetchError::ConfigurationError(
+                "SECRETFS_TRUSTED_SIGNING_KEY_FILES / SECRETFS_TRUSTED_SIGNING_KEYS list no keys".to_string()
+            ));
+        }
+        Ok(Some(trusted))
+    }
+
+    /// Verify a base64 signature over `message`, returning the id of the key that made it
+    pub fn verify(&self, message: &[u8], signature_b64: &str) -> Result<String, SecretFetchError> {
+        let bytes = general_purpose::STANDARD.decode(signature_b64.trim())
+            .map_err(|e| SecretFetchError::SignatureError(format!("Invalid base64 signature: {}", e)))?;
+        let signature = Signature::from_slice(&bytes)
+            .map_err(|_| SecretFetchError::SignatureError(format!("Signature must be 64 bytes, got {}", bytes.len())))?;
+
+        self.keys.iter()
+            .find(|key| key.verify_strict(message, &signature).is_ok())
+            .map(key_id)
+            .ok_or_else(|| SecretFetchError::SignatureError("Signature does not verify with any trusted key".to_string()))
+    }
+
+    /// Verify the secrets parsed from one response
+    ///
+    /// A `payload_signature` (from `SIGNATURE_HEADER`) over the raw `body`
+    /// covers every secret in it; otherwise each secret must carry its own
+    /// signature in its `SIGNATURE_FIELD`. Any unverified secret rejects
+    /// the whole response. Verified secrets get the signing key id in their
+    /// `signed_by` metadata.
+    pub fn verify_response(&self, body: &[u8], payload_signature: Option<&str>, secrets: &mut [FetchedSecret]) -> Result<(), SecretFetchError> {
+        if let Some(payload_signature) = payload_signature {
+            let signer = self.verify(body, payload_signature)
+                .map_err(|e| SecretFetchError::SignatureError(format!("Response signature rejected: {}", e)))?;
+            for secret in secrets.iter_mut() {
+                secret.metadata.insert("signed_by".to_string(), signer.clone());
+            }
+            return Ok(());
+        }
+
+        for secret in secrets.iter_mut() {
+            let signature = secret.metadata.get(SIGNATURE_FIELD).ok_or_else(|| SecretFetchError::SignatureError(format!(
+                "Secret '{}' is unsigned and the response carries no {} header", secret.key, SIGNATURE_HEADER
+            )))?;
+            let signer = self.verify(&signed_secret_message(&secret.key, &secret.value)?, signature)
+                .map_err(|e| SecretFetchError::SignatureError(format!("Secret '{}' rejected: {}", secret.key, e)))?;
+            secret.metadata.insert("signed_by".to_string(), signer);
+        }
+        Ok(())
+    }
+
+    /// Key ids (first 8 bytes of the SHA-256 of the raw public key) of all keys
+    pub fn key_ids(&self) -> Vec<String> {
+        self.keys.iter().map(key_id).collect()
+    }
+
+    /// Number of trusted keys
+    pub fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    /// Whether no key is trusted
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+}
+
+fn key_id(key: &VerifyingKey) -> String {
+    format_key_id(&Sha256::digest(key.as_bytes())[..8])
+}
+
+fn list_items(list: &str) -> impl Iterator<Item = &str> {
+    list.split(',').map(str::trim).filter(|item| !item.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use ed25519_dalek::pkcs8::{EncodePublicKey, spki::der::pem::LineEnding};
+    use rand::rngs::OsRng;
+
+    fn fetched(key: &str, value: &str, signature: Option<String>) -> FetchedSecret {
+        let mut metadata = HashMap::new();
+        if let Some(signature) = signature {
+            metadata.insert(SIGNATURE_FIELD.to_string(), signature);
+        }
+        FetchedSecret {
+            key: key.to_string(),
+            value: Zeroizing::new(value.to_string()),
+            source_url: "test://url".to_string(),
+            metadata,
+        }
+    }
+
+    #[test]
+    fn test_per_secret_signatures() {
+        let service = SigningKey::generate(&mut OsRng);
+        let attacker = SigningKey::generate(&mut OsRng);
+
+        let mut trusted = TrustedSigningKeys::new();
+        trusted.add_pem(&service.verifying_key().to_public_key_pem(LineEnding::LF).unwrap(), "test").unwrap();
+        trusted.add_base64(&general_purpose::STANDARD.encode(service.verifying_key().as_bytes())).unwrap();
+        assert_eq!(trusted.len(), 1);
+
+        let mut secrets = vec![fetched("api_key", "sk-123", Some(sign_secret(&service, "api_key", "sk-123").unwrap()))];
+        trusted.verify_response(b"ignored", None, &mut secrets).unwrap();
+        assert_eq!(secrets[0].metadata.get("signed_by"), Some(&trusted.key_ids()[0]));
+
+        // Swapped value, moved signature, foreign key and missing signature are all rejected
+        let signature = sign_secret(&service, "api_key", "sk-123").unwrap();
+        for mut secret in [
+            fetched("api_key", "sk-evil", Some(signature.clone())),
+            fetched("db_password", "sk-123", Some(signature)),
+            fetched("api_key", "sk-123", Some(sign_secret(&attacker, "api_key", "sk-123").unwrap())),
+            fetched("api_key", "sk-123", None),
+        ] {
+            let err = trusted.verify_response(b"ignored", None, std::slice::from_mut(&mut secret)).unwrap_err();
+            assert!(matches!(err, SecretFetchError::SignatureError(_)));
+        }
+    }
+
+    #[test]
+    fn test_payload_signature() {
+        let service = SigningKey::generate(&mut OsRng);
+        let mut trusted = TrustedSigningKeys::new();
+        trusted.add(service.verifying_key());
+
+        let body = br#"{"api_key": "sk-123"}"#;
+        let signature = general_purpose::STANDARD.encode(service.sign(body).to_bytes());
+
+        let mut secrets = vec![fetched("api_key", "sk-123", None)];
+        trusted.verify_response(body, Some(&signature), &mut secrets).unwrap();
+        assert!(secrets[0].metadata.contains_key("signed_by"));
+
+        let tampered = br#"{"api_key": "sk-999"}"#;
+        assert!(trusted.verify_response(tampered, Some(&signature), &mut secrets).is_err());
+        assert!(trusted.verify_response(body, Some("not base64!"), &mut secrets).is_err());
+    }
+}
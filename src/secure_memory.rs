@@ -0,0 +1,536 @@
+use std::alloc::{Layout, handle_alloc_error};
+use std::fmt;
+use std::io;
+use std::ops::Deref;
+use std::ptr::{self, NonNull};
+use std::sync::atomic::{AtomicBool, AtomicI32, AtomicUsize, Ordering};
+use zeroize::Zeroize;
+
+/// Bytes of secret buffers currently locked in RAM (including memfd_secret ones)
+static LOCKED_BYTES: AtomicUsize = AtomicUsize::new(0);
+
+/// Bytes of secret buffers in memfd_secret regions
+static SECRETMEM_BYTES: AtomicUsize = AtomicUsize::new(0);
+
+/// Bytes of secret buffers that could not be locked (swappable)
+static UNLOCKED_BYTES: AtomicUsize = AtomicUsize::new(0);
+
+/// Number of secret buffers that could not be locked
+static UNLOCKED_BUFFERS: AtomicUsize = AtomicUsize::new(0);
+
+/// Whether new buffers are allocated from memfd_secret
+static USE_MEMFD_SECRET: AtomicBool = AtomicBool::new(false);
+
+/// Number of buffers that fell back to mlock'd pages because memfd_secret failed
+static MEMFD_SECRET_FALLBACKS: AtomicUsize = AtomicUsize::new(0);
+
+/// errno of the last failed memfd_secret allocation, 0 if none failed
+static MEMFD_SECRET_ERRNO: AtomicI32 = AtomicI32::new(0);
+
+/// Where `SecureBuffer`s get their pages from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SecureMemoryBackend {
+    /// Anonymous pages locked with `mlock` and excluded from core dumps
+    Mlock,
+    /// `memfd_secret` pages (Linux 5.14+), which are locked and also removed
+    /// from the kernel direct map, so not even the kernel can read them
+    /// without first mapping them back in
+    MemfdSecret,
+}
+
+impl SecureMemoryBackend {
+    /// The backend new buffers are allocated from
+    pub fn active() -> Self {
+        if USE_MEMFD_SECRET.load(Ordering::Relaxed) {
+            Self::MemfdSecret
+        } else {
+            Self::Mlock
+        }
+    }
+}
+
+impl fmt::Display for SecureMemoryBackend {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Mlock => write!(f, "mlock"),
+            Self::MemfdSecret => write!(f, "memfd_secret"),
+        }
+    }
+}
+
+/// Allocate all further `SecureBuffer`s from memfd_secret
+///
+/// Probes the syscall with a one-page region first. When the kernel is older
+/// than 5.14, was booted without `secretmem.enable=1` (the default before
+/// 6.5) or a seccomp profile blocks the syscall, this fails and buffers keep
+/// using mlock'd pages. Buffers that already exist are not moved.
+pub fn enable_memfd_secret() -> io::Result<()> {
+    let probe = map_secretmem(page_size()).map_err(|e| io::Error::new(e.kind(), format!(
+        "memfd_secret is not available ({}); it needs Linux 5.14+, `secretmem.enable=1` on kernels before 6.5 \
+         and a seccomp profile that allows the syscall", e
+    )))?;
+    unsafe { libc::munmap(probe.as_ptr().cast(), page_size()) };
+
+    USE_MEMFD_SECRET.store(true, Ordering::Relaxed);
+    Ok(())
+}
+
+/// A read-only copy of secret data on pages of its own
+///
+/// The pages are locked in RAM (`mlock`), so they are never written to
+/// swap, and excluded from core dumps (`MADV_DONTDUMP`). They are zeroized
+/// and unmapped on drop. Every buffer takes at least one page, since locks
+/// are per page and do not nest, so two buffers must never share one.
+///
+/// After `enable_memfd_secret` the pages come from memfd_secret instead;
+/// should an allocation fail there, that buffer falls back to mlock'd pages.
+/// When `RLIMIT_MEMLOCK` is too low the buffer is still allocated, just not
+/// locked; `MemoryProtectionStatus::current` reports how much that affects.
+pub struct SecureBuffer {
+    ptr: NonNull<u8>,
+    len: usize,
+    /// Length of the mapping; 0 for empty buffers, which map nothing
+    mapped_len: usize,
+    backend: SecureMemoryBackend,
+    locked: bool,
+}
+
+// The buffer is never mutated after construction
+unsafe impl Send for SecureBuffer {}
+unsafe impl Sync for SecureBuffer {}
+
+impl SecureBuffer {
+    /// Copy `data` into a new buffer from the active backend
+    pub fn from_slice(data: &[u8]) -> Self {
+        Self::with_backend(data, SecureMemoryBackend::active())
+    }
+
+    /// Copy `data` into a new buffer from `backend`, falling back to mlock'd
+    /// pages when memfd_secret fails
+    pub fn with_backend(data: &[u8], backend: SecureMemoryBackend) -> Self {
+        if data.is_empty() {
+            return Self::default();
+        }
+
+        let mapped_len = data.len().div_ceil(page_size()) * page_size();
+        let secretmem = match backend {
+            SecureMemoryBackend::MemfdSecret => match map_secretmem(mapped_len) {
+                Ok(mapping) => Some(mapping),
+                Err(e) => {
+                    MEMFD_SECRET_FALLBACKS.fetch_add(1, Ordering::Relaxed);
+                    MEMFD_SECRET_ERRNO.store(e.raw_os_error().unwrap_or(libc::EIO), Ordering::Relaxed);
+                    None
+                }
+            },
+            SecureMemoryBackend::Mlock => None,
+        };
+
+        let buffer = match secretmem {
+            // memfd_secret pages are locked by the kernel
+            Some(ptr) => {
+                LOCKED_BYTES.fetch_add(mapped_len, Ordering::Relaxed);
+                SECRETMEM_BYTES.fetch_add(mapped_len, Ordering::Relaxed);
+                Self { ptr, len: data.len(), mapped_len, backend: SecureMemoryBackend::MemfdSecret, locked: true }
+            }
+            None => {
+                let ptr = map_anonymous(mapped_len);
+                // Lock before the secret is copied in
+                let locked = unsafe { libc::mlock(ptr.as_ptr().cast(), mapped_len) } == 0;
+                if locked {
+                    LOCKED_BYTES.fetch_add(mapped_len, Ordering::Relaxed);
+                } else {
+                    UNLOCKED_BYTES.fetch_add(mapped_len, Ordering::Relaxed);
+                    UNLOCKED_BUFFERS.fetch_add(1, Ordering::Relaxed);
+                }
+                Self { ptr, len: data.len(), mapped_len, backend: SecureMemoryBackend::Mlock, locked }
+            }
+        };
+
+        unsafe { ptr::copy_nonoverlapping(data.as_ptr(), buffer.ptr.as_ptr(), data.len()) };
+        buffer
+    }
+
+    /// The secret data
+    pub fn as_slice(&self) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(self.ptr.as_ptr(), self.len) }
+    }
+
+    /// Length of the secret data
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Whether the buffer is empty
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Whether the pages are locked in RAM (empty buffers count as locked)
+    pub fn is_locked(&self) -> bool {
+        self.locked
+    }
+
+    /// The backend the pages actually came from
+    pub fn backend(&self) -> SecureMemoryBackend {
+        self.backend
+    }
+}
+
+/// Map `len` bytes of anonymous memory, excluded from core dumps
+fn map_anonymous(len: usize) -> NonNull<u8> {
+    let mapping = unsafe {
+        libc::mmap(
+            ptr::null_mut(),
+            len,
+            libc::PROT_READ | libc::PROT_WRITE,
+            libc::MAP_PRIVATE | libc::MAP_ANONYMOUS,
+            -1,
+            0,
+        )
+    };
+    if mapping == libc::MAP_FAILED {
+        handle_alloc_error(Layout::from_size_align(len, page_size()).expect("page-sized layout"));
+    }
+
+    #[cfg(target_os = "linux")]
+    unsafe {
+        libc::madvise(mapping, len, libc::MADV_DONTDUMP);
+    }
+    NonNull::new(mapping.cast::<u8>()).expect("mmap does not return null on success")
+}
+
+/// Map `len` bytes of a new memfd_secret region
+///
+/// The file descriptor is closed right away; the mapping keeps the region
+/// alive until it is unmapped.
+#[cfg(target_os = "linux")]
+fn map_secretmem(len: usize) -> io::Result<NonNull<u8>> {
+    let fd = unsafe { libc::syscall(libc::SYS_memfd_secret, libc::O_CLOEXEC) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let fd = fd as libc::c_int;
+
+    let mapping = unsafe {
+        if libc::ftruncate(fd, len as libc::off_t) != 0 {
+            let error = io::Error::last_os_error();
+            libc::close(fd);
+            return Err(error);
+        }
+        let mapping = libc::mmap(ptr::null_mut(), len, libc::PROT_READ | libc::PROT_WRITE, libc::MAP_SHARED, fd, 0);
+        let error = io::Error::last_os_error();
+        libc::close(fd);
+        if mapping == libc::MAP_FAILED {
+            return Err(error);
+        }
+        libc::madvise(mapping, len, libc::MADV_DONTDUMP);
+        mapping
+    };
+    Ok(NonNull::new(mapping.cast::<u8>()).expect("mmap does not return null on success"))
+}
+
+/// memfd_secret only exists on Linux
+#[cfg(not(target_os = "linux"))]
+fn map_secretmem(_len: usize) -> io::Result<NonNull<u8>> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "memfd_secret is Linux-only"))
+}
+
+impl Default for SecureBuffer {
+    fn default() -> Self {
+        Self { ptr: NonNull::dangling(), len: 0, mapped_len: 0, backend: SecureMemoryBackend::Mlock, locked: true }
+    }
+}
+
+impl Deref for SecureBuffer {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        self.as_slice()
+    }
+}
+
+impl Clone for SecureBuffer {
+    fn clone(&self) -> Self {
+        Self::with_backend(self.as_slice(), self.backend)
+    }
+}
+
+impl fmt::Debug for SecureBuffer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let state = match self.backend {
+            SecureMemoryBackend::MemfdSecret => "memfd_secret",
+            SecureMemoryBackend::Mlock if self.locked => "locked",
+            SecureMemoryBackend::Mlock => "unlocked",
+        };
+        write!(f, "SecureBuffer({} bytes, {})", self.len, state)
+    }
+}
+
+impl Drop for SecureBuffer {
+    fn drop(&mut self) {
+        if self.mapped_len == 0 {
+            return;
+        }
+
+        let mapping = unsafe { std::slice::from_raw_parts_mut(self.ptr.as_ptr(), self.mapped_len) };
+        mapping.zeroize();
+
+        let mapping = self.ptr.as_ptr().cast::<libc::c_void>();
+        unsafe {
+            if self.locked && self.backend == SecureMemoryBackend::Mlock {
+                libc::munlock(mapping, self.mapped_len);
+            }
+            libc::munmap(mapping, self.mapped_len);
+        }
+
+        if self.backend == SecureMemoryBackend::MemfdSecret {
+            SECRETMEM_BYTES.fetch_sub(self.mapped_len, Ordering::Relaxed);
+        }
+        if self.locked {
+            LOCKED_BYTES.fetch_sub(self.mapped_len, Ordering::Relaxed);
+        } else {
+            UNLOCKED_BYTES.fetch_sub(self.mapped_len, Ordering::Relaxed);
+            UNLOCKED_BUFFERS.fetch_sub(1, Ordering::Relaxed);
+        }
+    }
+}
+
+fn page_size() -> usize {
+    match unsafe { libc::sysconf(libc::_SC_PAGESIZE) } {
+        size if size > 0 => size as usize,
+        _ => 4096,
+    }
+}
+
+/// Mark the process non-dumpable
+///
+/// With `PR_SET_DUMPABLE=0` the kernel writes no core dump on a crash and
+/// other processes of the same user can neither ptrace it nor read its
+/// memory through `/proc/<pid>/mem`. Call this at startup, before any secret
+/// is loaded.
+#[cfg(target_os = "linux")]
+pub fn disable_core_dumps() -> io::Result<()> {
+    if unsafe { libc::prctl(libc::PR_SET_DUMPABLE, 0, 0, 0, 0) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Mark the process non-dumpable (only supported on Linux)
+#[cfg(not(target_os = "linux"))]
+pub fn disable_core_dumps() -> io::Result<()> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "PR_SET_DUMPABLE is Linux-only"))
+}
+
+/// Whether the process is currently dumpable
+#[cfg(target_os = "linux")]
+pub fn is_dumpable() -> bool {
+    unsafe { libc::prctl(libc::PR_GET_DUMPABLE, 0, 0, 0, 0) != 0 }
+}
+
+/// Whether the process is currently dumpable (always assumed on other systems)
+#[cfg(not(target_os = "linux"))]
+pub fn is_dumpable() -> bool {
+    true
+}
+
+/// The soft `RLIMIT_MEMLOCK` in bytes, `None` when unlimited
+pub fn memlock_limit() -> io::Result<Option<u64>> {
+    let mut limit = libc::rlimit { rlim_cur: 0, rlim_max: 0 };
+    if unsafe { libc::getrlimit(libc::RLIMIT_MEMLOCK, &mut limit) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    #[allow(clippy::unnecessary_cast)] // rlim_t is not u64 on every platform
+    Ok((limit.rlim_cur != libc::RLIM_INFINITY).then_some(limit.rlim_cur as u64))
+}
+
+/// Snapshot of how well secret memory is protected
+#[derive(Debug, Clone, PartialEq)]
+pub struct MemoryProtectionStatus {
+    /// Backend new secret buffers are allocated from
+    pub backend: SecureMemoryBackend,
+    /// Bytes of secret buffers locked in RAM, including memfd_secret ones
+    pub locked_bytes: usize,
+    /// Bytes of secret buffers in memfd_secret regions
+    pub secretmem_bytes: usize,
+    /// Number of buffers that fell back to mlock'd pages because memfd_secret failed
+    pub memfd_secret_fallbacks: usize,
+    /// Why memfd_secret last failed, if it did
+    pub memfd_secret_error: Option<String>,
+    /// Bytes of secret buffers that could not be locked
+    pub unlocked_bytes: usize,
+    /// Number of secret buffers that could not be locked
+    pub unlocked_buffers: usize,
+    /// Soft `RLIMIT_MEMLOCK` in bytes, `None` when unlimited or unknown
+    pub memlock_limit: Option<u64>,
+    /// Why `RLIMIT_MEMLOCK` could not be read, if it could not
+    pub memlock_limit_error: Option<String>,
+    /// Whether the process can still be core dumped or ptraced
+    pub dumpable: bool,
+}
+
+impl MemoryProtectionStatus {
+    /// Current status of all live `SecureBuffer`s
+    pub fn current() -> Self {
+        let errno = MEMFD_SECRET_ERRNO.load(Ordering::Relaxed);
+        let memlock = memlock_limit();
+        Self {
+            backend: SecureMemoryBackend::active(),
+            locked_bytes: LOCKED_BYTES.load(Ordering::Relaxed),
+            secretmem_bytes: SECRETMEM_BYTES.load(Ordering::Relaxed),
+            memfd_secret_fallbacks: MEMFD_SECRET_FALLBACKS.load(Ordering::Relaxed),
+            memfd_secret_error: (errno != 0).then(|| io::Error::from_raw_os_error(errno).to_string()),
+            unlocked_bytes: UNLOCKED_BYTES.load(Ordering::Relaxed),
+            unlocked_buffers: UNLOCKED_BUFFERS.load(Ordering::Relaxed),
+            memlock_limit: memlock.as_ref().ok().copied().flatten(),
+            memlock_limit_error: memlock.err().map(|e| e.to_string()),
+            dumpable: is_dumpable(),
+        }
+    }
+
+    /// Whether every secret buffer is locked and the process is not dumpable
+    pub fn is_fully_protected(&self) -> bool {
+        self.unlocked_buffers == 0 && !self.dumpable
+    }
+
+    fn memlock_limit_text(&self) -> String {
+        match (self.memlock_limit, &self.memlock_limit_error) {
+            (_, Some(_)) => "unknown".to_string(),
+            (Some(limit), None) => format_kib(limit),
+            (None, None) => "unlimited".to_string(),
+        }
+    }
+
+    /// Human-readable problems, with how to fix them
+    pub fn warnings(&self) -> Vec<String> {
+        let mut warnings = Vec::new();
+
+        if self.unlocked_buffers > 0 {
+            let limit = self.memlock_limit_text();
+            warnings.push(format!(
+                "Could not lock {} secret buffer(s) ({}) in RAM, they may be swapped to disk: RLIMIT_MEMLOCK is {}, SecretFS needs at least {}. \
+                 Raise it with `ulimit -l`, `docker run --ulimit memlock=-1` or grant CAP_IPC_LOCK",
+                self.unlocked_buffers,
+                format_kib(self.unlocked_bytes as u64),
+                limit,
+                format_kib((self.locked_bytes + self.unlocked_bytes) as u64),
+            ));
+        }
+
+        if self.memfd_secret_fallbacks > 0 {
+            warnings.push(format!(
+                "memfd_secret failed for {} secret buffer(s) ({}), they use mlock'd pages instead",
+                self.memfd_secret_fallbacks,
+                self.memfd_secret_error.as_deref().unwrap_or("unknown error"),
+            ));
+        }
+
+        if self.dumpable {
+            warnings.push("The process is dumpable: a crash may write secrets into a core dump".to_string());
+        }
+
+        warnings
+    }
+}
+
+impl fmt::Display for MemoryProtectionStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}, {} locked in RAM", self.backend, format_kib(self.locked_bytes as u64))?;
+        if self.secretmem_bytes > 0 {
+            write!(f, ", {} outside the kernel direct map", format_kib(self.secretmem_bytes as u64))?;
+        }
+        if self.unlocked_bytes > 0 {
+            write!(f, ", {} NOT locked", format_kib(self.unlocked_bytes as u64))?;
+        }
+        write!(f, " (RLIMIT_MEMLOCK {}), ", self.memlock_limit_text())?;
+        write!(f, "{}", if self.dumpable { "core dumps possible" } else { "core dumps disabled" })
+    }
+}
+
+fn format_kib(bytes: u64) -> String {
+    format!("{} KiB", bytes.div_ceil(1024))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    /// VmFlags of the mapping containing `address`, from /proc/self/smaps
+    fn vm_flags(address: usize) -> Option<String> {
+        let smaps = fs::read_to_string("/proc/self/smaps").ok()?;
+        let mut in_mapping = false;
+        for line in smaps.lines() {
+            if let Some((start, end)) = line.split_whitespace().next().and_then(|range| range.split_once('-')) {
+                if let (Ok(start), Ok(end)) = (usize::from_str_radix(start, 16), usize::from_str_radix(end, 16)) {
+                    in_mapping = (start..end).contains(&address);
+                    continue;
+                }
+            }
+            if in_mapping {
+                if let Some(flags) = line.strip_prefix("VmFlags:") {
+                    return Some(flags.trim().to_string());
+                }
+            }
+        }
+        None
+    }
+
+    #[test]
+    fn test_secure_buffer() {
+        let buffer = SecureBuffer::from_slice(b"top secret");
+        assert_eq!(buffer.as_slice(), b"top secret");
+        assert_eq!(&*buffer.clone(), b"top secret");
+        assert!(SecureBuffer::from_slice(b"").is_empty());
+        assert!(format!("{:?}", buffer).starts_with("SecureBuffer(10 bytes"));
+
+        // Linux reports the flags of every mapping: dd = MADV_DONTDUMP, lo = mlock
+        if let Some(flags) = vm_flags(buffer.as_ptr() as usize) {
+            let flags: Vec<&str> = flags.split_whitespace().collect();
+            assert!(flags.contains(&"dd"), "{:?}", flags);
+            assert_eq!(flags.contains(&"lo"), buffer.is_locked(), "{:?}", flags);
+        }
+
+        let status = MemoryProtectionStatus::current();
+        if buffer.is_locked() {
+            assert!(status.locked_bytes >= page_size());
+        } else {
+            assert!(status.warnings()[0].contains("RLIMIT_MEMLOCK"));
+        }
+    }
+
+    #[test]
+    fn test_unknown_memlock_limit() {
+        let mut status = MemoryProtectionStatus::current();
+        status.unlocked_buffers = 1;
+        status.memlock_limit = None;
+        status.memlock_limit_error = Some("Operation not permitted".to_string());
+        assert!(status.warnings()[0].contains("RLIMIT_MEMLOCK is unknown"), "{:?}", status.warnings());
+        assert!(status.to_string().contains("(RLIMIT_MEMLOCK unknown)"), "{}", status);
+
+        status.memlock_limit_error = None;
+        assert!(status.warnings()[0].contains("RLIMIT_MEMLOCK is unlimited"));
+    }
+
+    #[test]
+    fn test_memfd_secret_backend() {
+        let buffer = SecureBuffer::with_backend(b"top secret", SecureMemoryBackend::MemfdSecret);
+        assert_eq!(buffer.as_slice(), b"top secret");
+        assert!(buffer.is_locked() || buffer.backend() == SecureMemoryBackend::Mlock);
+
+        let status = MemoryProtectionStatus::current();
+        match buffer.backend() {
+            SecureMemoryBackend::MemfdSecret => {
+                assert_eq!(buffer.clone().backend(), SecureMemoryBackend::MemfdSecret);
+                assert!(format!("{:?}", buffer).ends_with("memfd_secret)"));
+                assert!(status.secretmem_bytes >= page_size());
+                assert!(status.to_string().contains("outside the kernel direct map"));
+            }
+            // Kernels without memfd_secret fall back to mlock'd pages and say so
+            SecureMemoryBackend::Mlock => {
+                assert!(status.memfd_secret_fallbacks > 0);
+                assert!(status.warnings().iter().any(|warning| warning.contains("memfd_secret failed")));
+            }
+        }
+    }
+}
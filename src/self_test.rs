@@ -0,0 +1,211 @@
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use aes_gcm::aead::{Aead, KeyInit, Payload};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+use hkdf::Hkdf;
+use sha2::Sha256;
+use x25519_dalek::{PublicKey, StaticSecret};
+use crate::encryption::{EncryptionError, SecretCipher};
+
+/// Name of the secret the round-trip self-test encrypts
+pub const SELF_TEST_NAME: &str = "secretfs-self-test";
+
+/// Content of the secret the round-trip self-test encrypts
+pub const SELF_TEST_PLAINTEXT: &[u8] = b"SecretFS cipher self-test: if you can read this outside SecretFS, encryption is broken";
+
+/// Primitives the built-in ciphers are made of
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Primitive {
+    /// HKDF-SHA256 (per-secret subkeys, X25519 key wrapping)
+    HkdfSha256,
+    /// AES-256-GCM (`AesGcmCipher`, RSA envelopes)
+    Aes256Gcm,
+    /// XChaCha20-Poly1305 (`XChaCha20Cipher`, X25519 payloads)
+    XChaCha20Poly1305,
+    /// X25519 Diffie-Hellman (`X25519Cipher`)
+    X25519,
+}
+
+impl Primitive {
+    /// Name of the primitive for error messages
+    pub fn name(self) -> &'static str {
+        match self {
+            Primitive::HkdfSha256 => "HKDF-SHA256",
+            Primitive::Aes256Gcm => "AES-256-GCM",
+            Primitive::XChaCha20Poly1305 => "XChaCha20-Poly1305",
+            Primitive::X25519 => "X25519",
+        }
+    }
+
+    /// Check the primitive against its published test vector
+    pub fn known_answer_test(self) -> Result<(), EncryptionError> {
+        let passed = match self {
+            Primitive::HkdfSha256 => hkdf_sha256_known_answer(),
+            Primitive::Aes256Gcm => aes_256_gcm_known_answer(),
+            Primitive::XChaCha20Poly1305 => xchacha20_poly1305_known_answer(),
+            Primitive::X25519 => x25519_known_answer(),
+        };
+
+        if passed {
+            Ok(())
+        } else {
+            Err(EncryptionError::SelfTestFailed(format!("{} does not match its known-answer test vector", self.name())))
+        }
+    }
+}
+
+/// Run the known-answer tests of `primitives`
+pub fn known_answer_tests(primitives: &[Primitive]) -> Result<(), EncryptionError> {
+    primitives.iter().try_for_each(|primitive| primitive.known_answer_test())
+}
+
+/// Encrypt a test secret with `cipher` and check that it decrypts again
+pub fn round_trip_self_test<C: SecretCipher + ?Sized>(cipher: &C) -> Result<(), EncryptionError> {
+    let ciphertext = cipher.encrypt_secret(SELF_TEST_NAME, SELF_TEST_PLAINTEXT)
+        .map_err(|e| EncryptionError::SelfTestFailed(format!("Encrypting the test secret failed: {}", e)))?;
+    let decrypted = cipher.decrypt_secret(SELF_TEST_NAME, &ciphertext)
+        .map_err(|e| EncryptionError::SelfTestFailed(format!("Decrypting the test secret failed: {}", e)))?;
+
+    if decrypted != SELF_TEST_PLAINTEXT {
+        return Err(EncryptionError::SelfTestFailed("The test secret does not decrypt to what was encrypted".to_string()));
+    }
+
+    // SecretFS serves reads through the range decryption
+    let range = cipher.decrypt_secret_range(SELF_TEST_NAME, &ciphertext, 10, 20)
+        .map_err(|e| EncryptionError::SelfTestFailed(format!("Decrypting part of the test secret failed: {}", e)))?;
+    if range != SELF_TEST_PLAINTEXT[10..30] {
+        return Err(EncryptionError::SelfTestFailed("Part of the test secret does not decrypt to what was encrypted".to_string()));
+    }
+    Ok(())
+}
+
+/// Encrypt the test secret with `encrypt` and check the plaintext does not show
+///
+/// For ciphers that only encrypt, since the private key lives in the
+/// applications.
+pub fn encrypt_only_self_test(encrypt: impl FnOnce(&[u8]) -> Result<Vec<u8>, EncryptionError>) -> Result<(), EncryptionError> {
+    let ciphertext = encrypt(SELF_TEST_PLAINTEXT)
+        .map_err(|e| EncryptionError::SelfTestFailed(format!("Encrypting the test secret failed: {}", e)))?;
+
+    if ciphertext.windows(SELF_TEST_PLAINTEXT.len()).any(|window| window == SELF_TEST_PLAINTEXT) {
+        return Err(EncryptionError::SelfTestFailed("The encrypted test secret contains its plaintext".to_string()));
+    }
+    Ok(())
+}
+
+/// RFC 5869, test case 1
+fn hkdf_sha256_known_answer() -> bool {
+    let ikm = [0x0b; 22];
+    let salt = unhex("000102030405060708090a0b0c");
+    let info = unhex("f0f1f2f3f4f5f6f7f8f9");
+    let expected = unhex("3cb25f25faacd57a90434f64d0362f2a2d2d0a90cf1a5a4c5db02d56ecc4c5bf34007208d5b887185865");
+
+    let mut okm = [0u8; 42];
+    Hkdf::<Sha256>::new(Some(&salt), &ikm).expand(&info, &mut okm).is_ok() && okm[..] == expected[..]
+}
+
+/// The GCM specification, test case 14 (256-bit zero key, one zero block)
+fn aes_256_gcm_known_answer() -> bool {
+    let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&[0u8; 32]));
+    let nonce = Nonce::from_slice(&[0u8; 12]);
+    let expected = unhex("cea7403d4d606b6e074ec5d3baf39d18d0d1c8a799996bf0265b98b5d48ab919");
+
+    sealed_and_opened(
+        cipher.encrypt(nonce, &[0u8; 16][..]),
+        |ciphertext| cipher.decrypt(nonce, ciphertext),
+        &expected,
+        &[0u8; 16],
+    )
+}
+
+/// draft-irtf-cfrg-xchacha-03, appendix A.3.1
+fn xchacha20_poly1305_known_answer() -> bool {
+    let key: Vec<u8> = (0x80..=0x9f).collect();
+    let nonce: Vec<u8> = (0x40..=0x57).collect();
+    let aad = unhex("50515253c0c1c2c3c4c5c6c7");
+    let plaintext = b"Ladies and Gentlemen of the class of '99: If I could offer you only one tip for the future, sunscreen would be it.";
+    let expected = unhex(concat!(
+        "bd6d179d3e83d43b9576579493c0e939572a1700252bfaccbed2902c21396cbb",
+        "731c7f1b0b4aa6440bf3a82f4eda7e39ae64c6708c54c216cb96b72e1213b452",
+        "2f8c9ba40db5d945b11b69b982c1bb9e3f3fac2bc369488f76b2383565d3fff9",
+        "21f9664c97637da9768812f615c68b13b52e",
+        "c0875924c1c7987947deafd8780acf49",
+    ));
+
+    let cipher = XChaCha20Poly1305::new(chacha20poly1305::Key::from_slice(&key));
+    let nonce = XNonce::from_slice(&nonce);
+    sealed_and_opened(
+        cipher.encrypt(nonce, Payload { msg: plaintext, aad: &aad }),
+        |ciphertext| cipher.decrypt(nonce, Payload { msg: ciphertext, aad: &aad }),
+        &expected,
+        plaintext,
+    )
+}
+
+/// RFC 7748, section 6.1
+fn x25519_known_answer() -> bool {
+    let alice: [u8; 32] = unhex("77076d0a7318a57d3c16c17251b26645df4c2f87ebc0992ab177fba51db92c2a").try_into().expect("32 bytes");
+    let bob_public: [u8; 32] = unhex("de9edb7d7b7dc1b4d35b61c2ece435373f8343c85b78674dadfc7e146f882b4f").try_into().expect("32 bytes");
+    let alice_public = unhex("8520f0098930a754748b7ddcb43ef75a0dbf3a0d26381af4eba4a98eaa9b4e6a");
+    let expected = unhex("4a5d9d5ba4ce2de1728e3bf480350f25e07e21c947d19e3376f09b3c1e161742");
+
+    let alice = StaticSecret::from(alice);
+    PublicKey::from(&alice).as_bytes()[..] == alice_public[..]
+        && alice.diffie_hellman(&PublicKey::from(bob_public)).as_bytes()[..] == expected[..]
+}
+
+/// Whether sealing produced `expected` and opening it gives back `plaintext`
+fn sealed_and_opened<E>(
+    sealed: Result<Vec<u8>, E>,
+    open: impl Fn(&[u8]) -> Result<Vec<u8>, E>,
+    expected: &[u8],
+    plaintext: &[u8],
+) -> bool {
+    matches!(sealed, Ok(ciphertext) if ciphertext == expected)
+        && matches!(open(expected), Ok(opened) if opened == plaintext)
+}
+
+/// Decode a hex test vector
+fn unhex(hex: &str) -> Vec<u8> {
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).expect("valid hex test vector"))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encryption::{AesGcmCipher, PlaintextCipher};
+
+    /// A cipher whose decrypt does not undo its encrypt
+    struct BrokenCipher;
+
+    impl SecretCipher for BrokenCipher {
+        fn encrypt(&self, plaintext: &[u8]) -> Result<Vec<u8>, EncryptionError> {
+            Ok(plaintext.iter().rev().copied().collect())
+        }
+
+        fn decrypt(&self, ciphertext: &[u8]) -> Result<Vec<u8>, EncryptionError> {
+            Ok(ciphertext.to_vec())
+        }
+    }
+
+    #[test]
+    fn test_known_answers() {
+        known_answer_tests(&[Primitive::HkdfSha256, Primitive::Aes256Gcm, Primitive::XChaCha20Poly1305, Primitive::X25519]).unwrap();
+    }
+
+    #[test]
+    fn test_round_trip_self_test() {
+        let cipher = AesGcmCipher::new(&[7u8; 32]).unwrap();
+        round_trip_self_test(&cipher).unwrap();
+        encrypt_only_self_test(|plaintext| cipher.encrypt_secret(SELF_TEST_NAME, plaintext)).unwrap();
+        round_trip_self_test(&PlaintextCipher::new()).unwrap();
+
+        // Encrypt-only ciphers must not leak the plaintext
+        assert!(encrypt_only_self_test(|plaintext| PlaintextCipher::new().encrypt(plaintext)).is_err());
+
+        let err = round_trip_self_test(&BrokenCipher).unwrap_err();
+        assert!(matches!(err, EncryptionError::SelfTestFailed(_)));
+    }
+}
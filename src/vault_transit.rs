@@ -0,0 +1,462 @@
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, Payload};
+use base64::{Engine as _, engine::general_purpose};
+use rand::rngs::OsRng;
+use zeroize::Zeroizing;
+use crate::encryption::{AES_GCM_NONCE_LEN, AES_GCM_TAG_LEN, EncryptionError, SecretCipher, aead_associated_data};
+use crate::header::{CipherId, SecretHeader, format_key_id, symmetric_key_id};
+use crate::key_derivation::{SECRET_VERSION_LEN, SYMMETRIC_KEY_LEN, SecretKeys, split_secret_version};
+use crate::secret_fetcher::zeroize_json;
+use crate::self_test::{Primitive, SELF_TEST_NAME, known_answer_tests, round_trip_self_test};
+
+/// Mount path of the Transit secrets engine unless configured otherwise
+pub const DEFAULT_TRANSIT_MOUNT: &str = "transit";
+
+/// Timeout of a single request to the Transit API
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How long a failed unwrap is answered from the cache before Transit is asked again
+const UNWRAP_RETRY_DELAY: Duration = Duration::from_secs(30);
+
+/// Length of the wrapped data key length prefix
+const WRAPPED_KEY_LEN_LEN: usize = 2;
+
+/// Where to find the Transit API and how to authenticate to it
+pub struct TransitConfig {
+    pub address: String,
+    pub mount: String,
+    pub key_name: String,
+    pub token: Zeroizing<String>,
+    pub namespace: Option<String>,
+}
+
+impl TransitConfig {
+    /// Configuration for key `key_name` of the Transit engine at `address`
+    pub fn new(address: &str, key_name: &str, token: &str) -> Self {
+        Self {
+            address: address.trim_end_matches('/').to_string(),
+            mount: DEFAULT_TRANSIT_MOUNT.to_string(),
+            key_name: key_name.to_string(),
+            token: Zeroizing::new(token.to_string()),
+            namespace: None,
+        }
+    }
+
+    /// Use the Transit engine mounted at `mount` instead of `transit`
+    pub fn with_mount(mut self, mount: &str) -> Self {
+        self.mount = mount.trim_matches('/').to_string();
+        self
+    }
+
+    /// Send requests to Vault Enterprise namespace `namespace`
+    pub fn with_namespace(mut self, namespace: &str) -> Self {
+        self.namespace = Some(namespace.to_string());
+        self
+    }
+
+    /// Read the configuration from environment variables
+    ///
+    /// - `SECRETFS_TRANSIT_ADDR` (or `VAULT_ADDR`): base URL of the server
+    /// - `SECRETFS_TRANSIT_KEY`: name of the Transit key wrapping data keys
+    /// - `SECRETFS_TRANSIT_TOKEN_FILE`, `SECRETFS_TRANSIT_TOKEN` or `VAULT_TOKEN`
+    /// - `SECRETFS_TRANSIT_MOUNT`: engine mount path, `transit` by default
+    /// - `VAULT_NAMESPACE`: optional namespace
+    pub fn from_env() -> Result<Self, EncryptionError> {
+        let address = env::var("SECRETFS_TRANSIT_ADDR")
+            .or_else(|_| env::var("VAULT_ADDR"))
+            .map_err(|_| EncryptionError::InvalidKey("SECRETFS_TRANSIT_ADDR (or VAULT_ADDR) is not set".to_string()))?;
+        if !address.starts_with("http://") && !address.starts_with("https://") {
+            return Err(EncryptionError::InvalidKey(format!("Invalid Transit address: {}", address)));
+        }
+
+        let key_name = env::var("SECRETFS_TRANSIT_KEY")
+            .map_err(|_| EncryptionError::InvalidKey("SECRETFS_TRANSIT_KEY is not set".to_string()))?;
+
+        let token = match env::var("SECRETFS_TRANSIT_TOKEN_FILE") {
+            Ok(token_path) => Zeroizing::new(fs::read_to_string(&token_path)
+                .map_err(|e| EncryptionError::InvalidKey(format!("Failed to read token file {}: {}", token_path, e)))?),
+            Err(_) => env::var("SECRETFS_TRANSIT_TOKEN")
+                .or_else(|_| env::var("VAULT_TOKEN"))
+                .map(Zeroizing::new)
+                .map_err(|_| EncryptionError::InvalidKey(
+                    "No Transit token configured. Set SECRETFS_TRANSIT_TOKEN_FILE, SECRETFS_TRANSIT_TOKEN or VAULT_TOKEN".to_string()
+                ))?,
+        };
+
+        let mut config = Self::new(&address, &key_name, token.trim());
+        if let Ok(mount) = env::var("SECRETFS_TRANSIT_MOUNT") {
+            config = config.with_mount(&mount);
+        }
+        if let Ok(namespace) = env::var("VAULT_NAMESPACE") {
+            config = config.with_namespace(&namespace);
+        }
+        Ok(config)
+    }
+}
+
+/// A data key generated by Transit
+pub struct DataKey {
+    /// The key itself, zeroized on drop
+    pub plaintext: Zeroizing<Vec<u8>>,
+    /// The key wrapped by the Transit key (`vault:v1:...`)
+    pub ciphertext: String,
+}
+
+/// Client for the two Transit endpoints envelope encryption needs
+///
+/// `POST /v1/<mount>/datakey/plaintext/<key>` generates a data key and
+/// `POST /v1/<mount>/decrypt/<key>` unwraps one. The Transit key itself
+/// never leaves the server.
+pub struct TransitClient {
+    config: TransitConfig,
+    client: reqwest::Client,
+    runtime: tokio::runtime::Runtime,
+}
+
+impl TransitClient {
+    pub fn new(config: TransitConfig) -> Result<Self, EncryptionError> {
+        let client = reqwest::Client::builder()
+            .timeout(REQUEST_TIMEOUT)
+            .build()
+            .map_err(|e| EncryptionError::ConfigurationError(format!("Failed to create HTTP client: {}", e)))?;
+
+        // Requests are rare (startup, unknown data keys), a single-threaded runtime is enough
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .map_err(|e| EncryptionError::ConfigurationError(format!("Failed to start async runtime: {}", e)))?;
+
+        Ok(Self { config, client, runtime })
+    }
+
+    pub fn config(&self) -> &TransitConfig {
+        &self.config
+    }
+
+    /// Generate a 256-bit data key wrapped by the Transit key
+    pub fn generate_data_key(&self) -> Result<DataKey, EncryptionError> {
+        let body = serde_json::json!({ "bits": SYMMETRIC_KEY_LEN * 8 });
+        self.post("datakey/plaintext", &body, |data| {
+            Ok(DataKey {
+                plaintext: take_plaintext(data)?,
+                ciphertext: data.get("ciphertext")
+                    .and_then(|ciphertext| ciphertext.as_str())
+                    .ok_or("Response has no data.ciphertext")?
+                    .to_string(),
+            })
+        }).map_err(EncryptionError::InvalidKey)
+    }
+
+    /// Unwrap a data key generated by `generate_data_key`
+    pub fn decrypt_data_key(&self, ciphertext: &str) -> Result<Zeroizing<Vec<u8>>, EncryptionError> {
+        let body = serde_json::json!({ "ciphertext": ciphertext });
+        self.post("decrypt", &body, take_plaintext)
+            .map_err(EncryptionError::DecryptionFailed)
+    }
+
+    /// POST `body` to `operation` and hand the `data` of the response to `take`
+    ///
+    /// The response is zeroized once `take` has moved what it needs out.
+    fn post<T>(
+        &self,
+        operation: &str,
+        body: &serde_json::Value,
+        take: impl FnOnce(&mut serde_json::Value) -> Result<T, &'static str>,
+    ) -> Result<T, String> {
+        let url = format!("{}/v1/{}/{}/{}", self.config.address, self.config.mount, operation, self.config.key_name);
+        let context = format!("Transit {} with key '{}' at {}", operation, self.config.key_name, self.config.address);
+
+        let mut token = reqwest::header::HeaderValue::from_str(&self.config.token)
+            .map_err(|_| format!("{}: the token is not a valid header value", context))?;
+        token.set_sensitive(true);
+        let mut request = self.client.post(&url).header("X-Vault-Token", token).json(body);
+        if let Some(namespace) = &self.config.namespace {
+            request = request.header("X-Vault-Namespace", namespace);
+        }
+
+        let (status, response) = self.runtime.block_on(async {
+            let response = request.send().await?;
+            let status = response.status();
+            Ok::<_, reqwest::Error>((status, Zeroizing::new(Vec::from(response.bytes().await?))))
+        }).map_err(|e| format!("{}: {}", context, e))?;
+
+        let mut json: serde_json::Value = serde_json::from_slice(&response)
+            .map_err(|e| format!("{}: HTTP {}, invalid JSON: {}", context, status, e))?;
+        let result = if status.is_success() {
+            match json.get_mut("data") {
+                Some(data) => take(data).map_err(|e| format!("{}: {}", context, e)),
+                None => Err(format!("{}: Response has no data", context)),
+            }
+        } else {
+            Err(format!("{}: HTTP {}: {}", context, status, vault_errors(&json)))
+        };
+        zeroize_json(&mut json);
+        result
+    }
+}
+
+/// Move `data.plaintext` out of a response and decode it
+fn take_plaintext(data: &mut serde_json::Value) -> Result<Zeroizing<Vec<u8>>, &'static str> {
+    let plaintext = match data.get_mut("plaintext") {
+        Some(serde_json::Value::String(plaintext)) => Zeroizing::new(std::mem::take(plaintext)),
+        _ => return Err("Response has no data.plaintext"),
+    };
+    general_purpose::STANDARD.decode(plaintext.as_bytes())
+        .map(Zeroizing::new)
+        .map_err(|_| "data.plaintext is not valid base64")
+}
+
+/// The `errors` of a Vault error response
+fn vault_errors(json: &serde_json::Value) -> String {
+    let errors: Vec<&str> = json.get("errors")
+        .and_then(|errors| errors.as_array())
+        .map(|errors| errors.iter().filter_map(|error| error.as_str()).collect())
+        .unwrap_or_default();
+    if errors.is_empty() {
+        "no error message".to_string()
+    } else {
+        errors.join("; ")
+    }
+}
+
+/// Envelope encryption under a Vault Transit key
+///
+/// At startup the cipher asks Transit for a fresh data key and keeps the
+/// plaintext only as the master key of a `SecretKeys` (locked memory), from
+/// which it derives per-secret AES-256-GCM subkeys. Every stored secret
+/// carries the wrapped data key, so ciphertexts of other SecretFS instances
+/// are unwrapped through Transit on first use and cached the same way; a
+/// failed unwrap is retried after `UNWRAP_RETRY_DELAY` at the earliest.
+/// SecretFS never sees the Transit key, and the data key dies with the
+/// process.
+///
+/// The stored layout is `header || wrapped key length (u16) || wrapped key ||
+/// version || nonce || ciphertext || tag`; everything before the version is
+/// associated data.
+pub struct TransitCipher {
+    client: TransitClient,
+    wrapped_key: String,
+    keys: SecretKeys,
+    key_id: Vec<u8>,
+    unwrapped_keys: Mutex<HashMap<String, Arc<SecretKeys>>>,
+    /// Wrapped keys Transit failed to unwrap, with when and why
+    failed_unwraps: Mutex<HashMap<String, (Instant, EncryptionError)>>,
+}
+
+impl TransitCipher {
+    /// Generate a data key through `client`
+    pub fn new(client: TransitClient) -> Result<Self, EncryptionError> {
+        let data_key = client.generate_data_key()?;
+        if data_key.plaintext.len() != SYMMETRIC_KEY_LEN {
+            return Err(EncryptionError::InvalidKey(format!(
+                "Transit returned a {}-byte data key, expected {}", data_key.plaintext.len(), SYMMETRIC_KEY_LEN
+            )));
+        }
+        if data_key.ciphertext.len() > u16::MAX as usize {
+            return Err(EncryptionError::InvalidKey("Transit returned an oversized wrapped data key".to_string()));
+        }
+
+        Ok(Self {
+            key_id: symmetric_key_id(data_key.ciphertext.as_bytes()),
+            keys: SecretKeys::new(&data_key.plaintext),
+            wrapped_key: data_key.ciphertext,
+            client,
+            unwrapped_keys: Mutex::new(HashMap::new()),
+            failed_unwraps: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Create TransitCipher from the configuration in the environment
+    ///
+    /// See `TransitConfig::from_env`.
+    pub fn from_env() -> Result<Self, EncryptionError> {
+        Self::new(TransitClient::new(TransitConfig::from_env()?)?)
+    }
+
+    /// The data key of this instance, wrapped by the Transit key
+    pub fn wrapped_key(&self) -> &str {
+        &self.wrapped_key
+    }
+
+    /// Number of data keys of other instances unwrapped so far
+    pub fn unwrapped_key_count(&self) -> usize {
+        lock(&self.unwrapped_keys).len()
+    }
+
+    fn cipher(keys: &SecretKeys, name: &str, version: u64) -> Aes256Gcm {
+        let subkey = keys.derive(CipherId::VaultTransit, name, version);
+        Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(subkey.as_slice()))
+    }
+
+    fn seal(&self, plaintext: &[u8], name: &str) -> Result<Vec<u8>, EncryptionError> {
+        let mut output = SecretHeader::new(CipherId::VaultTransit, &self.key_id).encode();
+        output.extend_from_slice(&(self.wrapped_key.len() as u16).to_be_bytes());
+        output.extend_from_slice(self.wrapped_key.as_bytes());
+        let aad = aead_associated_data(&output, name);
+
+        let version = self.keys.next_version();
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let sealed = Self::cipher(&self.keys, name, version).encrypt(&nonce, Payload { msg: plaintext, aad: &aad })
+            .map_err(|_| EncryptionError::EncryptionFailed("AES-256-GCM encryption failed".to_string()))?;
+
+        output.extend_from_slice(&version.to_be_bytes());
+        output.extend_from_slice(&nonce);
+        output.extend_from_slice(&sealed);
+        Ok(output)
+    }
+
+    fn open(&self, ciphertext: &[u8], name: &str) -> Result<Vec<u8>, EncryptionError> {
+        let (header, header_len) = SecretHeader::parse(ciphertext)?;
+        if header.cipher_id != CipherId::VaultTransit {
+            return Err(EncryptionError::InvalidData(format!(
+                "Secret was encrypted with {}, not {}", header.cipher_id, CipherId::VaultTransit
+            )));
+        }
+
+        let body = &ciphertext[header_len..];
+        if body.len() < WRAPPED_KEY_LEN_LEN {
+            return Err(EncryptionError::InvalidData("Transit ciphertext is missing the wrapped data key".to_string()));
+        }
+        let wrapped_len = u16::from_be_bytes([body[0], body[1]]) as usize;
+        let envelope_len = header_len + WRAPPED_KEY_LEN_LEN + wrapped_len;
+        if ciphertext.len() < envelope_len + SECRET_VERSION_LEN + AES_GCM_NONCE_LEN + AES_GCM_TAG_LEN {
+            return Err(EncryptionError::InvalidData("Transit ciphertext is too short".to_string()));
+        }
+
+        let (envelope, body) = ciphertext.split_at(envelope_len);
+        let wrapped_key = std::str::from_utf8(&envelope[header_len + WRAPPED_KEY_LEN_LEN..])
+            .map_err(|_| EncryptionError::InvalidData("Wrapped data key is not UTF-8".to_string()))?;
+        let aad = aead_associated_data(envelope, name);
+        let (version, body) = split_secret_version(body)?;
+        let (nonce, sealed) = body.split_at(AES_GCM_NONCE_LEN);
+
+        self.with_data_key(wrapped_key, |keys| {
+            Self::cipher(keys, name, version).decrypt(Nonce::from_slice(nonce), Payload { msg: sealed, aad: &aad })
+                .map_err(|_| EncryptionError::DecryptionFailed(
+                    "AES-256-GCM authentication failed (wrong key or tampered data)".to_string()
+                ))
+        })?
+    }
+
+    /// Run `f` with the data key wrapped as `wrapped_key`, unwrapping it if needed
+    fn with_data_key<T>(&self, wrapped_key: &str, f: impl FnOnce(&SecretKeys) -> T) -> Result<T, EncryptionError> {
+        if wrapped_key == self.wrapped_key {
+            return Ok(f(&self.keys));
+        }
+        Ok(f(&*self.unwrapped_key(wrapped_key)?))
+    }
+
+    /// The data key wrapped as `wrapped_key`, unwrapped through Transit on first use
+    ///
+    /// Transit is called without holding a lock, so a slow unwrap does not
+    /// stall reads of other secrets. A failed unwrap is answered from the
+    /// cache for `UNWRAP_RETRY_DELAY` instead of calling Transit on every read.
+    fn unwrapped_key(&self, wrapped_key: &str) -> Result<Arc<SecretKeys>, EncryptionError> {
+        if let Some(keys) = lock(&self.unwrapped_keys).get(wrapped_key) {
+            return Ok(Arc::clone(keys));
+        }
+        if let Some((failed_at, error)) = lock(&self.failed_unwraps).get(wrapped_key) {
+            if failed_at.elapsed() < UNWRAP_RETRY_DELAY {
+                return Err(error.clone());
+            }
+        }
+
+        let keys = match self.unwrap_data_key(wrapped_key) {
+            Ok(keys) => keys,
+            Err(e) => {
+                lock(&self.failed_unwraps).insert(wrapped_key.to_string(), (Instant::now(), e.clone()));
+                return Err(e);
+            }
+        };
+        lock(&self.failed_unwraps).remove(wrapped_key);
+        Ok(Arc::clone(lock(&self.unwrapped_keys).entry(wrapped_key.to_string()).or_insert_with(|| Arc::new(keys))))
+    }
+
+    fn unwrap_data_key(&self, wrapped_key: &str) -> Result<SecretKeys, EncryptionError> {
+        let data_key = self.client.decrypt_data_key(wrapped_key)?;
+        if data_key.len() != SYMMETRIC_KEY_LEN {
+            return Err(EncryptionError::DecryptionFailed(format!(
+                "Transit unwrapped a {}-byte data key, expected {}", data_key.len(), SYMMETRIC_KEY_LEN
+            )));
+        }
+        Ok(SecretKeys::new(&data_key))
+    }
+}
+
+/// Lock `mutex`, recovering the data of a poisoned one
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+impl SecretCipher for TransitCipher {
+    fn encrypt(&self, plaintext: &[u8]) -> Result<Vec<u8>, EncryptionError> {
+        self.seal(plaintext, "")
+    }
+
+    fn decrypt(&self, ciphertext: &[u8]) -> Result<Vec<u8>, EncryptionError> {
+        self.open(ciphertext, "")
+    }
+
+    fn encrypt_secret(&self, name: &str, plaintext: &[u8]) -> Result<Vec<u8>, EncryptionError> {
+        self.seal(plaintext, name)
+    }
+
+    fn decrypt_secret(&self, name: &str, ciphertext: &[u8]) -> Result<Vec<u8>, EncryptionError> {
+        self.open(ciphertext, name)
+    }
+
+    fn cipher_info(&self) -> String {
+        let config = self.client.config();
+        format!(
+            "TransitCipher (Vault Transit key '{}' at {}, AES-256-GCM data key {}, name-bound)",
+            config.key_name, config.address, format_key_id(&self.key_id)
+        )
+    }
+
+    /// Also checks that Transit unwraps the data key again, so that a token
+    /// allowed to generate but not to decrypt data keys fails at startup
+    fn self_test(&self) -> Result<(), EncryptionError> {
+        known_answer_tests(&[Primitive::HkdfSha256, Primitive::Aes256Gcm])?;
+        round_trip_self_test(self)?;
+
+        let unwrapped = SecretKeys::new(&self.client.decrypt_data_key(&self.wrapped_key)
+            .map_err(|e| EncryptionError::SelfTestFailed(format!("Unwrapping the data key failed: {}", e)))?);
+        if unwrapped.derive(CipherId::VaultTransit, SELF_TEST_NAME, 0) != self.keys.derive(CipherId::VaultTransit, SELF_TEST_NAME, 0) {
+            return Err(EncryptionError::SelfTestFailed("Transit unwrapped a different data key".to_string()));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_transit_responses() {
+        let mut data = serde_json::json!({ "plaintext": "AAECAw==", "ciphertext": "vault:v1:abc" });
+        assert_eq!(take_plaintext(&mut data).unwrap().as_slice(), &[0, 1, 2, 3]);
+        assert_eq!(data["plaintext"], "");
+        assert!(take_plaintext(&mut serde_json::json!({ "plaintext": "not base64!" })).is_err());
+        assert!(take_plaintext(&mut serde_json::json!({})).is_err());
+
+        let errors = serde_json::json!({ "errors": ["permission denied", "1 error occurred"] });
+        assert_eq!(vault_errors(&errors), "permission denied; 1 error occurred");
+        assert_eq!(vault_errors(&serde_json::json!({})), "no error message");
+    }
+
+    #[test]
+    fn test_transit_config() {
+        let config = TransitConfig::new("http://127.0.0.1:8200/", "secretfs", "hvs.token")
+            .with_mount("/kms/transit/")
+            .with_namespace("team-a");
+        assert_eq!(config.address, "http://127.0.0.1:8200");
+        assert_eq!(config.mount, "kms/transit");
+        assert_eq!(config.namespace.as_deref(), Some("team-a"));
+    }
+}
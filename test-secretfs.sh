@@ -5,6 +5,9 @@
 
 set -e
 
+# Symmetric ciphers have no built-in key; use a throwaway one unless configured
+export SECRETFS_ENCRYPTION_KEY="${SECRETFS_ENCRYPTION_KEY:-$(openssl rand -base64 32)}"
+
 # Colors for output
 GREEN='\033[0;32m'
 BLUE='\033[0;34m'
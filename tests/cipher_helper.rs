@@ -0,0 +1,105 @@
+//! Runs the `external` cipher against the reference helper binary
+
+use std::process::Command;
+use std::time::{Duration, Instant};
+
+use ephemfs::encryption::{EncryptionError, SecretCipher};
+use ephemfs::external_cipher::ExternalCipher;
+
+const KEY: &str = "MDEyMzQ1Njc4OWFiY2RlZjAxMjM0NTY3ODlhYmNkZWY=";
+
+fn reference_helper() -> Command {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_secretfs-cipher-helper"));
+    command
+        .env_remove("SECRETFS_ENCRYPTION_KEY_FILE")
+        .env_remove("SECRETFS_ENCRYPTION_PASSPHRASE_FILE")
+        .env_remove("SECRETFS_ENCRYPTION_PASSPHRASE")
+        .env("SECRETFS_ENCRYPTION_KEY", KEY);
+    command
+}
+
+#[test]
+fn test_reference_helper_round_trip() {
+    let cipher = ExternalCipher::new(reference_helper).unwrap();
+    assert!(cipher.cipher_info().contains("reference helper"));
+    cipher.self_test().unwrap();
+
+    let ciphertext = cipher.encrypt_secret("api_key", b"sk-1234567890").unwrap();
+    assert!(!ciphertext.windows(13).any(|window| window == b"sk-1234567890"));
+    assert_eq!(cipher.decrypt_secret("api_key", &ciphertext).unwrap(), b"sk-1234567890");
+
+    // The helper binds ciphertexts to the secret name
+    let err = cipher.decrypt_secret("other_key", &ciphertext).unwrap_err();
+    assert!(matches!(err, EncryptionError::DecryptionFailed(_)));
+
+    // Helper errors leave it running for the next request
+    assert_eq!(cipher.decrypt_secret("api_key", &ciphertext).unwrap(), b"sk-1234567890");
+}
+
+#[test]
+fn test_helper_failures() {
+    let missing = ExternalCipher::new(|| Command::new("/nonexistent/secretfs-cipher-helper"));
+    assert!(matches!(missing, Err(EncryptionError::ConfigurationError(_))));
+
+    // The reference helper exits without a key, failing the handshake
+    let keyless = ExternalCipher::new(|| {
+        let mut command = reference_helper();
+        command.env_remove("SECRETFS_ENCRYPTION_KEY");
+        command
+    });
+    assert!(matches!(keyless, Err(EncryptionError::ConfigurationError(_))));
+
+    // A helper that does not speak the protocol
+    let silent = ExternalCipher::new(|| Command::new("true"));
+    assert!(matches!(silent, Err(EncryptionError::ConfigurationError(_))));
+}
+
+#[test]
+fn test_garbled_response_stops_helper() {
+    // Answers `api_key` with a malformed line followed by a valid response
+    let script = r#"
+        while IFS= read -r line; do
+            case "$line" in
+                operation=hello) reply='protocol=1' ;;
+                name=api_key) reply='garbage-line
+data=U0VDUkVULUZPUi1BUElfS0VZ' ;;
+                name=*) reply='data=ZnJlc2g=' ;;
+                '') printf '%s\n\n' "$reply" ;;
+            esac
+        done
+    "#;
+    let cipher = ExternalCipher::new(move || {
+        let mut command = Command::new("sh");
+        command.args(["-c", script]);
+        command
+    }).unwrap();
+
+    assert!(matches!(cipher.decrypt_secret("api_key", b"ciphertext"), Err(EncryptionError::DecryptionFailed(_))));
+    // The rest of that response must not be served as the next secret
+    assert_eq!(cipher.decrypt_secret("db_pass", b"ciphertext").unwrap(), b"fresh");
+}
+
+#[test]
+fn test_hung_helper_times_out() {
+    // Completes the handshake, then never answers
+    let script = r#"
+        while IFS= read -r line; do
+            case "$line" in
+                operation=hello) reply='protocol=1' ;;
+                '') [ -n "$reply" ] && printf '%s\n\n' "$reply"; reply='' ;;
+            esac
+        done
+    "#;
+    let cipher = ExternalCipher::new(move || {
+        let mut command = Command::new("sh");
+        command.args(["-c", script]);
+        command
+    }).unwrap().with_timeout(Duration::from_millis(200));
+
+    for _ in 0..2 {
+        let start = Instant::now();
+        let err = cipher.decrypt_secret("api_key", b"ciphertext").unwrap_err();
+        assert!(err.to_string().contains("did not answer"), "{}", err);
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+}
@@ -0,0 +1,46 @@
+//! Reads the private key passphrase from an inherited file descriptor
+//!
+//! The descriptor is process-wide state, so this runs in its own test binary.
+
+use std::env;
+use std::fs;
+use std::os::fd::{AsRawFd, IntoRawFd};
+
+use ephemfs::asymmetric_encryption::AsymmetricDecryption;
+use ephemfs::x25519_encryption::X25519KeyPair;
+use tempfile::TempDir;
+
+fn is_open(fd: i32) -> bool {
+    unsafe { libc::fcntl(fd, libc::F_GETFD) != -1 }
+}
+
+#[test]
+fn test_passphrase_fd_is_read_once_when_needed() {
+    let dir = TempDir::new().unwrap();
+    let path = |name: &str| dir.path().join(name).to_string_lossy().into_owned();
+    let key_pair = X25519KeyPair::generate();
+    key_pair.save_to_pem_files(&path("plain.pem"), &path("plain.pub")).unwrap();
+    key_pair.save_to_encrypted_pem_files(&path("protected.pem"), &path("protected.pub"), b"correct horse").unwrap();
+
+    fs::write(path("passphrase"), "correct horse\n").unwrap();
+    let fd = fs::File::open(path("passphrase")).unwrap().into_raw_fd();
+    for name in ["SECRETFS_PRIVATE_KEY_PEM", "SECRETFS_PRIVATE_KEY_PASSPHRASE", "SECRETFS_PRIVATE_KEY_PASSPHRASE_FILE"] {
+        env::remove_var(name);
+    }
+    env::set_var("SECRETFS_PRIVATE_KEY_PASSPHRASE_FD", fd.to_string());
+
+    // An unprotected key leaves the descriptor alone
+    env::set_var("SECRETFS_PRIVATE_KEY_FILE", path("plain.pem"));
+    AsymmetricDecryption::from_env().unwrap();
+    assert!(is_open(fd));
+
+    env::set_var("SECRETFS_PRIVATE_KEY_FILE", path("protected.pem"));
+    AsymmetricDecryption::from_env().unwrap();
+    assert!(!is_open(fd));
+
+    // Later loads use the passphrase read before, not whatever reuses the number
+    let reused = fs::File::open(path("plain.pub")).unwrap();
+    assert_eq!(reused.as_raw_fd(), fd);
+    AsymmetricDecryption::from_env().unwrap();
+    assert!(is_open(fd));
+}
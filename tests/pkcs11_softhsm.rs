@@ -0,0 +1,87 @@
+//! Runs the PKCS#11 key against a real SoftHSM2 token
+//!
+//! Needs `softhsm2-util` and the SoftHSM2 module; run with
+//! `cargo test --test pkcs11_softhsm -- --ignored`. Set `SOFTHSM2_MODULE`
+//! when the module is not in one of the usual places.
+
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+use ephemfs::asymmetric_encryption::{AsymmetricDecryption, AsymmetricEncryption, RsaKeyPair};
+
+const MODULE_PATHS: &[&str] = &[
+    "/usr/lib/softhsm/libsofthsm2.so",
+    "/usr/lib/x86_64-linux-gnu/softhsm/libsofthsm2.so",
+    "/usr/lib64/pkcs11/libsofthsm2.so",
+    "/usr/local/lib/softhsm/libsofthsm2.so",
+    "/opt/homebrew/lib/softhsm/libsofthsm2.so",
+];
+
+fn softhsm_module() -> String {
+    env::var("SOFTHSM2_MODULE").ok()
+        .or_else(|| MODULE_PATHS.iter().find(|path| Path::new(path).exists()).map(|path| path.to_string()))
+        .expect("SoftHSM2 module not found; set SOFTHSM2_MODULE")
+}
+
+fn softhsm2_util(args: &[&str]) {
+    let output = Command::new("softhsm2-util").args(args).output().expect("softhsm2-util not found");
+    assert!(output.status.success(), "softhsm2-util {:?}: {}", args, String::from_utf8_lossy(&output.stderr));
+}
+
+#[test]
+#[ignore = "needs SoftHSM2"]
+fn test_softhsm_token_key() {
+    let dir = tempfile::tempdir().unwrap();
+    let token_dir = dir.path().join("tokens");
+    fs::create_dir(&token_dir).unwrap();
+    let conf = dir.path().join("softhsm2.conf");
+    fs::write(&conf, format!("directories.tokendir = {}\nobjectstore.backend = file\n", token_dir.display())).unwrap();
+    env::set_var("SOFTHSM2_CONF", &conf);
+
+    // Import a key pair made here, so the test can check the token decrypts what the PEM key encrypts
+    let key_pair = RsaKeyPair::generate(2048).unwrap();
+    let private_key = dir.path().join("private.pem");
+    let public_key = dir.path().join("public.pem");
+    key_pair.save_to_pem_files(private_key.to_str().unwrap(), public_key.to_str().unwrap()).unwrap();
+    softhsm2_util(&["--init-token", "--free", "--label", "secretfs", "--pin", "1234", "--so-pin", "5678"]);
+    softhsm2_util(&[
+        "--import", private_key.to_str().unwrap(), "--token", "secretfs",
+        "--label", "app-key", "--id", "01", "--pin", "1234",
+    ]);
+
+    let pin_file = dir.path().join("pin");
+    fs::write(&pin_file, "1234\n").unwrap();
+    for name in ["SECRETFS_PRIVATE_KEY_PEM", "SECRETFS_PRIVATE_KEY_FILE", "SECRETFS_PUBLIC_KEY_PEM", "SECRETFS_PUBLIC_KEY_FILE", "SECRETFS_PUBLIC_KEY_FILES"] {
+        env::remove_var(name);
+    }
+    env::set_var("SECRETFS_PKCS11_MODULE", softhsm_module());
+    env::set_var("SECRETFS_PKCS11_TOKEN_LABEL", "secretfs");
+    env::set_var("SECRETFS_PKCS11_KEY_LABEL", "app-key");
+    env::set_var("SECRETFS_PKCS11_PIN_FILE", &pin_file);
+
+    let software = AsymmetricEncryption::new_with_public_key(key_pair.public_key.clone()).unwrap();
+    let token_encryption = AsymmetricEncryption::from_env().unwrap();
+    let token = AsymmetricDecryption::from_env().unwrap();
+    assert_eq!(token_encryption.key_id(), software.key_id());
+    assert!(token.decryption_info().contains("PKCS#11"));
+
+    for ciphertext in [
+        software.encrypt(b"sk-1234567890").unwrap(),
+        token_encryption.encrypt(b"sk-1234567890").unwrap(),
+        software.encrypt_chunked(b"sk-1234567890").unwrap(),
+    ] {
+        assert_eq!(token.decrypt(&ciphertext).unwrap(), b"sk-1234567890");
+    }
+
+    // The token stays logged in while a key uses it, so the wrong PIN is only checked after
+    drop(token);
+    fs::write(&pin_file, "0000\n").unwrap();
+    let err = AsymmetricDecryption::from_env().err().unwrap();
+    assert!(err.to_string().contains("CKR_PIN_INCORRECT"), "{}", err);
+
+    // Encrypting reads the public key object without logging in
+    env::remove_var("SECRETFS_PKCS11_PIN_FILE");
+    assert_eq!(AsymmetricEncryption::from_env().unwrap().key_id(), software.key_id());
+}
@@ -0,0 +1,167 @@
+//! Runs `TransitCipher` against a local mock of the Vault Transit API
+
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use base64::{Engine as _, engine::general_purpose};
+use ephemfs::encryption::{EncryptionError, SecretCipher};
+use ephemfs::vault_transit::{TransitCipher, TransitClient, TransitConfig};
+use rand::RngCore;
+
+const TOKEN: &str = "hvs.mock-token";
+const KEY_NAME: &str = "secretfs";
+
+/// Mock Transit engine: hands out random data keys and unwraps them again
+#[derive(Default)]
+struct MockTransit {
+    data_keys: Mutex<HashMap<String, Vec<u8>>>,
+    datakey_requests: AtomicUsize,
+    decrypt_requests: AtomicUsize,
+    /// Whether `decrypt` is denied, like a token without decrypt permission
+    deny_decrypt: bool,
+}
+
+impl MockTransit {
+    /// Serve HTTP on a random local port until the test process exits
+    fn start(self) -> (Arc<Self>, String) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = format!("http://{}", listener.local_addr().unwrap());
+        let transit = Arc::new(self);
+
+        let server = Arc::clone(&transit);
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                server.serve(stream);
+            }
+        });
+        (transit, address)
+    }
+
+    fn serve(&self, stream: TcpStream) {
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let mut request_line = String::new();
+        reader.read_line(&mut request_line).unwrap();
+
+        let mut headers = HashMap::new();
+        loop {
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            match line.trim_end().split_once(':') {
+                Some((name, value)) => headers.insert(name.to_lowercase(), value.trim().to_string()),
+                None => break,
+            };
+        }
+        let mut body = vec![0u8; headers.get("content-length").map_or(0, |length| length.parse().unwrap())];
+        reader.read_exact(&mut body).unwrap();
+
+        let path = request_line.split_whitespace().nth(1).unwrap_or_default().to_string();
+        let (status, response) = if headers.get("x-vault-token").map(String::as_str) != Some(TOKEN) {
+            (403, serde_json::json!({ "errors": ["permission denied"] }))
+        } else {
+            self.handle(&path, &serde_json::from_slice(&body).unwrap())
+        };
+
+        let response = response.to_string();
+        let mut stream = stream;
+        write!(
+            stream,
+            "HTTP/1.1 {} Mock\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            status, response.len(), response
+        ).unwrap();
+    }
+
+    fn handle(&self, path: &str, body: &serde_json::Value) -> (u16, serde_json::Value) {
+        if path == format!("/v1/transit/datakey/plaintext/{}", KEY_NAME) {
+            self.datakey_requests.fetch_add(1, Ordering::SeqCst);
+            assert_eq!(body["bits"], 256);
+
+            let mut data_key = vec![0u8; 32];
+            rand::thread_rng().fill_bytes(&mut data_key);
+            let mut data_keys = self.data_keys.lock().unwrap();
+            let ciphertext = format!("vault:v1:mock-{}", data_keys.len());
+            data_keys.insert(ciphertext.clone(), data_key.clone());
+            (200, serde_json::json!({ "data": {
+                "plaintext": general_purpose::STANDARD.encode(&data_key),
+                "ciphertext": ciphertext,
+                "key_version": 1,
+            }}))
+        } else if path == format!("/v1/transit/decrypt/{}", KEY_NAME) {
+            self.decrypt_requests.fetch_add(1, Ordering::SeqCst);
+            if self.deny_decrypt {
+                return (403, serde_json::json!({ "errors": ["1 error occurred:\n\t* permission denied\n\n"] }));
+            }
+            match self.data_keys.lock().unwrap().get(body["ciphertext"].as_str().unwrap_or_default()) {
+                Some(data_key) => (200, serde_json::json!({ "data": { "plaintext": general_purpose::STANDARD.encode(data_key) } })),
+                None => (400, serde_json::json!({ "errors": ["invalid ciphertext: unable to decrypt"] })),
+            }
+        } else {
+            (404, serde_json::json!({ "errors": [format!("no handler for route '{}'", path)] }))
+        }
+    }
+}
+
+fn transit_cipher(address: &str, token: &str) -> Result<TransitCipher, EncryptionError> {
+    TransitCipher::new(TransitClient::new(TransitConfig::new(address, KEY_NAME, token))?)
+}
+
+#[test]
+fn test_transit_envelope_encryption() {
+    let (transit, address) = MockTransit::default().start();
+
+    let cipher = transit_cipher(&address, TOKEN).unwrap();
+    cipher.self_test().unwrap();
+    assert!(cipher.cipher_info().contains(KEY_NAME));
+    assert_eq!(transit.datakey_requests.load(Ordering::SeqCst), 1);
+
+    let ciphertext = cipher.encrypt_secret("api_key", b"sk-1234567890").unwrap();
+    assert!(!ciphertext.windows(13).any(|window| window == b"sk-1234567890"));
+    assert_eq!(cipher.decrypt_secret("api_key", &ciphertext).unwrap(), b"sk-1234567890");
+    assert!(cipher.decrypt_secret("other_key", &ciphertext).is_err());
+
+    // The own data key is cached: only the self-test asked Transit to unwrap it
+    assert_eq!(transit.decrypt_requests.load(Ordering::SeqCst), 1);
+
+    // Another instance has its own data key and unwraps this one once
+    let other = transit_cipher(&address, TOKEN).unwrap();
+    assert_ne!(other.wrapped_key(), cipher.wrapped_key());
+    assert_eq!(other.decrypt_secret("api_key", &ciphertext).unwrap(), b"sk-1234567890");
+    assert_eq!(other.decrypt_secret("api_key", &ciphertext).unwrap(), b"sk-1234567890");
+    assert_eq!(other.unwrapped_key_count(), 1);
+    assert_eq!(transit.decrypt_requests.load(Ordering::SeqCst), 2);
+
+    // Tampering with the wrapped data key is detected
+    let mut tampered = ciphertext.clone();
+    let position = tampered.windows(9).position(|window| window == b"vault:v1:").unwrap();
+    tampered[position + 9] ^= 1;
+    assert!(other.decrypt_secret("api_key", &tampered).is_err());
+}
+
+#[test]
+fn test_transit_failures() {
+    let (_, address) = MockTransit::default().start();
+    let err = transit_cipher(&address, "hvs.wrong-token").err().unwrap();
+    assert!(err.to_string().contains("permission denied"), "{}", err);
+
+    // A token that cannot unwrap data keys fails the self-test, not the first read
+    let (_, address) = MockTransit { deny_decrypt: true, ..Default::default() }.start();
+    let cipher = transit_cipher(&address, TOKEN).unwrap();
+    assert!(matches!(cipher.self_test(), Err(EncryptionError::SelfTestFailed(_))));
+
+    // A failed unwrap is remembered instead of asking Transit on every read
+    let (transit, address) = MockTransit { deny_decrypt: true, ..Default::default() }.start();
+    let ciphertext = transit_cipher(&address, TOKEN).unwrap().encrypt_secret("api_key", b"sk-1234567890").unwrap();
+    let other = transit_cipher(&address, TOKEN).unwrap();
+    for _ in 0..3 {
+        let err = other.decrypt_secret("api_key", &ciphertext).unwrap_err();
+        assert!(err.to_string().contains("permission denied"), "{}", err);
+    }
+    assert_eq!(transit.decrypt_requests.load(Ordering::SeqCst), 1);
+    assert_eq!(other.unwrapped_key_count(), 0);
+
+    let unreachable = transit_cipher("http://127.0.0.1:1", TOKEN);
+    assert!(matches!(unreachable, Err(EncryptionError::InvalidKey(_))));
+}
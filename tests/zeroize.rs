@@ -0,0 +1,166 @@
+//! Checks that plaintext on the secret path is wiped before its memory is freed
+//!
+//! The global allocator scans every block freed on a scanning thread for a
+//! canary that only the secret values (and keys) under test contain. A block
+//! still holding the canary when it is freed is plaintext left on the heap.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+use std::fs;
+
+use ephemfs::asymmetric_encryption::{AsymmetricDecryption, AsymmetricEncryption, RsaKeyPair};
+use ephemfs::encryption::{AesGcmCipher, DefaultCipher, EphemeralCipher, PlaintextCipher, RsaCipher, SecretCipher, X25519Cipher, XChaCha20Cipher};
+use ephemfs::recipient_policy::RecipientPolicy;
+use ephemfs::secret_client::SecretClient;
+use ephemfs::secret_fetcher::HttpSecretFetcher;
+use ephemfs::x25519_encryption::{X25519Encryption, X25519KeyPair};
+use tempfile::TempDir;
+use zeroize::Zeroizing;
+
+const CANARY: &[u8] = b"zeroize-canary-";
+
+thread_local! {
+    static SCANNING: Cell<bool> = const { Cell::new(false) };
+    static LEAKS: Cell<usize> = const { Cell::new(0) };
+}
+
+struct ScanningAllocator;
+
+unsafe impl GlobalAlloc for ScanningAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        unsafe { System.alloc(layout) }
+    }
+
+    // `realloc` is left to the default, which frees the old block through here
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        if SCANNING.try_with(Cell::get).unwrap_or(false) {
+            let block = unsafe { std::slice::from_raw_parts(ptr, layout.size()) };
+            if block.windows(CANARY.len()).any(|window| window == CANARY) {
+                let _ = LEAKS.try_with(|leaks| leaks.set(leaks.get() + 1));
+            }
+        }
+        unsafe { System.dealloc(ptr, layout) }
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: ScanningAllocator = ScanningAllocator;
+
+/// Run `f`, dropping its result, and count the freed blocks still holding the canary
+fn leaks_while<R>(f: impl FnOnce() -> R) -> usize {
+    LEAKS.with(|leaks| leaks.set(0));
+    SCANNING.with(|scanning| scanning.set(true));
+    drop(f());
+    SCANNING.with(|scanning| scanning.set(false));
+    LEAKS.with(Cell::get)
+}
+
+/// A secret value, built on the heap like one read from the environment
+fn secret_value() -> Zeroizing<String> {
+    let mut value = Zeroizing::new(String::with_capacity(64));
+    value.push_str(std::str::from_utf8(CANARY).unwrap());
+    value.push_str("sk-live-0123456789");
+    value
+}
+
+fn round_trip(cipher: &dyn SecretCipher) {
+    let plaintext = secret_value();
+    let ciphertext = Zeroizing::new(cipher.encrypt_secret("api_key", plaintext.as_bytes()).unwrap());
+    let decrypted = Zeroizing::new(cipher.decrypt_secret("api_key", &ciphertext).unwrap());
+    assert_eq!(decrypted.as_slice(), plaintext.as_bytes());
+}
+
+#[test]
+fn test_detector_sees_plain_buffers() {
+    assert!(leaks_while(|| secret_value().to_string()) > 0);
+}
+
+#[test]
+fn test_symmetric_ciphers_leave_no_plaintext() {
+    let key = [7u8; 32];
+    let ciphers: Vec<Box<dyn SecretCipher>> = vec![
+        Box::new(DefaultCipher::from_key_bytes(&key)),
+        Box::new(AesGcmCipher::new(&key).unwrap()),
+        Box::new(XChaCha20Cipher::new(&key).unwrap()),
+        Box::new(EphemeralCipher::new()),
+        Box::new(PlaintextCipher::new()),
+    ];
+
+    for cipher in &ciphers {
+        assert_eq!(leaks_while(|| round_trip(cipher.as_ref())), 0, "{}", cipher.cipher_info());
+    }
+}
+
+#[test]
+fn test_symmetric_keys_are_wiped() {
+    // 32-byte master key carrying the canary
+    let key = || {
+        let mut key = Zeroizing::new(Vec::with_capacity(32));
+        key.extend_from_slice(CANARY);
+        key.resize(32, b'k');
+        key
+    };
+
+    assert_eq!(leaks_while(|| round_trip(&DefaultCipher::from_key_bytes(&key()))), 0);
+    assert_eq!(leaks_while(|| round_trip(&AesGcmCipher::new(&key()).unwrap())), 0);
+    assert_eq!(leaks_while(|| round_trip(&XChaCha20Cipher::new(&key()).unwrap())), 0);
+}
+
+#[test]
+fn test_secret_client_leaves_no_plaintext() {
+    let temp_dir = TempDir::new().unwrap();
+    let mount_path = temp_dir.path().to_str().unwrap();
+    let key = [9u8; 32];
+
+    let x25519 = X25519KeyPair::generate();
+    let rsa = RsaKeyPair::generate(1024).unwrap();
+    let x25519_cipher = X25519Cipher::with_encryption(X25519Encryption::new_with_public_key(x25519.public_key).unwrap());
+    let rsa_cipher = RsaCipher::with_recipients(
+        Some(AsymmetricEncryption::new_with_public_key(rsa.public_key.clone()).unwrap()),
+        RecipientPolicy::new(),
+    ).unwrap();
+
+    let files: [(&str, &dyn SecretCipher); 4] = [
+        ("plain", &PlaintextCipher::new()),
+        ("aes", &AesGcmCipher::new(&key).unwrap()),
+        ("x25519", &x25519_cipher),
+        ("rsa", &rsa_cipher),
+    ];
+    for (name, cipher) in files {
+        fs::write(temp_dir.path().join(name), cipher.encrypt_secret(name, secret_value().as_bytes()).unwrap()).unwrap();
+    }
+
+    let clients = [
+        ("plain", SecretClient::new_plaintext(mount_path)),
+        ("aes", SecretClient::new_with_cipher(mount_path, Box::new(AesGcmCipher::new(&key).unwrap()))),
+        ("x25519", SecretClient::new_with_decryption(mount_path, AsymmetricDecryption::new_with_x25519_key(x25519.private_key))),
+        ("rsa", SecretClient::new_with_decryption(mount_path, AsymmetricDecryption::new_with_private_key(rsa.private_key))),
+    ];
+    for (name, client) in &clients {
+        assert_eq!(leaks_while(|| {
+            let value = client.get_secret(name).unwrap();
+            assert_eq!(value.as_str(), secret_value().as_str());
+        }), 0, "{}", name);
+    }
+}
+
+#[test]
+fn test_fetched_secrets_leave_no_plaintext() {
+    let fetcher = HttpSecretFetcher::new();
+
+    let formats = [
+        (r#"{"api_key": ""#, r#"", "other": {"nested": "x"}}"#),
+        (r#"[{"key": "api_key", "value": ""#, r#"", "env": "prod"}]"#),
+    ];
+    for (prefix, suffix) in formats {
+        assert_eq!(leaks_while(|| {
+            let mut body = Zeroizing::new(String::with_capacity(256));
+            body.push_str(prefix);
+            body.push_str(&secret_value());
+            body.push_str(suffix);
+
+            let secrets = fetcher.parse_json_secrets(&body, "test://url").unwrap();
+            assert_eq!(secrets[0].value.as_str(), secret_value().as_str());
+        }), 0, "{}", prefix);
+    }
+}